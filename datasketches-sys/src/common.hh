@@ -0,0 +1,41 @@
+/**
+ * Copyright 2024 Filippo Rossi
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *   http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#pragma once
+#include "rust/cxx.h"
+
+namespace datasketches
+{
+  template <typename S, typename T>
+  inline void update_slice(S &self, rust::Slice<const T> data)
+  {
+    for (const T datum : data)
+      self.update(datum);
+  }
+
+  // strings are packed back to back in `data`, the i-th one spanning
+  // [offsets[i], offsets[i + 1]); empty strings are skipped like in update(std::string)
+  template <typename S>
+  inline void update_packed_strings(S &self, rust::Slice<const uint8_t> data, rust::Slice<const size_t> offsets)
+  {
+    for (size_t i = 1; i < offsets.length(); i++)
+    {
+      const size_t length = offsets[i] - offsets[i - 1];
+      if (length > 0)
+        self.update((const void *)(data.data() + offsets[i - 1]), length);
+    }
+  }
+}
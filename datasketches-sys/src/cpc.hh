@@ -17,6 +17,7 @@
 #pragma once
 #include "cpc_sketch.hpp"
 #include "cpc_union.hpp"
+#include "common.hh"
 #include "rust/cxx.h"
 
 namespace datasketches
@@ -31,9 +32,34 @@ namespace datasketches
     return std::unique_ptr<cpc_sketch>(new cpc_sketch(self));
   }
 
-  inline std::unique_ptr<cpc_sketch> cpc_sketch_deserialize(rust::Slice<const uint8_t> bytes)
+  inline std::unique_ptr<cpc_sketch> cpc_sketch_deserialize(rust::Slice<const uint8_t> bytes, uint64_t seed = DEFAULT_SEED)
   {
-    return std::unique_ptr<cpc_sketch>(new cpc_sketch(cpc_sketch::deserialize((const void *)(bytes.data()), bytes.length())));
+    return std::unique_ptr<cpc_sketch>(new cpc_sketch(cpc_sketch::deserialize((const void *)(bytes.data()), bytes.length(), seed)));
+  }
+
+  inline void cpc_sketch_update_bytes(cpc_sketch &self, rust::Slice<const uint8_t> bytes)
+  {
+    self.update((const void *)(bytes.data()), bytes.length());
+  }
+
+  inline void cpc_sketch_update_u64_slice(cpc_sketch &self, rust::Slice<const uint64_t> data)
+  {
+    update_slice(self, data);
+  }
+
+  inline void cpc_sketch_update_i64_slice(cpc_sketch &self, rust::Slice<const int64_t> data)
+  {
+    update_slice(self, data);
+  }
+
+  inline void cpc_sketch_update_f64_slice(cpc_sketch &self, rust::Slice<const double> data)
+  {
+    update_slice(self, data);
+  }
+
+  inline void cpc_sketch_update_strings(cpc_sketch &self, rust::Slice<const uint8_t> data, rust::Slice<const size_t> offsets)
+  {
+    update_packed_strings(self, data, offsets);
   }
 
   rust::Vec<uint8_t> cpc_sketch_serialize(const cpc_sketch &self)
@@ -49,6 +75,11 @@ namespace datasketches
     return rust::String(self.to_string());
   }
 
+  inline uint32_t cpc_max_serialized_size_bytes(uint8_t lg_k)
+  {
+    return static_cast<uint32_t>(cpc_sketch::get_max_serialized_size_bytes(lg_k));
+  }
+
   inline std::unique_ptr<cpc_union> cpc_union_new(uint8_t lg_k = cpc_constants::DEFAULT_LG_K, uint64_t seed = DEFAULT_SEED)
   {
     return std::unique_ptr<cpc_union>(new cpc_union(lg_k, seed));
@@ -27,10 +27,17 @@ pub mod ffi {
         fn cpc_sketch_new(lg_k: u8, seed: u64) -> UniquePtr<cpc_sketch>;
         fn cpc_sketch_copy(sketch: &cpc_sketch) -> UniquePtr<cpc_sketch>;
 
-        fn cpc_sketch_deserialize(bytes: &[u8]) -> UniquePtr<cpc_sketch>;
+        fn cpc_sketch_deserialize(bytes: &[u8], seed: u64) -> Result<UniquePtr<cpc_sketch>>;
         fn cpc_sketch_serialize(sketch: &cpc_sketch) -> Vec<u8>;
 
         fn cpc_sketch_to_string(sketch: &cpc_sketch) -> String;
+        fn cpc_max_serialized_size_bytes(lg_k: u8) -> u32;
+
+        fn cpc_sketch_update_bytes(sketch: Pin<&mut cpc_sketch>, bytes: &[u8]);
+        fn cpc_sketch_update_u64_slice(sketch: Pin<&mut cpc_sketch>, data: &[u64]);
+        fn cpc_sketch_update_i64_slice(sketch: Pin<&mut cpc_sketch>, data: &[i64]);
+        fn cpc_sketch_update_f64_slice(sketch: Pin<&mut cpc_sketch>, data: &[f64]);
+        fn cpc_sketch_update_strings(sketch: Pin<&mut cpc_sketch>, data: &[u8], offsets: &[usize]);
 
         #[rust_name=update_string]
         pub fn update(self: Pin<&mut cpc_sketch>, datum: &CxxString);
@@ -59,6 +66,7 @@ pub mod ffi {
         pub fn get_lower_bound(&self, kappa: u32) -> f64;
         pub fn get_upper_bound(&self, kappa: u32) -> f64;
         pub fn get_lg_k(&self) -> u8;
+        pub fn get_num_coupons(&self) -> u32;
         pub fn is_empty(&self) -> bool;
     }
 
@@ -75,6 +83,16 @@ pub mod ffi {
     }
 }
 
+// SAFETY: a `cpc_sketch` or a `cpc_union` owns all of its memory through the
+// standard allocator, and the library keeps neither thread-local state nor any
+// mutable state shared between objects: the compression tables are built once,
+// in a thread-safe way, in a function-local static and only read afterwards.
+// So an object can be used and dropped on another thread than the one which
+// created it. Only `Send` is claimed: the const methods are not documented as
+// safe to call concurrently, so the types stay `!Sync`.
+unsafe impl Send for ffi::cpc_sketch {}
+unsafe impl Send for ffi::cpc_union {}
+
 #[cfg(test)]
 mod tests {
     use cxx::UniquePtr;
@@ -99,10 +117,27 @@ mod tests {
     fn serde() {
         let cpc = dummy_cpc();
 
-        let compact_cpc = ffi::cpc_sketch_deserialize(&ffi::cpc_sketch_serialize(&cpc));
+        let compact_cpc =
+            ffi::cpc_sketch_deserialize(&ffi::cpc_sketch_serialize(&cpc), DEFAULT_SEED).unwrap();
         assert_eq!(
             ffi::cpc_sketch_to_string(&cpc),
             ffi::cpc_sketch_to_string(&compact_cpc),
         );
     }
+
+    #[test]
+    fn update_bytes() {
+        let mut string_cpc = ffi::cpc_sketch_new(DEFAULT_LG_K, DEFAULT_SEED);
+        cxx::let_cxx_string!(datum = "datasketches");
+        string_cpc.pin_mut().update_string(&datum);
+
+        let mut bytes_cpc = ffi::cpc_sketch_new(DEFAULT_LG_K, DEFAULT_SEED);
+        ffi::cpc_sketch_update_bytes(bytes_cpc.pin_mut(), b"datasketches");
+        ffi::cpc_sketch_update_bytes(bytes_cpc.pin_mut(), b"");
+
+        assert_eq!(
+            ffi::cpc_sketch_serialize(&string_cpc),
+            ffi::cpc_sketch_serialize(&bytes_cpc),
+        );
+    }
 }
@@ -16,6 +16,7 @@
 
 #pragma once
 #include "hll.hpp"
+#include "common.hh"
 #include "rust/cxx.h"
 
 namespace datasketches
@@ -40,6 +41,34 @@ namespace datasketches
     return std::unique_ptr<hll_sketch>(new hll_sketch(hll_sketch::deserialize((const void *)(bytes.data()), bytes.length())));
   }
 
+  inline void hll_sketch_update_bytes(hll_sketch &self, rust::Slice<const uint8_t> bytes)
+  {
+    // empty slices are ignored like in the Java library and in cpc_sketch
+    if (bytes.length() == 0)
+      return;
+    self.update((const void *)(bytes.data()), bytes.length());
+  }
+
+  inline void hll_sketch_update_u64_slice(hll_sketch &self, rust::Slice<const uint64_t> data)
+  {
+    update_slice(self, data);
+  }
+
+  inline void hll_sketch_update_i64_slice(hll_sketch &self, rust::Slice<const int64_t> data)
+  {
+    update_slice(self, data);
+  }
+
+  inline void hll_sketch_update_f64_slice(hll_sketch &self, rust::Slice<const double> data)
+  {
+    update_slice(self, data);
+  }
+
+  inline void hll_sketch_update_strings(hll_sketch &self, rust::Slice<const uint8_t> data, rust::Slice<const size_t> offsets)
+  {
+    update_packed_strings(self, data, offsets);
+  }
+
   rust::Vec<uint8_t> hll_sketch_serialize_compact(const hll_sketch &self, unsigned header_size_bytes = 0)
   {
     hll_sketch::vector_bytes bytes = self.serialize_compact(header_size_bytes);
@@ -75,6 +104,13 @@ namespace datasketches
     return std::unique_ptr<hll_union>(new hll_union(self));
   }
 
+  inline void hll_union_update_bytes(hll_union &self, rust::Slice<const uint8_t> bytes)
+  {
+    if (bytes.length() == 0)
+      return;
+    self.update((const void *)(bytes.data()), bytes.length());
+  }
+
   inline std::unique_ptr<hll_sketch> hll_union_get_result(const hll_union &self, target_hll_type tgt_type = HLL_4)
   {
     return std::unique_ptr<hll_sketch>(new hll_sketch(self.get_result(tgt_type)));
@@ -85,4 +121,9 @@ namespace datasketches
   {
     return HllUtil<>::getRelErr(upper_bound, unioned, lg_config_k, num_std_dev);
   }
-}
\ No newline at end of file
+
+  inline uint32_t hll_max_updatable_serialization_bytes(uint8_t lg_config_k, target_hll_type tgt_type)
+  {
+    return static_cast<uint32_t>(hll_sketch::get_max_updatable_serialization_bytes(lg_config_k, tgt_type));
+  }
+}
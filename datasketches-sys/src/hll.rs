@@ -12,6 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+/// HLL sketches always hash items with this seed.
+pub const DEFAULT_SEED: u64 = 9001;
+
 pub const DEFAULT_LG_CONFIG_K: u8 = 12;
 pub const MIN_LG_CONFIG_K: u8 = 7;
 pub const MAX_LG_CONFIG_K: u8 = 21;
@@ -31,6 +34,8 @@ pub mod ffi {
 
         fn hll_rel_error(upper_bound: bool, unioned: bool, lg_config_k: u8, num_std_dev: u8)
             -> f64;
+        fn hll_max_updatable_serialization_bytes(lg_config_k: u8, tgt_type: target_hll_type)
+            -> u32;
     }
 
     unsafe extern "C++" {
@@ -49,7 +54,7 @@ pub mod ffi {
             tgt_type: target_hll_type,
         ) -> UniquePtr<hll_sketch>;
 
-        fn hll_sketch_deserialize(bytes: &[u8]) -> UniquePtr<hll_sketch>;
+        fn hll_sketch_deserialize(bytes: &[u8]) -> Result<UniquePtr<hll_sketch>>;
         fn hll_sketch_serialize_compact(sketch: &hll_sketch, header_size_bytes: u32) -> Vec<u8>;
         fn hll_sketch_serialize_updatable(sketch: &hll_sketch) -> Vec<u8>;
 
@@ -63,6 +68,12 @@ pub mod ffi {
 
         pub fn reset(self: Pin<&mut hll_sketch>);
 
+        fn hll_sketch_update_bytes(sketch: Pin<&mut hll_sketch>, bytes: &[u8]);
+        fn hll_sketch_update_u64_slice(sketch: Pin<&mut hll_sketch>, data: &[u64]);
+        fn hll_sketch_update_i64_slice(sketch: Pin<&mut hll_sketch>, data: &[i64]);
+        fn hll_sketch_update_f64_slice(sketch: Pin<&mut hll_sketch>, data: &[f64]);
+        fn hll_sketch_update_strings(sketch: Pin<&mut hll_sketch>, data: &[u8], offsets: &[usize]);
+
         #[rust_name=update_string]
         pub fn update(self: Pin<&mut hll_sketch>, datum: &CxxString);
         #[rust_name=update_u64]
@@ -111,6 +122,8 @@ pub mod ffi {
 
         pub fn reset(self: Pin<&mut hll_union>);
 
+        fn hll_union_update_bytes(union_: Pin<&mut hll_union>, bytes: &[u8]);
+
         #[rust_name=update_sketch]
         pub fn update(self: Pin<&mut hll_union>, sketch: &hll_sketch);
         #[rust_name=update_string]
@@ -146,6 +159,15 @@ pub mod ffi {
     }
 }
 
+// SAFETY: a `hll_sketch` or a `hll_union` owns all of its memory through the
+// standard allocator, and the library keeps neither thread-local state nor any
+// mutable state shared between objects: the estimator tables are constants.
+// So an object can be used and dropped on another thread than the one which
+// created it. Only `Send` is claimed: the const methods are not documented as
+// safe to call concurrently, so the types stay `!Sync`.
+unsafe impl Send for ffi::hll_sketch {}
+unsafe impl Send for ffi::hll_union {}
+
 #[cfg(test)]
 mod tests {
     use cxx::UniquePtr;
@@ -170,16 +192,34 @@ mod tests {
     fn serde() {
         let hll = dummy_hll();
 
-        let compact_hll = ffi::hll_sketch_deserialize(&ffi::hll_sketch_serialize_compact(&hll, 0));
+        let compact_hll =
+            ffi::hll_sketch_deserialize(&ffi::hll_sketch_serialize_compact(&hll, 0)).unwrap();
         assert_eq!(
             ffi::hll_sketch_to_string(&hll, true, true, true, true),
             ffi::hll_sketch_to_string(&compact_hll, true, true, true, true),
         );
 
-        let updatable_hll = ffi::hll_sketch_deserialize(&ffi::hll_sketch_serialize_updatable(&hll));
+        let updatable_hll =
+            ffi::hll_sketch_deserialize(&ffi::hll_sketch_serialize_updatable(&hll)).unwrap();
         assert_eq!(
             ffi::hll_sketch_to_string(&hll, true, true, true, true),
             ffi::hll_sketch_to_string(&updatable_hll, true, true, true, true),
         )
     }
+
+    #[test]
+    fn update_bytes() {
+        let mut string_hll = ffi::hll_sketch_new(12, ffi::target_hll_type::HLL_4, false);
+        cxx::let_cxx_string!(datum = "datasketches");
+        string_hll.pin_mut().update_string(&datum);
+
+        let mut bytes_hll = ffi::hll_sketch_new(12, ffi::target_hll_type::HLL_4, false);
+        ffi::hll_sketch_update_bytes(bytes_hll.pin_mut(), b"datasketches");
+        ffi::hll_sketch_update_bytes(bytes_hll.pin_mut(), b"");
+
+        assert_eq!(
+            ffi::hll_sketch_serialize_compact(&string_hll, 0),
+            ffi::hll_sketch_serialize_compact(&bytes_hll, 0),
+        );
+    }
 }
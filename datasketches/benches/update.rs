@@ -0,0 +1,103 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use datasketches::{cpc::sketch::CpcSketch, hll::HllSketch};
+
+const ITEMS: u64 = 1 << 20;
+
+fn hll(c: &mut Criterion) {
+    let integers: Vec<u64> = (0..ITEMS).collect();
+    let strings: Vec<String> = integers.iter().map(|i| i.to_string()).collect();
+
+    let mut group = c.benchmark_group("hll");
+    group.throughput(Throughput::Elements(ITEMS));
+    group.bench_function("update_u64", |b| {
+        b.iter(|| {
+            let mut hll = HllSketch::default();
+            for datum in &integers {
+                hll.update_u64(*datum);
+            }
+            black_box(hll)
+        })
+    });
+    group.bench_function("update_u64_slice", |b| {
+        b.iter(|| {
+            let mut hll = HllSketch::default();
+            hll.update_u64_slice(&integers);
+            black_box(hll)
+        })
+    });
+    group.bench_function("update_string", |b| {
+        b.iter(|| {
+            let mut hll = HllSketch::default();
+            for datum in &strings {
+                hll.update_string(datum);
+            }
+            black_box(hll)
+        })
+    });
+    group.bench_function("update_strings", |b| {
+        b.iter(|| {
+            let mut hll = HllSketch::default();
+            hll.update_strings(strings.iter().map(String::as_str));
+            black_box(hll)
+        })
+    });
+    group.finish();
+}
+
+fn cpc(c: &mut Criterion) {
+    let integers: Vec<u64> = (0..ITEMS).collect();
+    let strings: Vec<String> = integers.iter().map(|i| i.to_string()).collect();
+
+    let mut group = c.benchmark_group("cpc");
+    group.throughput(Throughput::Elements(ITEMS));
+    group.bench_function("update_u64", |b| {
+        b.iter(|| {
+            let mut cpc = CpcSketch::default();
+            for datum in &integers {
+                cpc.update_u64(*datum);
+            }
+            black_box(cpc)
+        })
+    });
+    group.bench_function("update_u64_slice", |b| {
+        b.iter(|| {
+            let mut cpc = CpcSketch::default();
+            cpc.update_u64_slice(&integers);
+            black_box(cpc)
+        })
+    });
+    group.bench_function("update_string", |b| {
+        b.iter(|| {
+            let mut cpc = CpcSketch::default();
+            for datum in &strings {
+                cpc.update_string(datum);
+            }
+            black_box(cpc)
+        })
+    });
+    group.bench_function("update_strings", |b| {
+        b.iter(|| {
+            let mut cpc = CpcSketch::default();
+            cpc.update_strings(strings.iter().map(String::as_str));
+            black_box(cpc)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, hll, cpc);
+criterion_main!(benches);
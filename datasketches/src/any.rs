@@ -0,0 +1,444 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [AnySketch] and [AnyUnion].
+
+use crate::config::{Family, SketchConfig};
+use crate::cpc::{sketch::CpcSketch, union::CpcUnion};
+use crate::error::Error;
+use crate::hash::DEFAULT_SEED;
+use crate::header::{inspect, SketchHeader};
+use crate::hll::{HllSketch, HllType, HllUnion, MAX_LG_CONFIG_K, MIN_LG_CONFIG_K};
+use crate::item::ItemSink;
+use crate::traits::CardinalitySketch;
+
+/// A distinct counting sketch of any family supported by this crate, for
+/// applications handling images without knowing which family produced them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AnySketch {
+    /// An [HllSketch].
+    Hll(HllSketch),
+    /// A [CpcSketch].
+    Cpc(CpcSketch),
+}
+
+impl From<HllSketch> for AnySketch {
+    fn from(sketch: HllSketch) -> Self {
+        Self::Hll(sketch)
+    }
+}
+
+impl From<CpcSketch> for AnySketch {
+    fn from(sketch: CpcSketch) -> Self {
+        Self::Cpc(sketch)
+    }
+}
+
+impl AnySketch {
+    /// Reconstructs a sketch from a serialized image of any supported family,
+    /// detected from its preamble with [inspect]. CPC images must have been
+    /// produced by a sketch using [DEFAULT_SEED].
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        match inspect(bytes)? {
+            SketchHeader::Hll(_) => {
+                <HllSketch as CardinalitySketch>::deserialize(bytes).map(Self::Hll)
+            }
+            SketchHeader::Cpc(_) => {
+                CpcSketch::deserialize_with_seed(bytes, DEFAULT_SEED).map(Self::Cpc)
+            }
+        }
+    }
+
+    /// Serializes the sketch to a byte array. HLL sketches are serialized in
+    /// their compact form.
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Self::Hll(sketch) => sketch.serialize_compact(0),
+            Self::Cpc(sketch) => sketch.serialize(),
+        }
+    }
+
+    /// Returns the family of the sketch.
+    pub fn family(&self) -> Family {
+        match self {
+            Self::Hll(_) => Family::Hll,
+            Self::Cpc(_) => Family::Cpc,
+        }
+    }
+
+    /// Returns the configuration of the sketch.
+    pub fn config(&self) -> SketchConfig {
+        match self {
+            Self::Hll(sketch) => sketch.config(),
+            Self::Cpc(sketch) => sketch.config(),
+        }
+    }
+
+    /// Returns the current cardinality estimate.
+    pub fn get_estimate(&self) -> f64 {
+        match self {
+            Self::Hll(sketch) => sketch.get_estimate(),
+            Self::Cpc(sketch) => sketch.get_estimate(),
+        }
+    }
+
+    /// Returns the approximate lower error bound given the number of standard
+    /// deviations (1, 2 or 3), which is the `kappa` parameter of CPC sketches.
+    pub fn get_lower_bound(&self, num_std_dev: u8) -> f64 {
+        match self {
+            Self::Hll(sketch) => sketch.get_lower_bound(num_std_dev),
+            Self::Cpc(sketch) => sketch.get_lower_bound(num_std_dev.into()),
+        }
+    }
+
+    /// Returns the approximate upper error bound given the number of standard
+    /// deviations (1, 2 or 3), which is the `kappa` parameter of CPC sketches.
+    pub fn get_upper_bound(&self, num_std_dev: u8) -> f64 {
+        match self {
+            Self::Hll(sketch) => sketch.get_upper_bound(num_std_dev),
+            Self::Cpc(sketch) => sketch.get_upper_bound(num_std_dev.into()),
+        }
+    }
+
+    /// Indicates if the sketch is currently empty.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Hll(sketch) => sketch.is_empty(),
+            Self::Cpc(sketch) => sketch.is_empty(),
+        }
+    }
+}
+
+impl ItemSink for AnySketch {
+    #[inline]
+    fn update_u64(&mut self, datum: u64) {
+        match self {
+            Self::Hll(sketch) => sketch.update_u64(datum),
+            Self::Cpc(sketch) => sketch.update_u64(datum),
+        }
+    }
+
+    #[inline]
+    fn update_i64(&mut self, datum: i64) {
+        match self {
+            Self::Hll(sketch) => sketch.update_i64(datum),
+            Self::Cpc(sketch) => sketch.update_i64(datum),
+        }
+    }
+
+    #[inline]
+    fn update_f64(&mut self, datum: f64) {
+        match self {
+            Self::Hll(sketch) => sketch.update_f64(datum),
+            Self::Cpc(sketch) => sketch.update_f64(datum),
+        }
+    }
+
+    #[inline]
+    fn update_bytes(&mut self, datum: &[u8]) {
+        match self {
+            Self::Hll(sketch) => sketch.update_bytes(datum),
+            Self::Cpc(sketch) => sketch.update_bytes(datum),
+        }
+    }
+
+    #[inline]
+    fn update_u64_slice(&mut self, data: &[u64]) {
+        match self {
+            Self::Hll(sketch) => ItemSink::update_u64_slice(sketch, data),
+            Self::Cpc(sketch) => ItemSink::update_u64_slice(sketch, data),
+        }
+    }
+
+    #[inline]
+    fn update_i64_slice(&mut self, data: &[i64]) {
+        match self {
+            Self::Hll(sketch) => ItemSink::update_i64_slice(sketch, data),
+            Self::Cpc(sketch) => ItemSink::update_i64_slice(sketch, data),
+        }
+    }
+
+    #[inline]
+    fn update_f64_slice(&mut self, data: &[f64]) {
+        match self {
+            Self::Hll(sketch) => ItemSink::update_f64_slice(sketch, data),
+            Self::Cpc(sketch) => ItemSink::update_f64_slice(sketch, data),
+        }
+    }
+
+    #[inline]
+    fn update_packed_bytes(&mut self, data: &[u8], offsets: &[usize]) {
+        match self {
+            Self::Hll(sketch) => ItemSink::update_packed_bytes(sketch, data, offsets),
+            Self::Cpc(sketch) => ItemSink::update_packed_bytes(sketch, data, offsets),
+        }
+    }
+}
+
+impl CardinalitySketch for AnySketch {
+    #[inline]
+    fn config(&self) -> SketchConfig {
+        AnySketch::config(self)
+    }
+
+    #[inline]
+    fn get_estimate(&self) -> f64 {
+        AnySketch::get_estimate(self)
+    }
+
+    #[inline]
+    fn get_lower_bound(&self, num_std_dev: u8) -> f64 {
+        AnySketch::get_lower_bound(self, num_std_dev)
+    }
+
+    #[inline]
+    fn get_upper_bound(&self, num_std_dev: u8) -> f64 {
+        AnySketch::get_upper_bound(self, num_std_dev)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        AnySketch::is_empty(self)
+    }
+
+    #[inline]
+    fn serialize(&self) -> Vec<u8> {
+        AnySketch::serialize(self)
+    }
+
+    #[inline]
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        AnySketch::deserialize(bytes)
+    }
+}
+
+#[derive(Clone)]
+enum Gadget {
+    Empty,
+    Hll(HllUnion),
+    Cpc(CpcUnion),
+}
+
+/// A union of sketches of any supported family.
+///
+/// The union adopts the family of the first sketch it is given. CPC sketches
+/// can be converted into HLL sketches, see [HllSketch::from_cpc], but not the
+/// other way around: the union switches to HLL as soon as it is given an HLL
+/// sketch, and keeps converting the CPC sketches it is given from then on.
+/// A union of CPC sketches thus stays as accurate as a [CpcUnion], while a
+/// union mixing both families is as accurate as an [HllUnion].
+#[derive(Clone)]
+pub struct AnyUnion {
+    lg_max_k: u8,
+    gadget: Gadget,
+}
+
+impl Default for AnyUnion {
+    fn default() -> Self {
+        Self::new(MAX_LG_CONFIG_K)
+    }
+}
+
+impl AnyUnion {
+    /// Creates an empty union given the maximum log2 of the number of buckets
+    /// of its result. Like the unions of each family, the result is
+    /// downsampled to the smallest `lg_k` of the sketches it is given.
+    ///
+    /// `lg_max_k` is clamped between 7 and 21, the bounds of HLL sketches, so
+    /// that CPC results can always be converted to HLL.
+    pub fn new(lg_max_k: u8) -> Self {
+        Self {
+            lg_max_k: lg_max_k.clamp(MIN_LG_CONFIG_K, MAX_LG_CONFIG_K),
+            gadget: Gadget::Empty,
+        }
+    }
+
+    /// Update this union operator with the given sketch.
+    ///
+    /// Fails, leaving the union untouched, if the sketch cannot be merged:
+    /// a CPC sketch must use the seed of the CPC sketches the union was given
+    /// before, and CPC sketches can only be converted to HLL sketches if they
+    /// use [DEFAULT_SEED] and an `lg_k` of at most 21.
+    pub fn update_sketch(&mut self, sketch: &AnySketch) -> Result<(), Error> {
+        match (&mut self.gadget, sketch) {
+            (Gadget::Empty, AnySketch::Hll(sketch)) => {
+                let mut union = self.hll_union();
+                union.update_sketch(sketch);
+                self.gadget = Gadget::Hll(union);
+            }
+            (Gadget::Empty, AnySketch::Cpc(sketch)) => {
+                let mut union = CpcUnion::new(self.lg_max_k, sketch.get_seed());
+                union.try_update_sketch(sketch)?;
+                self.gadget = Gadget::Cpc(union);
+            }
+            (Gadget::Hll(union), AnySketch::Hll(sketch)) => union.update_sketch(sketch),
+            (Gadget::Hll(union), AnySketch::Cpc(sketch)) => {
+                union.update_sketch(&HllSketch::from_cpc(sketch, HllType::HLL8)?)
+            }
+            (Gadget::Cpc(union), AnySketch::Cpc(sketch)) => union.try_update_sketch(sketch)?,
+            (Gadget::Cpc(cpc_union), AnySketch::Hll(sketch)) => {
+                let converted = HllSketch::from_cpc(&cpc_union.get_result(), HllType::HLL8)?;
+                let mut union = self.hll_union();
+                union.update_sketch(&converted);
+                union.update_sketch(sketch);
+                self.gadget = Gadget::Hll(union);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the family of the result, or [None] if the union was not
+    /// given any sketch yet.
+    pub fn family(&self) -> Option<Family> {
+        match self.gadget {
+            Gadget::Empty => None,
+            Gadget::Hll(_) => Some(Family::Hll),
+            Gadget::Cpc(_) => Some(Family::Cpc),
+        }
+    }
+
+    /// Returns the result of this union operator, or [None] if the union was
+    /// not given any sketch yet. HLL results have the specified [HllType].
+    pub fn get_result(&self, tgt_type: HllType) -> Option<AnySketch> {
+        match &self.gadget {
+            Gadget::Empty => None,
+            Gadget::Hll(union) => Some(AnySketch::Hll(union.get_result(tgt_type))),
+            Gadget::Cpc(union) => Some(AnySketch::Cpc(union.get_result())),
+        }
+    }
+
+    /// Returns the current cardinality estimate, 0 if the union was not
+    /// given any sketch yet.
+    pub fn get_estimate(&self) -> f64 {
+        match &self.gadget {
+            Gadget::Empty => 0.0,
+            Gadget::Hll(union) => union.get_estimate(),
+            Gadget::Cpc(union) => union.get_estimate(),
+        }
+    }
+
+    /// Resets the union to an empty state, keeping its configuration.
+    pub fn reset(&mut self) {
+        self.gadget = Gadget::Empty;
+    }
+
+    fn hll_union(&self) -> HllUnion {
+        HllUnion::new(self.lg_max_k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hll(range: std::ops::Range<u64>) -> AnySketch {
+        let mut sketch = HllSketch::new(10, HllType::HLL8, false);
+        sketch.update_u64_slice(&range.collect::<Vec<_>>());
+        sketch.into()
+    }
+
+    fn cpc(range: std::ops::Range<u64>, seed: u64) -> AnySketch {
+        let mut sketch = CpcSketch::new(10, seed);
+        sketch.update_u64_slice(&range.collect::<Vec<_>>());
+        sketch.into()
+    }
+
+    #[test]
+    fn deserialize() {
+        for sketch in [hll(0..1000), cpc(0..1000, DEFAULT_SEED)] {
+            let image = sketch.serialize();
+            let deserialized = AnySketch::deserialize(&image).unwrap();
+            assert_eq!(deserialized.family(), sketch.family());
+            assert_eq!(deserialized.get_estimate(), sketch.get_estimate());
+            assert!(deserialized.get_lower_bound(2) <= deserialized.get_estimate());
+            assert!(deserialized.get_upper_bound(2) >= deserialized.get_estimate());
+            assert!(!deserialized.is_empty());
+        }
+
+        assert!(matches!(
+            AnySketch::deserialize(&cpc(0..10, 42).serialize()),
+            Err(Error::IncompatibleSeed { .. })
+        ));
+        assert!(AnySketch::deserialize(&[0; 4]).is_err());
+        let image = hll(0..1000).serialize();
+        assert!(AnySketch::deserialize(&image[..image.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn union() {
+        let mut union = AnyUnion::new(12);
+        assert_eq!(union.get_result(HllType::HLL4), None);
+        union.update_sketch(&cpc(0..1000, DEFAULT_SEED)).unwrap();
+        union.update_sketch(&cpc(500..1500, DEFAULT_SEED)).unwrap();
+        assert_eq!(union.family(), Some(Family::Cpc));
+        let Some(AnySketch::Cpc(result)) = union.get_result(HllType::HLL4) else {
+            panic!("not a CPC result");
+        };
+        assert_eq!(result.get_lg_k(), 10);
+        assert!((union.get_estimate() - 1500.0).abs() < 1500.0 * 0.1);
+
+        union.update_sketch(&hll(1000..2000)).unwrap();
+        union.update_sketch(&cpc(2000..3000, DEFAULT_SEED)).unwrap();
+        assert_eq!(union.family(), Some(Family::Hll));
+        let mut expected = HllUnion::new(12);
+        for range in [0..1500, 1000..2000, 2000..3000] {
+            let AnySketch::Hll(sketch) = hll(range) else {
+                unreachable!()
+            };
+            expected.update_sketch(&sketch);
+        }
+        let result = union.get_result(HllType::HLL8).unwrap();
+        assert_eq!(result.family(), Family::Hll);
+        let AnySketch::Hll(result) = result else {
+            unreachable!()
+        };
+        assert_eq!(
+            result.registers(),
+            expected.get_result(HllType::HLL8).registers()
+        );
+
+        assert!(matches!(
+            union.update_sketch(&cpc(0..10, 42)),
+            Err(Error::IncompatibleSeed { .. })
+        ));
+        union.reset();
+        assert_eq!(union.get_estimate(), 0.0);
+        union.update_sketch(&cpc(0..10, 42)).unwrap();
+        assert!(matches!(
+            union.update_sketch(&cpc(0..10, DEFAULT_SEED)),
+            Err(Error::IncompatibleSeed { .. })
+        ));
+        assert!(union.update_sketch(&hll(0..10)).is_err());
+        assert_eq!(union.family(), Some(Family::Cpc));
+    }
+
+    #[test]
+    fn lg_max_k() {
+        for lg_max_k in [0, 4, 26, u8::MAX] {
+            let mut union = AnyUnion::new(lg_max_k);
+            union.update_sketch(&cpc(0..1000, DEFAULT_SEED)).unwrap();
+            union.update_sketch(&hll(0..1000)).unwrap();
+            // as few as 2^7 buckets
+            assert!((union.get_estimate() - 1000.0).abs() < 1000.0 * 0.3);
+        }
+
+        let mut union = AnyUnion::default();
+        union.update_sketch(&hll(0..1000)).unwrap();
+        let cpc = CpcSketch::new(MAX_LG_CONFIG_K + 1, DEFAULT_SEED);
+        assert!(matches!(
+            union.update_sketch(&cpc.into()),
+            Err(Error::UnsupportedLgK { .. })
+        ));
+    }
+}
@@ -0,0 +1,641 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Kernels updating sketches with [Apache Arrow](https://arrow.apache.org)
+//! arrays, and conversions of sketch columns to and from [BinaryArray]s of
+//! serialized images.
+//!
+//! Values are hashed like the matching Rust types would be by
+//! [SketchItem](crate::SketchItem), so a sketch updated with an [Int32Array]
+//! is identical to one updated with the same `i32` values one at a time.
+//! Null values are skipped.
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::*;
+use arrow_array::{Array, BinaryArray, OffsetSizeTrait};
+use arrow_schema::{ArrowError, DataType, TimeUnit};
+use std::ops::Range;
+
+use crate::cpc::sketch::CpcSketch;
+use crate::error::Error;
+use crate::hll::HllSketch;
+use crate::item::{ItemSink, SketchItem};
+use crate::traits::CardinalitySketch;
+
+/// Returns the sink receiving the value of a given row of an array.
+trait RowSinks {
+    fn row(&mut self, row: usize) -> &mut dyn ItemSink;
+
+    /// Returns the end of the run of rows from `start` to at most `end` whose
+    /// values go to the same sink.
+    fn run_end(&self, start: usize, end: usize) -> usize;
+}
+
+struct Single<'a, S>(&'a mut S);
+
+impl<S: ItemSink> RowSinks for Single<'_, S> {
+    #[inline]
+    fn row(&mut self, _row: usize) -> &mut dyn ItemSink {
+        self.0
+    }
+
+    #[inline]
+    fn run_end(&self, _start: usize, end: usize) -> usize {
+        end
+    }
+}
+
+struct Grouped<'a, S> {
+    sinks: &'a mut [S],
+    group_indices: &'a [usize],
+}
+
+impl<'a, S> Grouped<'a, S> {
+    /// Checks that there is one group index per row of an array of `len`
+    /// values, and that every group index is within the bounds of `sinks`.
+    fn new(sinks: &'a mut [S], group_indices: &'a [usize], len: usize) -> Result<Self, ArrowError> {
+        if group_indices.len() != len {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "expected {len} group indices, got {}",
+                group_indices.len()
+            )));
+        }
+        if let Some(&max) = group_indices.iter().max() {
+            if max >= sinks.len() {
+                return Err(ArrowError::InvalidArgumentError(format!(
+                    "group index {max} out of bounds for {} groups",
+                    sinks.len()
+                )));
+            }
+        }
+        Ok(Self {
+            sinks,
+            group_indices,
+        })
+    }
+}
+
+impl<S: ItemSink> RowSinks for Grouped<'_, S> {
+    #[inline]
+    fn row(&mut self, row: usize) -> &mut dyn ItemSink {
+        &mut self.sinks[self.group_indices[row]]
+    }
+
+    fn run_end(&self, start: usize, end: usize) -> usize {
+        let group = self.group_indices[start];
+        self.group_indices[start..end]
+            .iter()
+            .position(|&index| index != group)
+            .map_or(end, |len| start + len)
+    }
+}
+
+/// Updates `sinks` with the values of `array` at the given `(row, index)`
+/// pairs, skipping nulls.
+fn update_rows(
+    sinks: &mut dyn RowSinks,
+    array: &dyn Array,
+    rows: &mut dyn Iterator<Item = (usize, usize)>,
+) -> Result<(), ArrowError> {
+    macro_rules! update {
+        ($array:expr, |$value:ident| $item:expr) => {{
+            let array = $array;
+            for (row, index) in rows {
+                if array.is_valid(index) {
+                    let $value = array.value(index);
+                    $item.update_into(sinks.row(row));
+                }
+            }
+        }};
+        ($array:expr) => {
+            update!($array, |value| value)
+        };
+    }
+
+    match array.data_type() {
+        DataType::Null => {}
+        DataType::Boolean => update!(array.as_boolean()),
+        DataType::Int8 => update!(array.as_primitive::<Int8Type>()),
+        DataType::Int16 => update!(array.as_primitive::<Int16Type>()),
+        DataType::Int32 => update!(array.as_primitive::<Int32Type>()),
+        DataType::Int64 => update!(array.as_primitive::<Int64Type>()),
+        DataType::UInt8 => update!(array.as_primitive::<UInt8Type>()),
+        DataType::UInt16 => update!(array.as_primitive::<UInt16Type>()),
+        DataType::UInt32 => update!(array.as_primitive::<UInt32Type>()),
+        DataType::UInt64 => update!(array.as_primitive::<UInt64Type>()),
+        DataType::Float16 => update!(array.as_primitive::<Float16Type>(), |value| value.to_f64()),
+        DataType::Float32 => update!(array.as_primitive::<Float32Type>()),
+        DataType::Float64 => update!(array.as_primitive::<Float64Type>()),
+        DataType::Decimal128(_, _) => update!(array.as_primitive::<Decimal128Type>()),
+        DataType::Date32 => update!(array.as_primitive::<Date32Type>()),
+        DataType::Date64 => update!(array.as_primitive::<Date64Type>()),
+        DataType::Time32(TimeUnit::Second) => update!(array.as_primitive::<Time32SecondType>()),
+        DataType::Time32(_) => update!(array.as_primitive::<Time32MillisecondType>()),
+        DataType::Time64(TimeUnit::Microsecond) => {
+            update!(array.as_primitive::<Time64MicrosecondType>())
+        }
+        DataType::Time64(_) => update!(array.as_primitive::<Time64NanosecondType>()),
+        DataType::Timestamp(TimeUnit::Second, _) => {
+            update!(array.as_primitive::<TimestampSecondType>())
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            update!(array.as_primitive::<TimestampMillisecondType>())
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            update!(array.as_primitive::<TimestampMicrosecondType>())
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            update!(array.as_primitive::<TimestampNanosecondType>())
+        }
+        DataType::Duration(TimeUnit::Second) => {
+            update!(array.as_primitive::<DurationSecondType>())
+        }
+        DataType::Duration(TimeUnit::Millisecond) => {
+            update!(array.as_primitive::<DurationMillisecondType>())
+        }
+        DataType::Duration(TimeUnit::Microsecond) => {
+            update!(array.as_primitive::<DurationMicrosecondType>())
+        }
+        DataType::Duration(TimeUnit::Nanosecond) => {
+            update!(array.as_primitive::<DurationNanosecondType>())
+        }
+        DataType::Utf8 => update!(array.as_string::<i32>()),
+        DataType::LargeUtf8 => update!(array.as_string::<i64>()),
+        DataType::Utf8View => update!(array.as_string_view()),
+        DataType::Binary => update!(array.as_binary::<i32>()),
+        DataType::LargeBinary => update!(array.as_binary::<i64>()),
+        DataType::BinaryView => update!(array.as_binary_view()),
+        DataType::FixedSizeBinary(_) => update!(array.as_fixed_size_binary()),
+        DataType::Dictionary(_, _) => {
+            let dictionary = array.as_any_dictionary();
+            if dictionary.values().is_empty() {
+                return Ok(());
+            }
+            let keys = dictionary.keys();
+            let normalized_keys = dictionary.normalized_keys();
+            let mut rows = rows
+                .filter(|(_, index)| keys.is_valid(*index))
+                .map(|(row, index)| (row, normalized_keys[index]));
+            update_rows(sinks, dictionary.values().as_ref(), &mut rows)?;
+        }
+        data_type => {
+            return Err(ArrowError::NotYetImplemented(format!(
+                "updating sketches with {data_type} arrays"
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Calls `update` with the sink and the rows of every run of consecutive
+/// non-null values of `array` that go to the same sink.
+fn for_each_run(
+    sinks: &mut dyn RowSinks,
+    array: &dyn Array,
+    update: &mut dyn FnMut(&mut dyn ItemSink, Range<usize>),
+) {
+    let mut update_valid = |mut start: usize, end: usize| {
+        while start < end {
+            let run_end = sinks.run_end(start, end);
+            update(sinks.row(start), start..run_end);
+            start = run_end;
+        }
+    };
+    match array.nulls() {
+        Some(nulls) => nulls
+            .valid_slices()
+            .for_each(|(start, end)| update_valid(start, end)),
+        None => update_valid(0, array.len()),
+    }
+}
+
+/// Converts the offsets of a run of byte strings of an array to the ones
+/// taken by [ItemSink::update_packed_bytes].
+fn packed_offsets<O: OffsetSizeTrait>(offsets: &[O], buf: &mut Vec<usize>) {
+    buf.clear();
+    buf.extend(offsets.iter().map(|offset| offset.as_usize()));
+}
+
+/// Updates `sinks` with the non-null values of `array` one run at a time, see
+/// [for_each_run], handing the values of a run over in a single call. Returns
+/// `false` without updating anything if the values of `array` cannot be
+/// handed over this way, in which case [update_rows] must be used.
+fn update_runs(sinks: &mut dyn RowSinks, array: &dyn Array) -> bool {
+    macro_rules! update {
+        ($array:expr, packed) => {{
+            let strings = $array;
+            let (data, offsets) = (strings.value_data(), strings.value_offsets());
+            let mut buf = Vec::new();
+            for_each_run(sinks, array, &mut |sink, run| {
+                packed_offsets(&offsets[run.start..=run.end], &mut buf);
+                sink.update_packed_bytes(data, &buf);
+            });
+        }};
+        ($array:expr, $update:ident) => {{
+            let values = $array.values();
+            for_each_run(sinks, array, &mut |sink, run| sink.$update(&values[run]));
+        }};
+        ($array:expr, $update:ident, |$value:ident| $item:expr) => {{
+            let values = $array.values();
+            let mut buf = Vec::new();
+            for_each_run(sinks, array, &mut |sink, run| {
+                buf.clear();
+                buf.extend(values[run].iter().map(|&$value| $item));
+                sink.$update(&buf);
+            });
+        }};
+    }
+
+    // Narrower values are widened like by their SketchItem implementations.
+    match array.data_type() {
+        DataType::Int8 => update!(
+            array.as_primitive::<Int8Type>(),
+            update_i64_slice,
+            |value| value as i64
+        ),
+        DataType::Int16 => update!(
+            array.as_primitive::<Int16Type>(),
+            update_i64_slice,
+            |value| value as i64
+        ),
+        DataType::Int32 => update!(
+            array.as_primitive::<Int32Type>(),
+            update_i64_slice,
+            |value| value as i64
+        ),
+        DataType::Int64 => update!(array.as_primitive::<Int64Type>(), update_i64_slice),
+        DataType::UInt8 => update!(
+            array.as_primitive::<UInt8Type>(),
+            update_i64_slice,
+            |value| value as i8 as i64
+        ),
+        DataType::UInt16 => update!(
+            array.as_primitive::<UInt16Type>(),
+            update_i64_slice,
+            |value| value as i16 as i64
+        ),
+        DataType::UInt32 => update!(
+            array.as_primitive::<UInt32Type>(),
+            update_i64_slice,
+            |value| value as i32 as i64
+        ),
+        DataType::UInt64 => update!(array.as_primitive::<UInt64Type>(), update_u64_slice),
+        DataType::Float16 => update!(
+            array.as_primitive::<Float16Type>(),
+            update_f64_slice,
+            |value| value.to_f64()
+        ),
+        DataType::Float32 => update!(
+            array.as_primitive::<Float32Type>(),
+            update_f64_slice,
+            |value| value as f64
+        ),
+        DataType::Float64 => update!(array.as_primitive::<Float64Type>(), update_f64_slice),
+        DataType::Date32 => update!(
+            array.as_primitive::<Date32Type>(),
+            update_i64_slice,
+            |value| value as i64
+        ),
+        DataType::Date64 => update!(array.as_primitive::<Date64Type>(), update_i64_slice),
+        DataType::Time32(TimeUnit::Second) => {
+            update!(
+                array.as_primitive::<Time32SecondType>(),
+                update_i64_slice,
+                |value| value as i64
+            )
+        }
+        DataType::Time32(_) => {
+            update!(
+                array.as_primitive::<Time32MillisecondType>(),
+                update_i64_slice,
+                |value| value as i64
+            )
+        }
+        DataType::Time64(TimeUnit::Microsecond) => {
+            update!(
+                array.as_primitive::<Time64MicrosecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Time64(_) => {
+            update!(
+                array.as_primitive::<Time64NanosecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Timestamp(TimeUnit::Second, _) => {
+            update!(
+                array.as_primitive::<TimestampSecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            update!(
+                array.as_primitive::<TimestampMillisecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            update!(
+                array.as_primitive::<TimestampMicrosecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            update!(
+                array.as_primitive::<TimestampNanosecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Duration(TimeUnit::Second) => {
+            update!(array.as_primitive::<DurationSecondType>(), update_i64_slice)
+        }
+        DataType::Duration(TimeUnit::Millisecond) => {
+            update!(
+                array.as_primitive::<DurationMillisecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Duration(TimeUnit::Microsecond) => {
+            update!(
+                array.as_primitive::<DurationMicrosecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Duration(TimeUnit::Nanosecond) => {
+            update!(
+                array.as_primitive::<DurationNanosecondType>(),
+                update_i64_slice
+            )
+        }
+        DataType::Utf8 => update!(array.as_string::<i32>(), packed),
+        DataType::LargeUtf8 => update!(array.as_string::<i64>(), packed),
+        DataType::Binary => update!(array.as_binary::<i32>(), packed),
+        DataType::LargeBinary => update!(array.as_binary::<i64>(), packed),
+        _ => return false,
+    }
+    true
+}
+
+/// Presents every non-null value of `array` to `sink`. The values of
+/// primitive, string and binary arrays are handed over one run of non-null
+/// values at a time through the slice updates of [ItemSink], so a sketch
+/// backed by the C++ library is called once per run rather than per value.
+pub fn update_array<S: ItemSink>(sink: &mut S, array: &dyn Array) -> Result<(), ArrowError> {
+    let mut sink = Single(sink);
+    if update_runs(&mut sink, array) {
+        return Ok(());
+    }
+    let mut rows = (0..array.len()).map(|i| (i, i));
+    update_rows(&mut sink, array, &mut rows)
+}
+
+/// Presents every non-null value of `array` to the sink of its group, given
+/// by the matching entry of `group_indices`. This is the update step of a
+/// grouped aggregation, `sinks` holding one sketch per group.
+///
+/// Fails if `group_indices` does not have one entry per value or if a group
+/// index is out of the bounds of `sinks`.
+pub fn update_groups<S: ItemSink>(
+    sinks: &mut [S],
+    group_indices: &[usize],
+    array: &dyn Array,
+) -> Result<(), ArrowError> {
+    let mut sinks = Grouped::new(sinks, group_indices, array.len())?;
+    if update_runs(&mut sinks, array) {
+        return Ok(());
+    }
+    let mut rows = (0..array.len()).map(|i| (i, i));
+    update_rows(&mut sinks, array, &mut rows)
+}
+
+/// A sketch that can be stored in a [BinaryArray] of serialized images.
+pub trait BinarySketch: Sized {
+    /// Returns the serialized image stored in the array.
+    fn to_image(&self) -> Vec<u8>;
+
+    /// Reconstructs a sketch from an image stored in the array, failing if
+    /// the image is invalid.
+    fn from_image(bytes: &[u8]) -> Result<Self, Error>;
+}
+
+impl BinarySketch for HllSketch {
+    /// HLL sketches are stored in their compact form.
+    fn to_image(&self) -> Vec<u8> {
+        self.serialize_compact(0)
+    }
+
+    fn from_image(bytes: &[u8]) -> Result<Self, Error> {
+        <Self as CardinalitySketch>::deserialize(bytes)
+    }
+}
+
+impl BinarySketch for CpcSketch {
+    fn to_image(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn from_image(bytes: &[u8]) -> Result<Self, Error> {
+        <Self as CardinalitySketch>::deserialize(bytes)
+    }
+}
+
+/// Builds a [BinaryArray] of the serialized images of `sketches`, missing
+/// sketches becoming nulls.
+pub fn to_binary_array<'a, S: BinarySketch + 'a>(
+    sketches: impl IntoIterator<Item = Option<&'a S>>,
+) -> BinaryArray {
+    sketches
+        .into_iter()
+        .map(|sketch| sketch.map(BinarySketch::to_image))
+        .collect()
+}
+
+/// Reconstructs the sketches stored in a [BinaryArray], nulls becoming
+/// missing sketches. Fails on the first invalid image.
+pub fn from_binary_array<S: BinarySketch>(array: &BinaryArray) -> Result<Vec<Option<S>>, Error> {
+    array
+        .iter()
+        .map(|image| image.map(S::from_image).transpose())
+        .collect()
+}
+
+impl HllSketch {
+    /// Presents every non-null value of `array` as a potential unique item,
+    /// see [update_array].
+    pub fn update_array(&mut self, array: &dyn Array) -> Result<(), ArrowError> {
+        update_array(self, array)
+    }
+}
+
+impl CpcSketch {
+    /// Presents every non-null value of `array` as a potential unique item,
+    /// see [update_array].
+    pub fn update_array(&mut self, array: &dyn Array) -> Result<(), ArrowError> {
+        update_array(self, array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::hll::HllType;
+    use arrow_array::{
+        DictionaryArray, Float32Array, Int32Array, Int8Array, StringArray, StructArray,
+        UInt32Array, UInt8Array,
+    };
+    use arrow_schema::Field;
+
+    #[test]
+    fn primitive() {
+        let array = Int32Array::from(vec![Some(-1), None, Some(7), Some(-1)]);
+        let mut hll = HllSketch::default();
+        hll.update_array(&array).unwrap();
+
+        let mut expected = HllSketch::default();
+        expected.update_i32(-1);
+        expected.update_i32(7);
+        assert!(hll == expected);
+
+        let mut hll = HllSketch::default();
+        hll.update_array(&UInt32Array::from(vec![u32::MAX]))
+            .unwrap();
+        let mut expected = HllSketch::default();
+        expected.update_u32(u32::MAX);
+        assert!(hll == expected);
+
+        let mut cpc = CpcSketch::default();
+        cpc.update_array(&Float32Array::from(vec![Some(-0.0), Some(1.5), None]))
+            .unwrap();
+        let mut expected = CpcSketch::default();
+        expected.update_f32(0.0);
+        expected.update_f32(1.5);
+        assert!(cpc == expected);
+    }
+
+    #[test]
+    fn strings() {
+        let array = StringArray::from(vec![Some("a"), None, Some(""), Some("b")]);
+        let mut hll = HllSketch::default();
+        hll.update_array(&array).unwrap();
+
+        let mut expected = HllSketch::default();
+        expected.update_string("a");
+        expected.update_string("b");
+        assert!(hll == expected);
+
+        let sliced = array.slice(3, 1);
+        let mut hll = HllSketch::default();
+        hll.update_array(&sliced).unwrap();
+        assert_eq!(hll.get_estimate().round(), 1.0);
+    }
+
+    #[test]
+    fn dictionary() {
+        let keys = Int8Array::from(vec![Some(0), None, Some(2), Some(0)]);
+        let values = StringArray::from(vec![Some("a"), Some("unused"), None]);
+        let array = DictionaryArray::new(keys, Arc::new(values));
+
+        let mut hll = HllSketch::default();
+        hll.update_array(&array).unwrap();
+
+        let mut expected = HllSketch::default();
+        expected.update_string("a");
+        assert!(hll == expected);
+    }
+
+    #[test]
+    fn groups() {
+        let array = Int32Array::from(vec![Some(1), Some(2), None, Some(3), Some(1)]);
+        let group_indices = [0, 1, 1, 1, 0];
+
+        let mut sketches = vec![HllSketch::new(12, HllType::HLL8, false); 2];
+        update_groups(&mut sketches, &group_indices, &array).unwrap();
+        assert_eq!(sketches[0].get_estimate().round(), 1.0);
+        assert_eq!(sketches[1].get_estimate().round(), 2.0);
+
+        assert!(update_groups(&mut sketches, &[0], &array).is_err());
+        assert!(update_groups(&mut sketches, &[0, 1, 2, 1, 0], &array).is_err());
+    }
+
+    #[test]
+    fn runs() {
+        let strings = StringArray::from(vec![
+            Some("x"),
+            Some("a"),
+            Some(""),
+            None,
+            Some("b"),
+            Some("c"),
+            Some("a"),
+        ]);
+        let array = strings.slice(1, 6);
+        let group_indices = [0, 0, 0, 1, 1, 0];
+        let mut sketches = vec![CpcSketch::default(); 2];
+        update_groups(&mut sketches, &group_indices, &array).unwrap();
+
+        let mut expected = vec![CpcSketch::default(); 2];
+        for (row, value) in array.iter().enumerate() {
+            if let Some(value) = value {
+                expected[group_indices[row]].update_string(value);
+            }
+        }
+        assert!(sketches == expected);
+        assert_eq!(sketches[1].get_estimate(), 2.0);
+
+        let array = UInt8Array::from(vec![Some(255), None, Some(1)]);
+        let mut hll = HllSketch::default();
+        hll.update_array(&array).unwrap();
+        let mut expected = HllSketch::default();
+        expected.update_u8(255);
+        expected.update_u8(1);
+        assert!(hll == expected);
+    }
+
+    #[test]
+    fn unsupported() {
+        let array = StructArray::from(vec![(
+            Arc::new(Field::new("a", DataType::Int32, false)),
+            Arc::new(Int32Array::from(vec![1])) as _,
+        )]);
+        let mut cpc = CpcSketch::default();
+        assert!(cpc.update_array(&array).is_err());
+    }
+
+    #[test]
+    fn binary_array() {
+        let mut hll = HllSketch::default();
+        hll.update_array(&Int32Array::from(vec![1, 2, 3])).unwrap();
+
+        let array = to_binary_array([Some(&hll), None]);
+        assert_eq!(array.len(), 2);
+        assert!(array.is_null(1));
+
+        let sketches: Vec<Option<HllSketch>> = from_binary_array(&array).unwrap();
+        assert!(sketches[0].as_ref() == Some(&hll));
+        assert!(sketches[1].is_none());
+
+        let cpc = CpcSketch::default();
+        let sketches: Vec<Option<CpcSketch>> =
+            from_binary_array(&to_binary_array([Some(&cpc)])).unwrap();
+        assert!(sketches[0].as_ref().unwrap().is_empty());
+
+        let image = hll.serialize_compact(0);
+        let corrupt = BinaryArray::from(vec![Some(&image[..image.len() - 1])]);
+        assert!(from_binary_array::<HllSketch>(&corrupt).is_err());
+    }
+}
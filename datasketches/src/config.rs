@@ -0,0 +1,98 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [SketchConfig], [Sizing] and [Confidence].
+
+use crate::hll::HllType;
+
+/// The sketch families supported by this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Family {
+    /// HyperLogLog, see [crate::hll].
+    Hll,
+    /// Compressed Probabilistic Counting, see [crate::cpc].
+    Cpc,
+}
+
+/// Describes how a sketch was configured, so that sketches coming from
+/// different producers can be checked for compatibility before being merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SketchConfig {
+    /// The family of the sketch.
+    pub family: Family,
+    /// The log2 of the number of buckets K: `lg_config_k` for HLL sketches and
+    /// `lg_k` for CPC sketches.
+    pub lg_k: u8,
+    /// The target type of HLL sketches, [None] for other families.
+    pub hll_type: Option<HllType>,
+    /// The seed used to hash items. HLL sketches always use the default seed.
+    pub seed: u64,
+}
+
+impl SketchConfig {
+    /// Indicates whether sketches with the two configurations can be merged by
+    /// the same union. Unions accept sketches with any `lg_k` (and, for HLL,
+    /// any target type), but both the family and the seed must match.
+    pub fn is_compatible_with(&self, other: &SketchConfig) -> bool {
+        self.family == other.family && self.seed == other.seed
+    }
+}
+
+/// The outcome of sizing a sketch for a target error, see
+/// [HllSketch::lg_k_for_error](crate::hll::HllSketch::lg_k_for_error) and
+/// [CpcSketch::lg_k_for_error](crate::cpc::sketch::CpcSketch::lg_k_for_error).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sizing {
+    /// The smallest log2 of the number of buckets K meeting the target error.
+    pub lg_k: u8,
+    /// The theoretical relative error of a sketch configured with `lg_k`.
+    pub relative_error: f64,
+    /// The maximum size in bytes of a serialized sketch configured with `lg_k`.
+    pub max_serialized_bytes: u32,
+}
+
+/// The confidence of an error bound, expressed as a number of standard
+/// deviations of the estimate, for sizing sketches with
+/// [HllSketch::lg_k_for_error](crate::hll::HllSketch::lg_k_for_error) and
+/// [CpcSketch::lg_k_for_error](crate::cpc::sketch::CpcSketch::lg_k_for_error).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Confidence {
+    /// One standard deviation, a confidence of about 68.27%.
+    OneStdDev = 1,
+    /// Two standard deviations, a confidence of about 95.45%.
+    TwoStdDev = 2,
+    /// Three standard deviations, a confidence of about 99.73%.
+    ThreeStdDev = 3,
+}
+
+impl Confidence {
+    /// Returns the smallest confidence at least as high as the given
+    /// probability, so `0.68`, `0.95` and `0.997` map to one, two and three
+    /// standard deviations, or [None] if the probability is above 99.73%.
+    pub fn at_least(probability: f64) -> Option<Self> {
+        [
+            (0.6827, Self::OneStdDev),
+            (0.9545, Self::TwoStdDev),
+            (0.9973, Self::ThreeStdDev),
+        ]
+        .into_iter()
+        .find_map(|(level, confidence)| (probability <= level).then_some(confidence))
+    }
+
+    /// Returns the number of standard deviations of the confidence.
+    #[inline]
+    pub fn num_std_dev(self) -> u8 {
+        self as u8
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Layout of the serialized image of a CPC sketch, which is shared by the
+//! C++, Java and Python libraries.
+
+pub(crate) const PREAMBLE_INTS_BYTE: usize = 0;
+pub(crate) const SER_VER_BYTE: usize = 1;
+pub(crate) const LG_K_BYTE: usize = 3;
+pub(crate) const FIRST_INTERESTING_COLUMN_BYTE: usize = 4;
+pub(crate) const FLAGS_BYTE: usize = 5;
+pub(crate) const SEED_HASH_SHORT: usize = 6;
+pub(crate) const NUM_COUPONS_INT: usize = 8;
+/// Both HIP fields are stored at the same offsets whether or not the sketch
+/// has a surprising values table and a window.
+pub(crate) const KXP_DOUBLE: usize = 16;
+pub(crate) const HIP_EST_ACCUM_DOUBLE: usize = 24;
+
+pub(crate) const HAS_HIP_FLAG: u8 = 1 << 2;
+
+pub(crate) const SER_VER: u8 = 1;
+pub(crate) const FAMILY_ID: u8 = 16;
+
+/// The bounds of the `lg_k` of valid images.
+pub(crate) const MIN_LG_K: u8 = 4;
+pub(crate) const MAX_LG_K: u8 = 26;
+
+/// Returns a canonical encoding of the logical state of the serialized image
+/// of a sketch: its `lg_k`, seed hash and number of coupons followed by its
+/// compressed data. The preamble fields that depend on the history of the
+/// sketch, such as the HIP registers, are left out.
+pub(crate) fn canonical_state(bytes: &[u8]) -> Vec<u8> {
+    let mut state = vec![FAMILY_ID, bytes[LG_K_BYTE]];
+    state.extend(&bytes[SEED_HASH_SHORT..SEED_HASH_SHORT + 2]);
+    if bytes.len() > NUM_COUPONS_INT {
+        state.extend(&bytes[NUM_COUPONS_INT..NUM_COUPONS_INT + 4]);
+    }
+    state.extend(&bytes[bytes[PREAMBLE_INTS_BYTE] as usize * 4..]);
+    state
+}
+
+/// Returns the offset of the sliding window given the number of coupons.
+pub(crate) fn window_offset(lg_k: u8, num_coupons: u32) -> u8 {
+    let k = 1i64 << lg_k;
+    let tmp = ((num_coupons as i64) << 3) - 19 * k;
+    if tmp < 0 {
+        0
+    } else {
+        (tmp >> (lg_k + 3)) as u8
+    }
+}
@@ -25,6 +25,20 @@
 //! Neither HLL nor CPC sketches provide means for set intersections or set
 //! differences. If you anticipate your application might require this
 //! capability you are better off using the Theta family of sketches.
+//!
+//! Unlike [HllSketch](crate::hll::HllSketch), CPC sketches have no native
+//! port: `CpcSketch` and `CpcUnion` wrap the C++ library and only exist with
+//! the `cpp` feature, which the `pure-rust` feature does not replace for them.
+//! Their images are compressed with length-limited Huffman codes whose tables
+//! were generated offline for the reference implementations, and a port
+//! producing the same bytes needs those tables verbatim. A build without the
+//! `cpp` feature has no CPC sketch at all: CPC images can only be inspected
+//! there, see [summary].
 
+#[cfg_attr(not(feature = "cpp"), allow(dead_code))]
+pub(crate) mod format;
+#[cfg(feature = "cpp")]
 pub mod sketch;
+pub mod summary;
+#[cfg(feature = "cpp")]
 pub mod union;
@@ -14,11 +14,25 @@
 
 //! Refer to [CpcSketch].
 
+use crate::config::{Confidence, Family, Sizing, SketchConfig};
+use crate::error::Error;
+use crate::hash::{murmur3_x64_128, preimage, seed_hash};
+use crate::header::inspect_family;
+use crate::item::{check_packed, pack_strings, ItemSink, SketchItem};
 use crate::macros::*;
+use crate::traits::CardinalitySketch;
+
+use super::format::{canonical_state, SEED_HASH_SHORT};
+use super::summary::CpcSummary;
 use std::ops::Deref;
 
 use cxx::{let_cxx_string, UniquePtr};
-use datasketches_sys::cpc::{ffi::*, DEFAULT_LG_K, DEFAULT_SEED};
+use datasketches_sys::cpc::{ffi::*, DEFAULT_LG_K, DEFAULT_SEED, MAX_LG_K, MIN_LG_K};
+
+/// Error constant of the ICON estimator, the natural log of 2.
+const ICON_ERROR_CONSTANT: f64 = std::f64::consts::LN_2;
+/// Error constant of the HIP estimator, the square root of ln(2) / 2.
+const HIP_ERROR_CONSTANT: f64 = 0.5887050112577373;
 
 /// High performance C++ implementation of Compressed Probabilistic Counting (CPC) Sketch.
 ///
@@ -28,7 +42,11 @@ use datasketches_sys::cpc::{ffi::*, DEFAULT_LG_K, DEFAULT_SEED};
 ///
 /// - author Kevin Lang
 /// - author Alexander Saydakov
-pub struct CpcSketch(pub(crate) UniquePtr<cpc_sketch>);
+pub struct CpcSketch(
+    pub(crate) UniquePtr<cpc_sketch>,
+    // The hash seed, which the C++ sketch does not expose.
+    pub(crate) u64,
+);
 
 impl Default for CpcSketch {
     fn default() -> Self {
@@ -38,7 +56,46 @@ impl Default for CpcSketch {
 
 impl Clone for CpcSketch {
     fn clone(&self) -> Self {
-        Self(cpc_sketch_copy(&self.0))
+        Self(cpc_sketch_copy(&self.0), self.1)
+    }
+}
+
+impl<T: SketchItem> Extend<T> for CpcSketch {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.update(&item);
+        }
+    }
+}
+
+impl<T: SketchItem> FromIterator<T> for CpcSketch {
+    /// Builds a sketch with the default configuration out of the given items.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut sketch = Self::default();
+        sketch.extend(iter);
+        sketch
+    }
+}
+
+/// Two sketches are equal when they share `lg_k` and seed and hold the same
+/// coupons. The state of the HIP estimator is ignored.
+impl PartialEq for CpcSketch {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical_state() == other.canonical_state()
+    }
+}
+
+impl Eq for CpcSketch {}
+
+impl std::hash::Hash for CpcSketch {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.content_hash());
+    }
+}
+
+impl std::fmt::Debug for CpcSketch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CpcSketch").field(&self.summary()).finish()
     }
 }
 
@@ -51,13 +108,86 @@ impl std::fmt::Display for CpcSketch {
 impl CpcSketch {
     /// Creates an instance of the sketch given the lg_k parameter and hash seed.
     pub fn new(lg_k: u8, seed: u64) -> Self {
-        Self(cpc_sketch_new(lg_k, seed))
+        Self(cpc_sketch_new(lg_k, seed), seed)
+    }
+
+    /// Returns the theoretical relative error of the estimate of a sketch
+    /// configured with `lg_k`, for a given kappa (1, 2 or 3).
+    /// - `merged`: whether the sketch is the result of a union, in which case
+    ///   the less accurate ICON estimator is used instead of HIP
+    ///
+    /// This is the asymptotic error of the estimators: for `lg_k` up to 14 the
+    /// C++ library refines its bounds with empirical corrections of a few
+    /// percent of this value.
+    ///
+    /// Panics if `lg_k` is not between [MIN_LG_K] and [MAX_LG_K] or if
+    /// `kappa` is not 1, 2 or 3.
+    pub fn relative_error(lg_k: u8, kappa: u32, merged: bool) -> f64 {
+        assert!(
+            (MIN_LG_K..=MAX_LG_K).contains(&lg_k),
+            "lg_k must be between {MIN_LG_K} and {MAX_LG_K}, got {lg_k}"
+        );
+        assert!(
+            (1..=3).contains(&kappa),
+            "kappa must be 1, 2 or 3, got {kappa}"
+        );
+        let constant = if merged {
+            ICON_ERROR_CONSTANT
+        } else {
+            HIP_ERROR_CONSTANT
+        };
+        kappa as f64 * constant / ((1u64 << lg_k) as f64).sqrt()
+    }
+
+    /// Returns the maximum size of a serialized sketch configured with `lg_k`.
+    #[inline]
+    pub fn get_max_serialized_size_bytes(lg_k: u8) -> u32 {
+        cpc_max_serialized_size_bytes(lg_k)
+    }
+
+    /// Returns the smallest `lg_k` whose relative error, at the given
+    /// confidence, is within `target_rse`, together with the size of the
+    /// resulting sketches, or [None] if no valid `lg_k` is accurate enough.
+    ///
+    /// The error accounts for sketches that are the result of a union, which
+    /// are less accurate than sketches that have only been updated.
+    pub fn lg_k_for_error(target_rse: f64, confidence: Confidence) -> Option<Sizing> {
+        let kappa = confidence.num_std_dev().into();
+        (MIN_LG_K..=MAX_LG_K).find_map(|lg_k| {
+            let relative_error = Self::relative_error(lg_k, kappa, true);
+            (relative_error <= target_rse).then(|| Sizing {
+                lg_k,
+                relative_error,
+                max_serialized_bytes: Self::get_max_serialized_size_bytes(lg_k),
+            })
+        })
     }
 
     /// Reconstructs a sketch from a serialized image in a byte array.
+    /// The image must have been produced by a sketch using [DEFAULT_SEED].
+    ///
+    /// Panics if the image is invalid or was produced with another seed, see
+    /// [CpcSketch::deserialize_with_seed].
     #[inline]
     pub fn deserialize(bytes: &[u8]) -> Self {
-        Self(cpc_sketch_deserialize(bytes))
+        Self::deserialize_with_seed(bytes, DEFAULT_SEED).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Reconstructs a sketch from a serialized image in a byte array, produced
+    /// by a sketch using `seed`. The seed hash stored in the image is checked
+    /// before the image is handed to the C++ library, whose exceptions on
+    /// invalid images are reported as errors.
+    pub fn deserialize_with_seed(bytes: &[u8], seed: u64) -> Result<Self, Error> {
+        if let Some(found) = bytes.get(SEED_HASH_SHORT..SEED_HASH_SHORT + 2) {
+            let expected = seed_hash(seed);
+            let found = u16::from_le_bytes([found[0], found[1]]);
+            if found != expected {
+                return Err(Error::IncompatibleSeed { expected, found });
+            }
+        }
+        let sketch = cpc_sketch_deserialize(bytes, seed)
+            .map_err(|_| Error::InvalidImage("rejected by the C++ library"))?;
+        Ok(Self(sketch, seed))
     }
 
     /// This method serializes the sketch as a vector of bytes.
@@ -66,6 +196,22 @@ impl CpcSketch {
         cpc_sketch_serialize(&self.0)
     }
 
+    /// Returns a machine-readable summary of the state of the sketch.
+    pub fn summary(&self) -> CpcSummary {
+        CpcSummary::from_image(&self.serialize(), self.get_estimate())
+    }
+
+    /// Returns a hash of the logical state of the sketch, which is equal for
+    /// sketches that are equal. It is stable across processes and versions of
+    /// this crate, so it can be used to deduplicate stored sketches.
+    pub fn content_hash(&self) -> u64 {
+        murmur3_x64_128(&self.canonical_state(), DEFAULT_SEED).0
+    }
+
+    fn canonical_state(&self) -> Vec<u8> {
+        canonical_state(&self.serialize())
+    }
+
     /// Present the given string as a potential unique item.
     /// The string is converted to a byte array using UTF8 encoding.
     /// If the string is null or empty no update attempt is made and the method returns.
@@ -75,6 +221,61 @@ impl CpcSketch {
         self.0.pin_mut().update_string(raw_datum.deref());
     }
 
+    /// Present the given bytes as a potential unique item.
+    /// The bytes are hashed exactly as by the C++ `update(const void*, size_t)`
+    /// and the Java `update(byte[])` methods, so binary keys can be shared with
+    /// sketches built in other languages.
+    /// If the slice is empty no update attempt is made and the method returns.
+    #[inline]
+    pub fn update_bytes(&mut self, datum: &[u8]) {
+        cpc_sketch_update_bytes(self.0.pin_mut(), datum);
+    }
+
+    /// Present an item by its hash, as returned by the functions of
+    /// [hash](crate::hash) with the seed of the sketch. The item is counted
+    /// exactly as if it had been presented itself.
+    #[inline]
+    pub fn update_hash(&mut self, hash: (u64, u64)) {
+        self.update_bytes(&preimage(hash, self.1));
+    }
+
+    /// Present every unsigned 64-bit integer in the slice as a potential unique item.
+    /// The whole slice is handed to the C++ library in a single call, which is
+    /// much faster than calling [CpcSketch::update_u64] for each element.
+    #[inline]
+    pub fn update_u64_slice(&mut self, data: &[u64]) {
+        cpc_sketch_update_u64_slice(self.0.pin_mut(), data);
+    }
+
+    /// Present every signed 64-bit integer in the slice as a potential unique item.
+    /// The whole slice is handed to the C++ library in a single call.
+    #[inline]
+    pub fn update_i64_slice(&mut self, data: &[i64]) {
+        cpc_sketch_update_i64_slice(self.0.pin_mut(), data);
+    }
+
+    /// Present every 64-bit floating point value in the slice as a potential unique item.
+    /// The whole slice is handed to the C++ library in a single call.
+    #[inline]
+    pub fn update_f64_slice(&mut self, data: &[f64]) {
+        cpc_sketch_update_f64_slice(self.0.pin_mut(), data);
+    }
+
+    /// Present every given string as a potential unique item, as
+    /// [CpcSketch::update_string] does. The strings are packed into a single buffer
+    /// and handed to the C++ library in one call.
+    pub fn update_strings<'a>(&mut self, data: impl IntoIterator<Item = &'a str>) {
+        let (buf, offsets) = pack_strings(data);
+        cpc_sketch_update_strings(self.0.pin_mut(), &buf, &offsets);
+    }
+
+    /// Present the given item as a potential unique item. The item is hashed
+    /// exactly as the C++ and Java libraries hash it, see [SketchItem].
+    #[inline]
+    pub fn update<T: SketchItem + ?Sized>(&mut self, item: &T) {
+        item.update_into(self);
+    }
+
     wrap_mut!(
         /// Present the given unsigned 64-bit integer as a potential unique item.
         pub fn update_u64(datum: u64) -> ());
@@ -124,11 +325,124 @@ impl CpcSketch {
     wrap!(
         /// Indicates if the sketch is currently empty.
         pub fn is_empty() -> bool);
+    wrap!(
+        /// Returns the configured log2 of the number of buckets K.
+        pub fn get_lg_k() -> u8);
+    wrap!(
+        /// Returns the number of coupons collected by the sketch, i.e. the
+        /// number of distinct (row, column) pairs the hashes of its items hit.
+        pub fn get_num_coupons() -> u32);
+
+    /// Returns the seed used to hash the items presented to the sketch.
+    #[inline]
+    pub fn get_seed(&self) -> u64 {
+        self.1
+    }
+
+    /// Returns the 16-bit hash of the seed of the sketch, which serialized
+    /// images store so that sketches built with different seeds are never
+    /// combined.
+    #[inline]
+    pub fn seed_hash(&self) -> u16 {
+        seed_hash(self.1)
+    }
+
+    /// Describes the configuration of the sketch.
+    pub fn config(&self) -> SketchConfig {
+        SketchConfig {
+            family: Family::Cpc,
+            lg_k: self.get_lg_k(),
+            hll_type: None,
+            seed: self.get_seed(),
+        }
+    }
+}
+
+impl ItemSink for CpcSketch {
+    #[inline]
+    fn update_u64(&mut self, datum: u64) {
+        CpcSketch::update_u64(self, datum)
+    }
+
+    #[inline]
+    fn update_i64(&mut self, datum: i64) {
+        CpcSketch::update_i64(self, datum)
+    }
+
+    #[inline]
+    fn update_f64(&mut self, datum: f64) {
+        CpcSketch::update_f64(self, datum)
+    }
+
+    #[inline]
+    fn update_bytes(&mut self, datum: &[u8]) {
+        CpcSketch::update_bytes(self, datum)
+    }
+
+    #[inline]
+    fn update_u64_slice(&mut self, data: &[u64]) {
+        CpcSketch::update_u64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_i64_slice(&mut self, data: &[i64]) {
+        CpcSketch::update_i64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_f64_slice(&mut self, data: &[f64]) {
+        CpcSketch::update_f64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_packed_bytes(&mut self, data: &[u8], offsets: &[usize]) {
+        check_packed(data, offsets);
+        cpc_sketch_update_strings(self.0.pin_mut(), data, offsets);
+    }
+}
+
+impl CardinalitySketch for CpcSketch {
+    #[inline]
+    fn config(&self) -> SketchConfig {
+        CpcSketch::config(self)
+    }
+
+    #[inline]
+    fn get_estimate(&self) -> f64 {
+        CpcSketch::get_estimate(self)
+    }
+
+    #[inline]
+    fn get_lower_bound(&self, num_std_dev: u8) -> f64 {
+        CpcSketch::get_lower_bound(self, num_std_dev.into())
+    }
+
+    #[inline]
+    fn get_upper_bound(&self, num_std_dev: u8) -> f64 {
+        CpcSketch::get_upper_bound(self, num_std_dev.into())
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        CpcSketch::is_empty(self)
+    }
+
+    #[inline]
+    fn serialize(&self) -> Vec<u8> {
+        CpcSketch::serialize(self)
+    }
+
+    /// The image must have been produced by a sketch using [DEFAULT_SEED].
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        inspect_family(bytes, Family::Cpc)?;
+        Self::deserialize_with_seed(bytes, DEFAULT_SEED)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpc::summary::CpcFlavor;
 
     fn dummy_cpc() -> CpcSketch {
         let mut cpc = CpcSketch::default();
@@ -151,4 +465,173 @@ mod tests {
         let compact_cpc = CpcSketch::deserialize(&cpc.serialize());
         assert_eq!(cpc.to_string(), compact_cpc.to_string(),);
     }
+
+    #[test]
+    fn generic_update() {
+        let cpc = dummy_cpc();
+
+        let mut generic_cpc = CpcSketch::default();
+        generic_cpc.update(&-1i8);
+        generic_cpc.update(&1u64);
+        generic_cpc.update(&7i32);
+        assert_eq!(cpc.to_string(), generic_cpc.to_string());
+
+        let mut string_cpc = CpcSketch::default();
+        string_cpc.update_string("datasketches");
+        let mut bytes_cpc = CpcSketch::default();
+        bytes_cpc.update("datasketches".as_bytes());
+        assert_eq!(string_cpc.serialize(), bytes_cpc.serialize());
+    }
+
+    #[test]
+    fn batch_update() {
+        let integers: Vec<i64> = (-500..500).collect();
+        let floats: Vec<f64> = integers.iter().map(|i| *i as f64 / 2.0).collect();
+
+        let mut cpc = CpcSketch::default();
+        for (integer, float) in integers.iter().zip(&floats) {
+            cpc.update_i64(*integer);
+            cpc.update_f64(*float);
+        }
+
+        let mut batch_cpc = CpcSketch::default();
+        batch_cpc.update_i64_slice(&integers);
+        batch_cpc.update_f64_slice(&floats);
+        assert_eq!(cpc.serialize(), batch_cpc.serialize());
+    }
+
+    #[test]
+    fn hash_update() {
+        use crate::hash::*;
+
+        let mut cpc = CpcSketch::new(DEFAULT_LG_K, 42);
+        let mut hashed_cpc = CpcSketch::new(DEFAULT_LG_K, 42);
+        for i in 0..1000 {
+            cpc.update_u64(i);
+            cpc.update_bytes(&i.to_be_bytes());
+            hashed_cpc.update_hash(hash_u64(i, 42));
+            hashed_cpc.update_hash(hash_bytes(&i.to_be_bytes(), 42));
+        }
+        assert_eq!(cpc.serialize(), hashed_cpc.serialize());
+
+        let mut union = crate::cpc::union::CpcUnion::new(DEFAULT_LG_K, 42);
+        union.update_hash(hash_u64(7, 42));
+        let mut sketch = CpcSketch::new(DEFAULT_LG_K, 42);
+        sketch.update_u64(7);
+        assert_eq!(union.get_result(), sketch);
+    }
+
+    #[test]
+    fn equality() {
+        let cpc = dummy_cpc();
+        let deserialized_cpc = CpcSketch::deserialize(&cpc.serialize());
+        assert!(cpc == deserialized_cpc);
+        assert_eq!(cpc.content_hash(), deserialized_cpc.content_hash());
+        assert!(cpc != CpcSketch::default());
+        assert!(CpcSketch::new(10, DEFAULT_SEED) != CpcSketch::new(11, DEFAULT_SEED));
+        assert!(CpcSketch::new(10, DEFAULT_SEED) != CpcSketch::new(10, 42));
+
+        let mut union = crate::cpc::union::CpcUnion::new(DEFAULT_LG_K, DEFAULT_SEED);
+        union.update_sketch(&cpc);
+        let merged_cpc = union.get_result();
+        assert!(merged_cpc.summary().merged);
+        assert!(cpc == merged_cpc);
+        assert_eq!(cpc.content_hash(), merged_cpc.content_hash());
+
+        let mut other = cpc.clone();
+        other.update_string("datasketches");
+        assert!(cpc != other);
+        assert_ne!(cpc.content_hash(), other.content_hash());
+    }
+
+    #[test]
+    fn seeds() {
+        let mut cpc = CpcSketch::new(DEFAULT_LG_K, 42);
+        cpc.update_u64(1);
+        assert_eq!(cpc.seed_hash(), cpc.summary().seed_hash);
+        assert_ne!(cpc.seed_hash(), CpcSketch::default().seed_hash());
+
+        let bytes = cpc.serialize();
+        let deserialized_cpc = CpcSketch::deserialize_with_seed(&bytes, 42).unwrap();
+        assert_eq!(deserialized_cpc, cpc);
+        assert_eq!(deserialized_cpc.get_seed(), 42);
+        assert_eq!(
+            CpcSketch::deserialize_with_seed(&bytes, DEFAULT_SEED),
+            Err(Error::IncompatibleSeed {
+                expected: CpcSketch::default().seed_hash(),
+                found: cpc.seed_hash(),
+            })
+        );
+    }
+
+    #[test]
+    fn collect() {
+        let ids: Vec<u64> = (0..1000).collect();
+
+        let mut cpc = CpcSketch::default();
+        cpc.update_u64_slice(&ids);
+        cpc.extend(["a", "b", "c"]);
+
+        let mut collected_cpc: CpcSketch = ids.iter().collect();
+        collected_cpc.extend(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert_eq!(cpc.serialize(), collected_cpc.serialize(),);
+    }
+
+    #[test]
+    fn config() {
+        let cpc = CpcSketch::new(10, 42);
+        assert_eq!(cpc.get_lg_k(), 10);
+        assert_eq!(cpc.get_seed(), 42);
+        assert_eq!(cpc.clone().config(), cpc.config());
+        assert!(!cpc
+            .config()
+            .is_compatible_with(&CpcSketch::default().config()));
+
+        let compact_cpc = CpcSketch::deserialize(&dummy_cpc().serialize());
+        assert_eq!(compact_cpc.config(), CpcSketch::default().config());
+    }
+
+    #[test]
+    fn sizing() {
+        assert!(CpcSketch::relative_error(11, 1, false) < CpcSketch::relative_error(11, 1, true));
+
+        let sizing = CpcSketch::lg_k_for_error(0.01, Confidence::TwoStdDev).unwrap();
+        assert!(sizing.relative_error <= 0.01);
+        assert!(CpcSketch::relative_error(sizing.lg_k - 1, 2, true) > 0.01);
+        assert!(sizing.max_serialized_bytes > 0);
+
+        assert!(CpcSketch::lg_k_for_error(1e-6, Confidence::ThreeStdDev).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "kappa must be 1, 2 or 3")]
+    fn relative_error_kappa() {
+        CpcSketch::relative_error(11, 0, false);
+    }
+
+    #[test]
+    fn summary() {
+        let summary = dummy_cpc().summary();
+        assert_eq!(summary.lg_k, DEFAULT_LG_K);
+        assert_eq!(summary.flavor, CpcFlavor::Sparse);
+        assert_eq!(summary.num_coupons, 3);
+        assert_eq!(summary.window_offset, 0);
+        assert!(!summary.merged);
+        assert!(summary.kxp.is_some());
+        assert_eq!(summary.hip_est_accum, Some(summary.estimate));
+
+        let mut cpc = CpcSketch::new(10, DEFAULT_SEED);
+        cpc.update_u64_slice(&(0..10_000).collect::<Vec<_>>());
+        let summary = cpc.summary();
+        assert_eq!(summary.flavor, CpcFlavor::Sliding);
+        assert!(summary.window_offset > 0);
+
+        let mut union = crate::cpc::union::CpcUnion::new(10, DEFAULT_SEED);
+        union.update_sketch(&cpc);
+        let summary = union.get_result().summary();
+        assert!(summary.merged);
+        assert_eq!(summary.kxp, None);
+
+        assert_eq!(CpcSketch::default().summary().flavor, CpcFlavor::Empty);
+    }
 }
@@ -0,0 +1,178 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [CpcSummary].
+
+use crate::config::Family;
+use crate::error::Error;
+use crate::format::{read_f64, read_u16, read_u32};
+
+use super::format::*;
+
+/// The representation a CPC sketch currently uses for its state, which is
+/// determined by the number of coupons it collected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpcFlavor {
+    /// No coupons have been collected.
+    Empty,
+    /// Fewer than `3K/32` coupons, all kept in the surprising values table.
+    Sparse,
+    /// Fewer than `K/2` coupons, the window is still at offset 0.
+    Hybrid,
+    /// Fewer than `27K/8` coupons, the window is still at offset 0.
+    Pinned,
+    /// The window slides towards higher columns as coupons are collected.
+    Sliding,
+}
+
+impl CpcFlavor {
+    /// Returns the flavor of a sketch with the given configuration and number
+    /// of coupons.
+    pub fn determine(lg_k: u8, num_coupons: u32) -> Self {
+        let c = num_coupons as u64;
+        let k = 1u64 << lg_k;
+        if c == 0 {
+            Self::Empty
+        } else if (c << 5) < 3 * k {
+            Self::Sparse
+        } else if (c << 1) < k {
+            Self::Hybrid
+        } else if (c << 3) < 27 * k {
+            Self::Pinned
+        } else {
+            Self::Sliding
+        }
+    }
+}
+
+/// A machine-readable summary of the state of a CPC sketch, see
+/// [CpcSketch::summary](super::sketch::CpcSketch::summary).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpcSummary {
+    /// The log2 of the number of buckets K.
+    pub lg_k: u8,
+    /// The 16-bit hash of the seed of the sketch.
+    pub seed_hash: u16,
+    /// The current representation of the sketch.
+    pub flavor: CpcFlavor,
+    /// The number of coupons collected.
+    pub num_coupons: u32,
+    /// The offset of the sliding window.
+    pub window_offset: u8,
+    /// The first column of the bit matrix that is not known to be full.
+    pub first_interesting_column: u8,
+    /// Whether the sketch is the result of a union, in which case the ICON
+    /// estimator is used instead of HIP.
+    pub merged: bool,
+    /// The `kxp` register of the HIP estimator, unless `merged`.
+    pub kxp: Option<f64>,
+    /// The accumulator of the HIP estimator, unless `merged`.
+    pub hip_est_accum: Option<f64>,
+    /// The current cardinality estimate.
+    pub estimate: f64,
+}
+
+impl CpcSummary {
+    /// Reads the summary out of a serialized image of a sketch with the given
+    /// estimate.
+    #[cfg_attr(not(feature = "cpp"), allow(dead_code))]
+    pub(crate) fn from_image(bytes: &[u8], estimate: f64) -> Self {
+        let lg_k = bytes[LG_K_BYTE];
+        let merged = bytes[FLAGS_BYTE] & HAS_HIP_FLAG == 0;
+        let num_coupons = if bytes.len() > NUM_COUPONS_INT {
+            read_u32(bytes, NUM_COUPONS_INT)
+        } else {
+            0
+        };
+        let has_hip = !merged && num_coupons > 0;
+
+        Self {
+            lg_k,
+            seed_hash: read_u16(bytes, SEED_HASH_SHORT),
+            flavor: CpcFlavor::determine(lg_k, num_coupons),
+            num_coupons,
+            window_offset: window_offset(lg_k, num_coupons),
+            first_interesting_column: bytes[FIRST_INTERESTING_COLUMN_BYTE],
+            merged,
+            kxp: has_hip.then(|| read_f64(bytes, KXP_DOUBLE)),
+            hip_est_accum: has_hip.then(|| read_f64(bytes, HIP_EST_ACCUM_DOUBLE)),
+            estimate,
+        }
+    }
+}
+
+/// The preamble of a serialized CPC sketch, see [inspect](crate::inspect).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpcHeader {
+    /// The serialization version of the image.
+    pub serial_version: u8,
+    /// The log2 of the number of buckets K.
+    pub lg_k: u8,
+    /// The 16-bit hash of the seed of the sketch.
+    pub seed_hash: u16,
+    /// Whether the sketch is empty.
+    pub is_empty: bool,
+    /// The number of coupons collected.
+    pub num_coupons: u32,
+    /// Whether the sketch is the result of a union.
+    pub merged: bool,
+}
+
+impl CpcHeader {
+    /// Parses and validates the preamble of an image whose family byte is
+    /// the one of CPC sketches.
+    pub(crate) fn from_image(bytes: &[u8]) -> Result<Self, Error> {
+        let serial_version = bytes[SER_VER_BYTE];
+        if serial_version != SER_VER {
+            return Err(Error::UnsupportedSerialVersion {
+                family: Family::Cpc,
+                version: serial_version,
+            });
+        }
+
+        let expected = bytes[PREAMBLE_INTS_BYTE] as usize * 4;
+        if expected < NUM_COUPONS_INT {
+            return Err(Error::InvalidImage("CPC preamble too small"));
+        }
+        if bytes.len() < expected {
+            return Err(Error::Truncated {
+                expected,
+                found: bytes.len(),
+            });
+        }
+
+        let lg_k = bytes[LG_K_BYTE];
+        if !(MIN_LG_K..=MAX_LG_K).contains(&lg_k) {
+            return Err(Error::InvalidImage("CPC lg_k out of range"));
+        }
+
+        // empty sketches only store the first 8 bytes of the preamble
+        let num_coupons = if expected > NUM_COUPONS_INT {
+            read_u32(bytes, NUM_COUPONS_INT)
+        } else {
+            0
+        };
+        Ok(Self {
+            serial_version,
+            lg_k,
+            seed_hash: read_u16(bytes, SEED_HASH_SHORT),
+            is_empty: num_coupons == 0,
+            num_coupons,
+            merged: bytes[FLAGS_BYTE] & HAS_HIP_FLAG == 0,
+        })
+    }
+}
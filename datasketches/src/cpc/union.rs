@@ -14,6 +14,11 @@
 
 //! Refer to [CpcUnion].
 
+use crate::error::Error;
+use crate::item::{ItemSink, SketchItem};
+use crate::macros::*;
+use crate::traits::{Mergeable, SketchUnion};
+
 use cxx::UniquePtr;
 use datasketches_sys::cpc::{ffi::*, DEFAULT_LG_K, DEFAULT_SEED};
 
@@ -21,9 +26,24 @@ use super::sketch::CpcSketch;
 
 /// High performance C++ implementation of Compressed Probabilistic Counting (CPC) Union.
 ///
+/// Unlike the C++ union, items can also be presented directly to the union:
+/// each update collects them in a sketch with the same configuration as the
+/// union, which is merged into the C++ union before the update returns. The
+/// slice updates merge all of their items at once, so they should be preferred
+/// for batches of items.
+///
 /// - author Kevin Lang
 /// - author Alexander Saydakov
-pub struct CpcUnion(pub(crate) UniquePtr<cpc_union>);
+pub struct CpcUnion(
+    pub(crate) UniquePtr<cpc_union>,
+    // Collects the items presented directly to the union while they are
+    // merged, and is empty otherwise. It also keeps the lg_k and hash seed of
+    // the union, which the C++ union does not expose.
+    pub(crate) CpcSketch,
+    // Whether a non-empty sketch was merged, as the C++ union can only tell
+    // by building its result.
+    pub(crate) bool,
+);
 
 impl Default for CpcUnion {
     fn default() -> Self {
@@ -31,22 +51,294 @@ impl Default for CpcUnion {
     }
 }
 
+impl Clone for CpcUnion {
+    fn clone(&self) -> Self {
+        Self(cpc_union_copy(&self.0), self.1.clone(), self.2)
+    }
+}
+
+impl std::fmt::Display for CpcUnion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.get_result())
+    }
+}
+
+impl<'a> Extend<&'a CpcSketch> for CpcUnion {
+    fn extend<I: IntoIterator<Item = &'a CpcSketch>>(&mut self, iter: I) {
+        for sketch in iter {
+            self.update_sketch(sketch);
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a CpcSketch> for CpcUnion {
+    /// Builds a union with the default configuration out of the given sketches.
+    fn from_iter<I: IntoIterator<Item = &'a CpcSketch>>(iter: I) -> Self {
+        let mut union = Self::default();
+        union.extend(iter);
+        union
+    }
+}
+
 impl CpcUnion {
     /// Creates an instance of the union given the lg_k parameter and hash seed.
     pub fn new(lg_k: u8, seed: u64) -> Self {
-        Self(cpc_union_new(lg_k, seed))
+        Self(cpc_union_new(lg_k, seed), CpcSketch::new(lg_k, seed), false)
+    }
+
+    /// Merges the items just presented to the union into the C++ union.
+    fn merge_items(&mut self) {
+        if !self.1.is_empty() {
+            self.0.pin_mut().update_sketch(&self.1 .0);
+            self.1 = CpcSketch::new(self.1.get_lg_k(), self.1.get_seed());
+            self.2 = true;
+        }
     }
 
     /// This method produces a copy of the current state of the union as a sketch.
-    #[inline]
     pub fn get_result(&self) -> CpcSketch {
-        CpcSketch(cpc_union_get_result(&self.0))
+        CpcSketch(cpc_union_get_result(&self.0), self.get_seed())
+    }
+
+    /// Resets the union to an empty state, keeping its configuration.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.1.get_lg_k(), self.get_seed());
     }
 
     /// Update this union operator with the given sketch.
+    ///
+    /// Panics if the sketch uses another seed than the union, see
+    /// [CpcUnion::try_update_sketch].
     #[inline]
     pub fn update_sketch(&mut self, sketch: &CpcSketch) {
+        self.try_update_sketch(sketch)
+            .unwrap_or_else(|err| panic!("{err}"));
+    }
+
+    /// Update this union operator with the given sketch, unless the sketch
+    /// uses another seed than the union.
+    pub fn try_update_sketch(&mut self, sketch: &CpcSketch) -> Result<(), Error> {
+        let (expected, found) = (self.seed_hash(), sketch.seed_hash());
+        if found != expected {
+            return Err(Error::IncompatibleSeed { expected, found });
+        }
         self.0.pin_mut().update_sketch(&sketch.0);
+        self.2 |= !sketch.is_empty();
+        Ok(())
+    }
+
+    /// Present the given string as a potential unique item.
+    /// The string is converted to a byte array using UTF8 encoding.
+    /// If the string is null or empty no update attempt is made and the method returns.
+    #[inline]
+    pub fn update_string(&mut self, datum: &str) {
+        self.1.update_string(datum);
+        self.merge_items();
+    }
+
+    /// Present the given bytes as a potential unique item.
+    /// If the slice is empty no update attempt is made and the method returns.
+    #[inline]
+    pub fn update_bytes(&mut self, datum: &[u8]) {
+        self.1.update_bytes(datum);
+        self.merge_items();
+    }
+
+    /// Present an item by its hash, as returned by the functions of
+    /// [hash](crate::hash) with the seed of the union. The item is counted
+    /// exactly as if it had been presented itself.
+    #[inline]
+    pub fn update_hash(&mut self, hash: (u64, u64)) {
+        self.1.update_hash(hash);
+        self.merge_items();
+    }
+
+    /// Present the given item as a potential unique item. The item is hashed
+    /// exactly as the C++ and Java libraries hash it, see [SketchItem].
+    #[inline]
+    pub fn update<T: SketchItem + ?Sized>(&mut self, item: &T) {
+        self.1.update(item);
+        self.merge_items();
+    }
+
+    /// Present the given unsigned 64-bit integers as potential unique items,
+    /// see [CpcSketch::update_u64_slice].
+    #[inline]
+    pub fn update_u64_slice(&mut self, data: &[u64]) {
+        self.1.update_u64_slice(data);
+        self.merge_items();
+    }
+
+    /// Present the given signed 64-bit integers as potential unique items,
+    /// see [CpcSketch::update_i64_slice].
+    #[inline]
+    pub fn update_i64_slice(&mut self, data: &[i64]) {
+        self.1.update_i64_slice(data);
+        self.merge_items();
+    }
+
+    /// Present the given 64-bit floating point values as potential unique
+    /// items, see [CpcSketch::update_f64_slice].
+    #[inline]
+    pub fn update_f64_slice(&mut self, data: &[f64]) {
+        self.1.update_f64_slice(data);
+        self.merge_items();
+    }
+
+    /// Present the given strings as potential unique items, see
+    /// [CpcSketch::update_strings].
+    #[inline]
+    pub fn update_strings<'a>(&mut self, data: impl IntoIterator<Item = &'a str>) {
+        self.1.update_strings(data);
+        self.merge_items();
+    }
+
+    delegate_merge_mut!(
+        /// Present the given unsigned 64-bit integer as a potential unique item.
+        pub fn update_u64(datum: u64) -> ());
+    delegate_merge_mut!(
+        /// Present the given unsigned 32-bit integer as a potential unique item.
+        pub fn update_u32(datum: u32) -> ());
+    delegate_merge_mut!(
+        /// Present the given unsigned 16-bit integer as a potential unique item.
+        pub fn update_u16(datum: u16) -> ());
+    delegate_merge_mut!(
+        /// Present the given unsigned 8-bit integer as a potential unique item.
+        pub fn update_u8(datum: u8) -> ());
+
+    delegate_merge_mut!(
+        /// Present the given signed 64-bit integer as a potential unique item.
+        pub fn update_i64(datum: i64) -> ());
+    delegate_merge_mut!(
+        /// Present the given signed 32-bit integer as a potential unique item.
+        pub fn update_i32(datum: i32) -> ());
+    delegate_merge_mut!(
+        /// Present the given signed 16-bit integer as a potential unique item.
+        pub fn update_i16(datum: i16) -> ());
+    delegate_merge_mut!(
+        /// Present the given signed 8-bit integer as a potential unique item.
+        pub fn update_i8(datum: i8) -> ());
+
+    delegate_merge_mut!(
+        /// Present the given 64-bit floating point value as a potential unique item.
+        pub fn update_f64(datum: f64) -> ());
+    delegate_merge_mut!(
+        /// Present the given 32-bit floating point value as a potential unique item.
+        pub fn update_f32(datum: f32) -> ());
+
+    /// Returns the current cardinality estimate of the union.
+    #[inline]
+    pub fn get_estimate(&self) -> f64 {
+        self.get_result().get_estimate()
+    }
+
+    /// Returns the approximate lower error bound of the union given a parameter
+    /// kappa (1, 2 or 3), see [CpcSketch::get_lower_bound].
+    #[inline]
+    pub fn get_lower_bound(&self, kappa: u32) -> f64 {
+        self.get_result().get_lower_bound(kappa)
+    }
+
+    /// Returns the approximate upper error bound of the union given a parameter
+    /// kappa (1, 2 or 3), see [CpcSketch::get_upper_bound].
+    #[inline]
+    pub fn get_upper_bound(&self, kappa: u32) -> f64 {
+        self.get_result().get_upper_bound(kappa)
+    }
+
+    /// Indicates if the union is currently empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        !self.2
+    }
+
+    /// Returns the seed the union expects the sketches it merges to use.
+    #[inline]
+    pub fn get_seed(&self) -> u64 {
+        self.1.get_seed()
+    }
+
+    /// Returns the 16-bit hash of the seed of the union, which must match the
+    /// one of the sketches it merges.
+    #[inline]
+    pub fn seed_hash(&self) -> u16 {
+        self.1.seed_hash()
+    }
+}
+
+impl ItemSink for CpcUnion {
+    #[inline]
+    fn update_u64(&mut self, datum: u64) {
+        CpcUnion::update_u64(self, datum)
+    }
+
+    #[inline]
+    fn update_i64(&mut self, datum: i64) {
+        CpcUnion::update_i64(self, datum)
+    }
+
+    #[inline]
+    fn update_f64(&mut self, datum: f64) {
+        CpcUnion::update_f64(self, datum)
+    }
+
+    #[inline]
+    fn update_bytes(&mut self, datum: &[u8]) {
+        CpcUnion::update_bytes(self, datum)
+    }
+
+    #[inline]
+    fn update_u64_slice(&mut self, data: &[u64]) {
+        CpcUnion::update_u64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_i64_slice(&mut self, data: &[i64]) {
+        CpcUnion::update_i64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_f64_slice(&mut self, data: &[f64]) {
+        CpcUnion::update_f64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_packed_bytes(&mut self, data: &[u8], offsets: &[usize]) {
+        self.1.update_packed_bytes(data, offsets);
+        self.merge_items();
+    }
+}
+
+impl SketchUnion for CpcUnion {
+    type Sketch = CpcSketch;
+
+    #[inline]
+    fn update_sketch(&mut self, sketch: &CpcSketch) {
+        CpcUnion::update_sketch(self, sketch)
+    }
+
+    #[inline]
+    fn get_result(&self) -> CpcSketch {
+        CpcUnion::get_result(self)
+    }
+
+    #[inline]
+    fn get_estimate(&self) -> f64 {
+        CpcUnion::get_estimate(self)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        CpcUnion::is_empty(self)
+    }
+}
+
+impl Mergeable for CpcSketch {
+    type Union = CpcUnion;
+
+    #[inline]
+    fn new_union(&self) -> CpcUnion {
+        CpcUnion::new(self.get_lg_k(), self.get_seed())
     }
 }
 
@@ -77,6 +369,77 @@ mod tests {
         union.update_sketch(&a);
         union.update_sketch(&b);
         let estimate = union.get_result().get_estimate().floor();
-        assert!(estimate >= 224.0 && estimate <= 226.0);
+        assert!((224.0..=226.0).contains(&estimate));
+    }
+
+    #[test]
+    fn collect() {
+        let sketches = [dummy_cpc(200, 0), dummy_cpc(50, 175)];
+        let union: CpcUnion = sketches.iter().collect();
+        let estimate = union.get_result().get_estimate().floor();
+        assert!((224.0..=226.0).contains(&estimate));
+    }
+
+    #[test]
+    fn items() {
+        let a = dummy_cpc(200, 0);
+        let mut union = CpcUnion::default();
+        assert!(union.is_empty());
+
+        union.update_sketch(&a);
+        let snapshot = union.clone();
+        for datum in 175..225u64 {
+            union.update_u64(datum);
+        }
+        let estimate = union.get_estimate().floor();
+        assert!((224.0..=226.0).contains(&estimate));
+        assert!(union.get_lower_bound(2) <= union.get_estimate());
+        assert!(union.get_upper_bound(2) >= union.get_estimate());
+        assert!((199.0..=201.0).contains(&snapshot.get_estimate().floor()));
+
+        // Reading the union must not lose the items of the following updates.
+        union.update_u64(1000);
+        let estimate = union.get_estimate().floor();
+        assert!((225.0..=227.0).contains(&estimate));
+        assert_eq!(union.get_result().get_estimate(), union.get_estimate());
+
+        union.reset();
+        assert!(union.is_empty());
+        assert_eq!(union.get_seed(), DEFAULT_SEED);
+        assert_eq!(union.get_result().get_lg_k(), DEFAULT_LG_K);
+    }
+
+    #[test]
+    fn emptiness() {
+        let mut union = CpcUnion::default();
+        union.update_sketch(&CpcSketch::default());
+        union.update_bytes(b"");
+        union.update_u64_slice(&[]);
+        assert!(union.is_empty());
+        assert!(union.get_result().is_empty());
+
+        union.update_strings(["a", "b", "a"]);
+        assert!(!union.is_empty());
+        assert_eq!(union.get_estimate(), 2.0);
+        assert!(!union.clone().is_empty());
+    }
+
+    #[test]
+    fn seeds() {
+        let mut union = CpcUnion::new(DEFAULT_LG_K, 42);
+        let mut sketch = CpcSketch::new(DEFAULT_LG_K, 42);
+        sketch.update_u64(1);
+        assert_eq!(union.try_update_sketch(&sketch), Ok(()));
+        assert_eq!(union.get_estimate(), 1.0);
+
+        let other = dummy_cpc(10, 0);
+        assert_eq!(
+            union.try_update_sketch(&other),
+            Err(Error::IncompatibleSeed {
+                expected: union.seed_hash(),
+                found: other.seed_hash(),
+            })
+        );
+        assert_eq!(union.get_estimate(), 1.0);
     }
 }
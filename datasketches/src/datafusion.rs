@@ -0,0 +1,544 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [DataFusion](https://datafusion.apache.org) aggregate and scalar functions
+//! counting distinct values with HLL and CPC sketches.
+//!
+//! | Function                                  | Result                                   |
+//! | ----------------------------------------- | ---------------------------------------- |
+//! | `approx_count_distinct_hll(col[, lg_k])`  | the estimate, as a `Float64`             |
+//! | `hll_sketch_agg(col[, lg_k])`             | the compact image of an HLL4 sketch      |
+//! | `hll_union_agg(sketch_col[, lg_k])`       | the compact image of the union           |
+//! | `hll_estimate(sketch)`                    | the estimate of a sketch image           |
+//! | `approx_count_distinct_cpc(col[, lg_k])`  | the estimate, as a `Float64`             |
+//! | `cpc_sketch_agg(col[, lg_k])`             | the image of a CPC sketch                |
+//! | `cpc_union_agg(sketch_col[, lg_k])`       | the image of the union                   |
+//! | `cpc_estimate(sketch)`                    | the estimate of a sketch image           |
+//!
+//! `lg_k` must be a literal, and defaults to the default of each family.
+//! Values are hashed as by [update_array](crate::arrow::update_array). The
+//! partial aggregates are exchanged between partitions as sketch images,
+//! which are merged with unions.
+
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Mutex, PoisonError};
+
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, ArrayRef, BinaryArray, Float64Array};
+use arrow_schema::{DataType, Field};
+use datafusion::common::{exec_err, plan_err, Result, ScalarValue};
+use datafusion::logical_expr::function::{AccumulatorArgs, StateFieldsArgs};
+use datafusion::logical_expr::registry::FunctionRegistry;
+use datafusion::logical_expr::{
+    Accumulator, AggregateUDF, AggregateUDFImpl, ColumnarValue, ScalarUDF, ScalarUDFImpl,
+    Signature, TypeSignature, Volatility,
+};
+use datafusion::physical_expr::expressions::Literal;
+use datasketches_sys::{cpc, hll};
+
+use crate::arrow::{update_array, BinarySketch};
+use crate::cpc::{sketch::CpcSketch, union::CpcUnion};
+use crate::hll::{HllSketch, HllType, HllUnion};
+use crate::traits::{CardinalitySketch, SketchUnion};
+
+/// The operations the functions need from a family of sketches.
+trait Family: fmt::Debug + Send + Sync + 'static {
+    type Sketch: CardinalitySketch + BinarySketch + Send;
+    type Union: SketchUnion<Sketch = Self::Sketch> + Send;
+
+    const NAME: &'static str;
+    const DEFAULT_LG_K: u8;
+    const MIN_LG_K: u8;
+    const MAX_LG_K: u8;
+
+    fn new_sketch(lg_k: u8) -> Self::Sketch;
+    fn new_union(lg_k: u8) -> Self::Union;
+    fn max_size(lg_k: u8) -> usize;
+}
+
+#[derive(Debug)]
+struct Hll;
+
+impl Family for Hll {
+    type Sketch = HllSketch;
+    type Union = HllUnion;
+
+    const NAME: &'static str = "hll";
+    const DEFAULT_LG_K: u8 = hll::DEFAULT_LG_CONFIG_K;
+    const MIN_LG_K: u8 = hll::MIN_LG_CONFIG_K;
+    const MAX_LG_K: u8 = hll::MAX_LG_CONFIG_K;
+
+    fn new_sketch(lg_k: u8) -> HllSketch {
+        HllSketch::new(lg_k, HllType::HLL4, false)
+    }
+
+    fn new_union(lg_k: u8) -> HllUnion {
+        HllUnion::new(lg_k)
+    }
+
+    fn max_size(lg_k: u8) -> usize {
+        HllSketch::get_max_updatable_serialization_bytes(lg_k, HllType::HLL4) as usize
+    }
+}
+
+#[derive(Debug)]
+struct Cpc;
+
+impl Family for Cpc {
+    type Sketch = CpcSketch;
+    type Union = CpcUnion;
+
+    const NAME: &'static str = "cpc";
+    const DEFAULT_LG_K: u8 = cpc::DEFAULT_LG_K;
+    const MIN_LG_K: u8 = cpc::MIN_LG_K;
+    const MAX_LG_K: u8 = cpc::MAX_LG_K;
+
+    fn new_sketch(lg_k: u8) -> CpcSketch {
+        CpcSketch::new(lg_k, cpc::DEFAULT_SEED)
+    }
+
+    fn new_union(lg_k: u8) -> CpcUnion {
+        CpcUnion::new(lg_k, cpc::DEFAULT_SEED)
+    }
+
+    fn max_size(lg_k: u8) -> usize {
+        CpcSketch::get_max_serialized_size_bytes(lg_k) as usize
+    }
+}
+
+/// Whether an aggregate consumes values or sketch images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    Values,
+    Sketches,
+}
+
+/// Whether an aggregate produces an estimate or a sketch image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Estimate,
+    Sketch,
+}
+
+/// Returns the value of a mutex which is only ever accessed through `&mut`,
+/// without locking it. Accumulators must be `Sync`, which the C++ sketches
+/// are not, but they only touch their sketches through `&mut self`.
+fn exclusive<T>(mutex: &mut Mutex<T>) -> &mut T {
+    mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reconstructs a sketch from an image of a user column.
+fn deserialize<F: Family>(image: &[u8]) -> Result<F::Sketch> {
+    <F::Sketch as CardinalitySketch>::deserialize(image)
+        .or_else(|err| exec_err!("invalid {} sketch image: {err}", F::NAME))
+}
+
+/// Collects the values of a partition in a sketch, and merges the partial
+/// aggregates of other partitions in a union created on demand.
+struct DistinctCountAccumulator<F: Family> {
+    lg_k: u8,
+    input: Input,
+    output: Output,
+    sketch: Mutex<F::Sketch>,
+    union: Mutex<Option<F::Union>>,
+    merged: bool,
+}
+
+impl<F: Family> fmt::Debug for DistinctCountAccumulator<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DistinctCountAccumulator")
+            .field("family", &F::NAME)
+            .field("lg_k", &self.lg_k)
+            .field("input", &self.input)
+            .field("output", &self.output)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: Family> DistinctCountAccumulator<F> {
+    fn new(lg_k: u8, input: Input, output: Output) -> Self {
+        Self {
+            lg_k,
+            input,
+            output,
+            sketch: Mutex::new(F::new_sketch(lg_k)),
+            union: Mutex::new(None),
+            merged: false,
+        }
+    }
+
+    /// Merges every non-null sketch image of `array` in the union.
+    fn merge_images(&mut self, array: &ArrayRef) -> Result<()> {
+        let Some(images) = array.as_binary_opt::<i32>() else {
+            return exec_err!(
+                "expected {} sketch images, got {}",
+                F::NAME,
+                array.data_type()
+            );
+        };
+
+        let lg_k = self.lg_k;
+        let union = exclusive(&mut self.union).get_or_insert_with(|| F::new_union(lg_k));
+        for image in images.iter().flatten() {
+            union.update_sketch(&deserialize::<F>(image)?);
+        }
+        self.merged = true;
+        Ok(())
+    }
+
+    /// Returns the sketch of everything seen so far.
+    fn with_result<R>(&mut self, f: impl FnOnce(&F::Sketch) -> R) -> R {
+        let sketch = exclusive(&mut self.sketch);
+        match exclusive(&mut self.union) {
+            None => f(sketch),
+            Some(union) => {
+                if !sketch.is_empty() {
+                    union.update_sketch(sketch);
+                    *sketch = F::new_sketch(self.lg_k);
+                }
+                f(&union.get_result())
+            }
+        }
+    }
+}
+
+impl<F: Family> Accumulator for DistinctCountAccumulator<F> {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        match self.input {
+            Input::Values => Ok(update_array(
+                exclusive(&mut self.sketch),
+                values[0].as_ref(),
+            )?),
+            Input::Sketches => self.merge_images(&values[0]),
+        }
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        Ok(match self.output {
+            Output::Estimate => {
+                ScalarValue::Float64(Some(self.with_result(F::Sketch::get_estimate)))
+            }
+            Output::Sketch => ScalarValue::Binary(Some(self.with_result(F::Sketch::to_image))),
+        })
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + F::max_size(self.lg_k) * (1 + self.merged as usize)
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![ScalarValue::Binary(Some(
+            self.with_result(F::Sketch::to_image),
+        ))])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        self.merge_images(&states[0])
+    }
+}
+
+/// Returns the `lg_k` given as the literal second argument of a function,
+/// if any.
+fn lg_k_argument<F: Family>(name: &str, args: &AccumulatorArgs) -> Result<u8> {
+    let Some(expr) = args.exprs.get(1) else {
+        return Ok(F::DEFAULT_LG_K);
+    };
+    let Some(literal) = expr.as_any().downcast_ref::<Literal>() else {
+        return plan_err!("the lg_k argument of {name} must be a literal");
+    };
+    match literal.value().cast_to(&DataType::UInt8)? {
+        ScalarValue::UInt8(Some(lg_k)) if (F::MIN_LG_K..=F::MAX_LG_K).contains(&lg_k) => Ok(lg_k),
+        value => plan_err!(
+            "the lg_k argument of {name} must be between {} and {}, got {value}",
+            F::MIN_LG_K,
+            F::MAX_LG_K
+        ),
+    }
+}
+
+/// An aggregate function backed by a [DistinctCountAccumulator].
+#[derive(Debug)]
+struct DistinctCountUdaf<F: Family> {
+    name: String,
+    signature: Signature,
+    input: Input,
+    output: Output,
+    family: PhantomData<F>,
+}
+
+impl<F: Family> DistinctCountUdaf<F> {
+    fn new(name: String, input: Input, output: Output) -> Self {
+        Self {
+            name,
+            signature: Signature::one_of(
+                vec![TypeSignature::Any(1), TypeSignature::Any(2)],
+                Volatility::Immutable,
+            ),
+            input,
+            output,
+            family: PhantomData,
+        }
+    }
+}
+
+impl<F: Family> AggregateUDFImpl for DistinctCountUdaf<F> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(match self.output {
+            Output::Estimate => DataType::Float64,
+            Output::Sketch => DataType::Binary,
+        })
+    }
+
+    fn accumulator(&self, args: AccumulatorArgs) -> Result<Box<dyn Accumulator>> {
+        let lg_k = lg_k_argument::<F>(&self.name, &args)?;
+        Ok(Box::new(DistinctCountAccumulator::<F>::new(
+            lg_k,
+            self.input,
+            self.output,
+        )))
+    }
+
+    fn state_fields(&self, args: StateFieldsArgs) -> Result<Vec<Field>> {
+        Ok(vec![Field::new(
+            format!("{}[{}_sketch]", args.name, F::NAME),
+            DataType::Binary,
+            true,
+        )])
+    }
+}
+
+/// A scalar function returning the estimate of sketch images.
+#[derive(Debug)]
+struct EstimateUdf<F: Family> {
+    name: String,
+    signature: Signature,
+    family: PhantomData<F>,
+}
+
+impl<F: Family> ScalarUDFImpl for EstimateUdf<F> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Float64)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let estimate = |image: &[u8]| deserialize::<F>(image).map(|sketch| sketch.get_estimate());
+        match &args[0] {
+            ColumnarValue::Scalar(ScalarValue::Binary(image)) => Ok(ColumnarValue::Scalar(
+                ScalarValue::Float64(image.as_deref().map(estimate).transpose()?),
+            )),
+            ColumnarValue::Array(array) => {
+                let images: &BinaryArray = array.as_binary();
+                let estimates: Float64Array = images
+                    .iter()
+                    .map(|image| image.map(estimate).transpose())
+                    .collect::<Result<_>>()?;
+                Ok(ColumnarValue::Array(std::sync::Arc::new(estimates)))
+            }
+            ColumnarValue::Scalar(value) => {
+                exec_err!("expected a {} sketch image, got {value}", F::NAME)
+            }
+        }
+    }
+}
+
+fn family_udafs<F: Family>() -> Vec<AggregateUDF> {
+    let name = F::NAME;
+    [
+        (
+            format!("approx_count_distinct_{name}"),
+            Input::Values,
+            Output::Estimate,
+        ),
+        (format!("{name}_sketch_agg"), Input::Values, Output::Sketch),
+        (format!("{name}_union_agg"), Input::Sketches, Output::Sketch),
+    ]
+    .into_iter()
+    .map(|(name, input, output)| {
+        AggregateUDF::new_from_impl(DistinctCountUdaf::<F>::new(name, input, output))
+    })
+    .collect()
+}
+
+impl<F: Family> EstimateUdf<F> {
+    fn new() -> Self {
+        Self {
+            name: format!("{}_estimate", F::NAME),
+            signature: Signature::exact(vec![DataType::Binary], Volatility::Immutable),
+            family: PhantomData,
+        }
+    }
+}
+
+/// Returns the aggregate functions of both families.
+pub fn udafs() -> Vec<AggregateUDF> {
+    let mut udafs = family_udafs::<Hll>();
+    udafs.extend(family_udafs::<Cpc>());
+    udafs
+}
+
+/// Returns the scalar functions of both families.
+pub fn udfs() -> Vec<ScalarUDF> {
+    vec![
+        ScalarUDF::new_from_impl(EstimateUdf::<Hll>::new()),
+        ScalarUDF::new_from_impl(EstimateUdf::<Cpc>::new()),
+    ]
+}
+
+/// Registers every function of this module, for instance in the state of a
+/// `SessionContext`.
+pub fn register_all(registry: &mut dyn FunctionRegistry) -> Result<()> {
+    for udaf in udafs() {
+        registry.register_udaf(udaf.into())?;
+    }
+    for udf in udfs() {
+        registry.register_udf(udf.into())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use arrow_array::{Int64Array, StringArray};
+
+    fn partial<F: Family>(values: ArrayRef) -> ScalarValue {
+        let mut accumulator = DistinctCountAccumulator::<F>::new(12, Input::Values, Output::Sketch);
+        accumulator.update_batch(&[values]).unwrap();
+        accumulator.state().unwrap().remove(0)
+    }
+
+    fn merged_estimate<F: Family>() -> f64 {
+        let states = ScalarValue::iter_to_array([
+            partial::<F>(Arc::new(Int64Array::from_iter_values(0..1000))),
+            partial::<F>(Arc::new(Int64Array::from_iter_values(500..1500))),
+            ScalarValue::Binary(None),
+        ])
+        .unwrap();
+
+        let mut accumulator =
+            DistinctCountAccumulator::<F>::new(12, Input::Values, Output::Estimate);
+        accumulator
+            .update_batch(&[Arc::new(Int64Array::from_iter_values(1500..2000))])
+            .unwrap();
+        accumulator.merge_batch(&[states]).unwrap();
+        match accumulator.evaluate().unwrap() {
+            ScalarValue::Float64(Some(estimate)) => estimate,
+            value => panic!("unexpected {value}"),
+        }
+    }
+
+    #[test]
+    fn partial_aggregates() {
+        assert!((1900.0..2100.0).contains(&merged_estimate::<Hll>()));
+        assert!((1900.0..2100.0).contains(&merged_estimate::<Cpc>()));
+    }
+
+    #[test]
+    fn union_agg() {
+        let images = ScalarValue::iter_to_array([
+            partial::<Hll>(Arc::new(StringArray::from(vec!["a", "b"]))),
+            partial::<Hll>(Arc::new(StringArray::from(vec!["b", "c"]))),
+        ])
+        .unwrap();
+
+        let mut accumulator =
+            DistinctCountAccumulator::<Hll>::new(12, Input::Sketches, Output::Sketch);
+        accumulator.update_batch(&[images]).unwrap();
+        let ScalarValue::Binary(Some(image)) = accumulator.evaluate().unwrap() else {
+            panic!("expected a sketch image");
+        };
+        assert_eq!(HllSketch::deserialize(&image).get_estimate().round(), 3.0);
+
+        let mut accumulator =
+            DistinctCountAccumulator::<Hll>::new(12, Input::Sketches, Output::Sketch);
+        let values: ArrayRef = Arc::new(Int64Array::from(vec![1]));
+        assert!(accumulator.update_batch(&[values]).is_err());
+
+        let corrupt =
+            ScalarValue::iter_to_array([ScalarValue::Binary(Some(vec![2, 1, 7, 12]))]).unwrap();
+        assert!(accumulator.update_batch(&[corrupt]).is_err());
+    }
+
+    #[test]
+    fn estimate_udf() {
+        let udf = EstimateUdf::<Cpc>::new();
+        assert_eq!(udf.name(), "cpc_estimate");
+
+        let image = partial::<Cpc>(Arc::new(Int64Array::from(vec![1, 2, 3])));
+        let images =
+            ScalarValue::iter_to_array([image.clone(), ScalarValue::Binary(None)]).unwrap();
+        let ColumnarValue::Array(estimates) = udf.invoke(&[ColumnarValue::Array(images)]).unwrap()
+        else {
+            panic!("expected an array");
+        };
+        let estimates = estimates.as_primitive::<arrow_array::types::Float64Type>();
+        assert_eq!(estimates.value(0).round(), 3.0);
+        assert!(estimates.is_null(1));
+
+        let ColumnarValue::Scalar(ScalarValue::Float64(Some(estimate))) =
+            udf.invoke(&[ColumnarValue::Scalar(image)]).unwrap()
+        else {
+            panic!("expected a scalar");
+        };
+        assert_eq!(estimate.round(), 3.0);
+
+        let corrupt = ScalarValue::Binary(Some(vec![0; 4]));
+        assert!(udf.invoke(&[ColumnarValue::Scalar(corrupt)]).is_err());
+    }
+
+    #[test]
+    fn functions() {
+        let names: Vec<_> = udafs().iter().map(|udaf| udaf.name().to_string()).collect();
+        assert_eq!(
+            names,
+            [
+                "approx_count_distinct_hll",
+                "hll_sketch_agg",
+                "hll_union_agg",
+                "approx_count_distinct_cpc",
+                "cpc_sketch_agg",
+                "cpc_union_agg"
+            ]
+        );
+        assert_eq!(udfs().len(), 2);
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [Error].
+
+use crate::config::Family;
+
+/// The errors reported by the fallible operations of the sketches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// A sketch was built with another hash seed than expected, so its items
+    /// cannot be combined with the ones of the expected seed. Seeds are
+    /// identified by their 16-bit hashes, which is all serialized images
+    /// store, see [CpcSketch::seed_hash](crate::cpc::sketch::CpcSketch::seed_hash).
+    IncompatibleSeed {
+        /// The hash of the expected seed.
+        expected: u16,
+        /// The hash of the seed of the sketch.
+        found: u16,
+    },
+    /// The family byte of an image is not the one of a family supported by
+    /// this crate. The id of the family is reported, see [family_name].
+    UnknownFamily(u8),
+    /// An image of a supported family uses a serialization version this
+    /// crate cannot read.
+    UnsupportedSerialVersion {
+        /// The family of the image.
+        family: Family,
+        /// The serialization version of the image.
+        version: u8,
+    },
+    /// An image is shorter than its preamble requires.
+    Truncated {
+        /// The number of bytes required.
+        expected: usize,
+        /// The length of the image.
+        found: usize,
+    },
+    /// An image holds an invalid value, in its preamble or in its body.
+    InvalidImage(&'static str),
+    /// A sketch cannot be converted to a format requiring another `lg_k`.
+    IncompatibleLgK {
+        /// The smallest `lg_k` the format can be converted from.
+        expected: u8,
+        /// The `lg_k` of the sketch.
+        found: u8,
+    },
+    /// A sketch cannot be converted to a format limited to a smaller `lg_k`.
+    UnsupportedLgK {
+        /// The largest `lg_k` the format can be converted from.
+        max: u8,
+        /// The `lg_k` of the sketch.
+        found: u8,
+    },
+    /// An image holds a sketch of another family than expected.
+    WrongFamily {
+        /// The expected family.
+        expected: Family,
+        /// The family of the image.
+        found: Family,
+    },
+}
+
+/// Returns the name of the DataSketches family with the given id, as stored
+/// in the third byte of serialized images, or [None] for unknown ids.
+pub fn family_name(id: u8) -> Option<&'static str> {
+    let name = match id {
+        1 => "Alpha",
+        2 => "QuickSelect",
+        3 => "Compact Theta",
+        4 => "Theta Union",
+        5 => "Theta Intersection",
+        6 => "Theta A-not-B",
+        7 => "HLL",
+        8 => "Quantiles",
+        9 => "Tuple",
+        10 => "Frequent Items",
+        11 => "Reservoir",
+        12 => "Reservoir Union",
+        13 => "VarOpt",
+        14 => "VarOpt Union",
+        15 => "KLL",
+        16 => "CPC",
+        17 => "REQ",
+        18 => "Count-Min",
+        _ => return None,
+    };
+    Some(name)
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IncompatibleSeed { expected, found } => write!(
+                f,
+                "incompatible seed hashes: expected {expected:#06x}, found {found:#06x}"
+            ),
+            Self::UnknownFamily(id) => match family_name(*id) {
+                Some(name) => write!(f, "unsupported sketch family {id} ({name})"),
+                None => write!(f, "unknown sketch family {id}"),
+            },
+            Self::UnsupportedSerialVersion { family, version } => {
+                write!(f, "unsupported {family:?} serialization version {version}")
+            }
+            Self::Truncated { expected, found } => write!(
+                f,
+                "image of {found} bytes is too short, expected at least {expected}"
+            ),
+            Self::InvalidImage(reason) => write!(f, "invalid image: {reason}"),
+            Self::IncompatibleLgK { expected, found } => {
+                write!(f, "expected an lg_k of at least {expected}, found {found}")
+            }
+            Self::UnsupportedLgK { max, found } => {
+                write!(f, "expected an lg_k of at most {max}, found {found}")
+            }
+            Self::WrongFamily { expected, found } => {
+                write!(
+                    f,
+                    "expected a {expected:?} sketch, found a {found:?} sketch"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,251 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! MurmurHash3_x64_128, the hash function used by every DataSketches library.
+//!
+//! The functions of this module hash items exactly as the sketches do, see
+//! [SketchItem](crate::SketchItem), and return both halves of the 128-bit
+//! hash. Items can thus be hashed upstream and presented to sketches later
+//! through their `update_hash` methods, for example to ship only hashes
+//! across the network:
+//!
+//! ```
+//! use datasketches::hash::{hash_str, DEFAULT_SEED};
+//! use datasketches::hll::HllSketch;
+//!
+//! let mut hashed = HllSketch::default();
+//! hashed.update_hash(hash_str("apple", DEFAULT_SEED));
+//!
+//! let mut direct = HllSketch::default();
+//! direct.update_string("apple");
+//! assert_eq!(hashed, direct);
+//! ```
+
+use crate::item::canonical_f64_bits;
+
+/// The seed of the hash function used by default by every sketch.
+pub const DEFAULT_SEED: u64 = 9001;
+
+const C1: u64 = 0x87c3_7b91_1142_53d5;
+const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+#[inline]
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
+
+#[inline]
+fn unfmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(inverse(0xc4ce_b9fe_1a85_ec53));
+    k ^= k >> 33;
+    k = k.wrapping_mul(inverse(0xff51_afd7_ed55_8ccd));
+    k ^= k >> 33;
+    k
+}
+
+#[inline]
+fn mix_k1(k1: u64) -> u64 {
+    k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2)
+}
+
+#[inline]
+fn unmix_k1(k1: u64) -> u64 {
+    k1.wrapping_mul(inverse(C2))
+        .rotate_right(31)
+        .wrapping_mul(inverse(C1))
+}
+
+#[inline]
+fn mix_k2(k2: u64) -> u64 {
+    k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1)
+}
+
+#[inline]
+fn unmix_k2(k2: u64) -> u64 {
+    k2.wrapping_mul(inverse(C1))
+        .rotate_right(33)
+        .wrapping_mul(inverse(C2))
+}
+
+/// Returns the multiplicative inverse of the odd `x` modulo 2^64.
+const fn inverse(x: u64) -> u64 {
+    // each Newton step doubles the number of correct low bits
+    let mut inv = x;
+    let mut i = 0;
+    while i < 5 {
+        inv = inv.wrapping_mul(2u64.wrapping_sub(x.wrapping_mul(inv)));
+        i += 1;
+    }
+    inv
+}
+
+/// Returns the two halves of the 128-bit MurmurHash3 of `data`.
+pub(crate) fn murmur3_x64_128(data: &[u8], seed: u64) -> (u64, u64) {
+    let (mut h1, mut h2) = (seed, seed);
+
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        let k1 = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let k2 = u64::from_le_bytes(block[8..].try_into().unwrap());
+
+        h1 ^= mix_k1(k1);
+        h1 = h1.rotate_left(27).wrapping_add(h2);
+        h1 = h1.wrapping_mul(5).wrapping_add(0x52dc_e729);
+
+        h2 ^= mix_k2(k2);
+        h2 = h2.rotate_left(31).wrapping_add(h1);
+        h2 = h2.wrapping_mul(5).wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let mut buf = [0u8; 16];
+        buf[..tail.len()].copy_from_slice(tail);
+        if tail.len() > 8 {
+            h2 ^= mix_k2(u64::from_le_bytes(buf[8..].try_into().unwrap()));
+        }
+        h1 ^= mix_k1(u64::from_le_bytes(buf[..8].try_into().unwrap()));
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    (h1, h2)
+}
+
+/// Returns the 16 bytes whose hash with `seed` is `hash`, by running the
+/// single block of MurmurHash3 backwards. The sketches of the C++ library can
+/// only be updated with items, so this is how they are given a hash.
+pub(crate) fn preimage((h1, h2): (u64, u64), seed: u64) -> [u8; 16] {
+    let len = 16;
+    let mut h2 = h2.wrapping_sub(h1);
+    let mut h1 = h1.wrapping_sub(h2);
+    h1 = unfmix64(h1);
+    h2 = unfmix64(h2);
+    h2 = h2.wrapping_sub(h1);
+    h1 = h1.wrapping_sub(h2);
+    h1 ^= len;
+    h2 ^= len;
+
+    let k2 = h2.wrapping_sub(0x3849_5ab5).wrapping_mul(inverse(5));
+    let k2 = unmix_k2(k2.wrapping_sub(h1).rotate_right(31) ^ seed);
+    let k1 = h1.wrapping_sub(0x52dc_e729).wrapping_mul(inverse(5));
+    let k1 = unmix_k1(k1.wrapping_sub(seed).rotate_right(27) ^ seed);
+
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&k1.to_le_bytes());
+    bytes[8..].copy_from_slice(&k2.to_le_bytes());
+    bytes
+}
+
+/// Returns the hash of an unsigned 64-bit integer, as presented by
+/// `update_u64`.
+#[inline]
+pub fn hash_u64(datum: u64, seed: u64) -> (u64, u64) {
+    murmur3_x64_128(&datum.to_le_bytes(), seed)
+}
+
+/// Returns the hash of a signed 64-bit integer, as presented by `update_i64`.
+/// Narrower integers are hashed as their value widened to 64 bits, with
+/// unsigned integers sign-extended through their signed counterpart.
+#[inline]
+pub fn hash_i64(datum: i64, seed: u64) -> (u64, u64) {
+    murmur3_x64_128(&datum.to_le_bytes(), seed)
+}
+
+/// Returns the hash of a 64-bit floating point value, as presented by
+/// `update_f64`. `-0.0` hashes as `0.0` and every NaN as the same value.
+#[inline]
+pub fn hash_f64(datum: f64, seed: u64) -> (u64, u64) {
+    murmur3_x64_128(&canonical_f64_bits(datum).to_le_bytes(), seed)
+}
+
+/// Returns the hash of a byte slice, as presented by `update_bytes`.
+///
+/// Sketches ignore empty slices, so the hash of an empty slice should not be
+/// presented to them.
+#[inline]
+pub fn hash_bytes(datum: &[u8], seed: u64) -> (u64, u64) {
+    murmur3_x64_128(datum, seed)
+}
+
+/// Returns the hash of the UTF-8 bytes of a string, as presented by
+/// `update_string`.
+///
+/// Sketches ignore empty strings, so the hash of an empty string should not
+/// be presented to them.
+#[inline]
+pub fn hash_str(datum: &str, seed: u64) -> (u64, u64) {
+    murmur3_x64_128(datum.as_bytes(), seed)
+}
+
+/// Returns the 16-bit hash of a seed that sketches store in their images to
+/// detect attempts to combine sketches built with different seeds.
+pub(crate) fn seed_hash(seed: u64) -> u16 {
+    murmur3_x64_128(&seed.to_le_bytes(), 0).0 as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_values() {
+        assert_eq!(
+            murmur3_x64_128(b"hello", 0),
+            (0xcbd8_a7b3_41bd_9b02, 0x5b1e_906a_48ae_1d19)
+        );
+        assert_eq!(
+            murmur3_x64_128(b"The quick brown fox jumps over the lazy dog", 0),
+            (0xe34b_bc7b_bc07_1b6c, 0x7a43_3ca9_c49a_9347)
+        );
+        assert_eq!(
+            murmur3_x64_128(b"", 9001),
+            (0x1e70_a322_6649_1bb9, 0x6097_36b2_5240_6b94)
+        );
+        assert_eq!(
+            murmur3_x64_128(b"datasketches", 9001),
+            (0x3bc2_cffa_079d_bdc6, 0x3865_38a6_64cb_f0d8)
+        );
+        assert_eq!(seed_hash(9001), 0x93cc);
+    }
+
+    #[test]
+    fn preimages() {
+        for seed in [DEFAULT_SEED, 0, 42, u64::MAX] {
+            for hash in [
+                (0, 0),
+                (1, u64::MAX),
+                hash_str("datasketches", seed),
+                hash_f64(-0.0, seed),
+            ] {
+                assert_eq!(murmur3_x64_128(&preimage(hash, seed), seed), hash);
+            }
+        }
+        assert_eq!(hash_f64(-0.0, 7), hash_f64(0.0, 7));
+        assert_eq!(hash_f64(f64::NAN, 7), hash_f64(-f64::NAN, 7));
+        assert_eq!(hash_i64(-1, 7), hash_u64(u64::MAX, 7));
+    }
+}
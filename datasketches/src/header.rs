@@ -0,0 +1,215 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [inspect].
+
+use crate::config::Family;
+use crate::cpc::format::FAMILY_ID as CPC_FAMILY_ID;
+use crate::cpc::summary::CpcHeader;
+use crate::error::Error;
+use crate::hash::{seed_hash, DEFAULT_SEED};
+use crate::hll::format::FAMILY_ID as HLL_FAMILY_ID;
+use crate::hll::HllHeader;
+
+/// The byte holding the family id, at the same offset in the images of every
+/// DataSketches family.
+const FAMILY_BYTE: usize = 2;
+/// The size of the first, common, part of the preambles.
+const MIN_PREAMBLE_BYTES: usize = 8;
+
+/// The preamble of a serialized sketch, as returned by [inspect].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SketchHeader {
+    /// The image of an [HllSketch](crate::hll::HllSketch).
+    Hll(HllHeader),
+    /// The image of a [CpcSketch](crate::cpc::sketch::CpcSketch).
+    Cpc(CpcHeader),
+}
+
+impl SketchHeader {
+    /// Returns the family of the sketch.
+    pub fn family(&self) -> Family {
+        match self {
+            Self::Hll(_) => Family::Hll,
+            Self::Cpc(_) => Family::Cpc,
+        }
+    }
+
+    /// Returns the serialization version of the image.
+    pub fn serial_version(&self) -> u8 {
+        match self {
+            Self::Hll(header) => header.serial_version,
+            Self::Cpc(header) => header.serial_version,
+        }
+    }
+
+    /// Returns the log2 of the number of buckets K: `lg_config_k` for HLL
+    /// sketches and `lg_k` for CPC sketches.
+    pub fn lg_k(&self) -> u8 {
+        match self {
+            Self::Hll(header) => header.lg_config_k,
+            Self::Cpc(header) => header.lg_k,
+        }
+    }
+
+    /// Returns whether the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Hll(header) => header.is_empty,
+            Self::Cpc(header) => header.is_empty,
+        }
+    }
+
+    /// Returns the 16-bit hash of the seed of the sketch. HLL images do not
+    /// store it, as HLL sketches always use [DEFAULT_SEED].
+    pub fn seed_hash(&self) -> u16 {
+        match self {
+            Self::Hll(_) => seed_hash(DEFAULT_SEED),
+            Self::Cpc(header) => header.seed_hash,
+        }
+    }
+}
+
+/// Parses the preamble of a serialized sketch of any family supported by this
+/// crate, without deserializing the sketch. This is much cheaper than
+/// deserialization and never hands the image to the C++ library, so it can
+/// be used to route or validate untrusted images.
+///
+/// Only the preamble is validated: an image with a valid preamble may still
+/// hold corrupted data.
+pub fn inspect(bytes: &[u8]) -> Result<SketchHeader, Error> {
+    if bytes.len() < MIN_PREAMBLE_BYTES {
+        return Err(Error::Truncated {
+            expected: MIN_PREAMBLE_BYTES,
+            found: bytes.len(),
+        });
+    }
+    match bytes[FAMILY_BYTE] {
+        HLL_FAMILY_ID => HllHeader::from_image(bytes).map(SketchHeader::Hll),
+        CPC_FAMILY_ID => CpcHeader::from_image(bytes).map(SketchHeader::Cpc),
+        id => Err(Error::UnknownFamily(id)),
+    }
+}
+
+/// Parses the preamble of an image with [inspect], failing if the image holds
+/// a sketch of another family than `expected`.
+pub(crate) fn inspect_family(bytes: &[u8], expected: Family) -> Result<SketchHeader, Error> {
+    let header = inspect(bytes)?;
+    if header.family() != expected {
+        return Err(Error::WrongFamily {
+            expected,
+            found: header.family(),
+        });
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "cpp")]
+    use crate::cpc::sketch::CpcSketch;
+    use crate::hll::{HllMode, HllSketch, HllType};
+
+    #[test]
+    fn hll() {
+        let mut hll = HllSketch::new(10, HllType::HLL6, false);
+        let header = inspect(&hll.serialize_compact(0)).unwrap();
+        assert_eq!(
+            header,
+            SketchHeader::Hll(HllHeader {
+                serial_version: 1,
+                lg_config_k: 10,
+                target_type: HllType::HLL6,
+                mode: HllMode::List,
+                is_empty: true,
+                is_compact: true,
+                out_of_order: false,
+            })
+        );
+        assert_eq!(header.family(), Family::Hll);
+        assert_eq!(header.seed_hash(), seed_hash(DEFAULT_SEED));
+
+        hll.update_u64_slice(&(0..1000).collect::<Vec<_>>());
+        let SketchHeader::Hll(header) = inspect(&hll.serialize_updatable()).unwrap() else {
+            panic!("not an HLL header");
+        };
+        assert_eq!(header.mode, HllMode::Hll);
+        assert!(!header.is_empty && !header.is_compact);
+    }
+
+    #[test]
+    #[cfg(feature = "cpp")]
+    fn cpc() {
+        let mut cpc = CpcSketch::new(9, 42);
+        let header = inspect(&cpc.serialize()).unwrap();
+        assert_eq!(header.family(), Family::Cpc);
+        assert_eq!(header.lg_k(), 9);
+        assert!(header.is_empty());
+        assert_eq!(header.seed_hash(), cpc.seed_hash());
+
+        cpc.update_u64(1);
+        let SketchHeader::Cpc(header) = inspect(&cpc.serialize()).unwrap() else {
+            panic!("not a CPC header");
+        };
+        assert_eq!(header.num_coupons, 1);
+        assert!(!header.is_empty && !header.merged);
+    }
+
+    #[test]
+    fn errors() {
+        let image = HllSketch::default().serialize_compact(0);
+        assert_eq!(
+            inspect(&image[..4]),
+            Err(Error::Truncated {
+                expected: 8,
+                found: 4
+            })
+        );
+
+        let mut theta = image.clone();
+        theta[FAMILY_BYTE] = 3;
+        assert_eq!(inspect(&theta), Err(Error::UnknownFamily(3)));
+        assert_eq!(
+            Error::UnknownFamily(3).to_string(),
+            "unsupported sketch family 3 (Compact Theta)"
+        );
+
+        let mut version = image.clone();
+        version[1] = 2;
+        assert_eq!(
+            inspect(&version),
+            Err(Error::UnsupportedSerialVersion {
+                family: Family::Hll,
+                version: 2
+            })
+        );
+
+        let mut lg_k = image;
+        lg_k[3] = 30;
+        assert!(matches!(inspect(&lg_k), Err(Error::InvalidImage(_))));
+
+        #[cfg(feature = "cpp")]
+        {
+            let mut cpc = CpcSketch::default();
+            cpc.update_u64(1);
+            let image = cpc.serialize();
+            assert!(matches!(
+                inspect(&image[..12]),
+                Err(Error::Truncated { found: 12, .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The improved raw estimator of Otmar Ertl, "New cardinality estimation
+//! algorithms for HyperLogLog sketches" (2017), used by Redis. Unlike the
+//! estimators of the C++ library it needs no empirical tables, so it is used
+//! to estimate the sketches imported from other libraries as they are.
+
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// Returns the estimate of the given registers, filled with the hashes of
+/// `q` bits left once the register index is taken. Registers above `q + 1`
+/// are ignored, like Redis does.
+pub(crate) fn estimate(registers: &[u8], q: usize) -> f64 {
+    let mut histogram = [0u32; 256];
+    for &value in registers {
+        histogram[value as usize] += 1;
+    }
+
+    let m = registers.len() as f64;
+    let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+    for j in (1..=q).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    ALPHA_INF * m * m / z
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Layout of the serialized image of an HLL sketch, which is shared by the
+//! C++, Java and Python libraries.
+
+use super::HllType;
+
+pub(crate) const PREAMBLE_INTS_BYTE: usize = 0;
+pub(crate) const SER_VER_BYTE: usize = 1;
+pub(crate) const FAMILY_BYTE: usize = 2;
+pub(crate) const LG_K_BYTE: usize = 3;
+#[cfg_attr(not(feature = "pure-rust"), allow(dead_code))]
+pub(crate) const LG_ARR_BYTE: usize = 4;
+pub(crate) const FLAGS_BYTE: usize = 5;
+pub(crate) const LIST_COUNT_BYTE: usize = 6;
+pub(crate) const HLL_CUR_MIN_BYTE: usize = 6;
+pub(crate) const MODE_BYTE: usize = 7;
+
+pub(crate) const LIST_INT_ARR_START: usize = 8;
+pub(crate) const HASH_SET_COUNT_INT: usize = 8;
+pub(crate) const HASH_SET_INT_ARR_START: usize = 12;
+
+pub(crate) const HIP_ACCUM_DOUBLE: usize = 8;
+pub(crate) const KXQ0_DOUBLE: usize = 16;
+pub(crate) const KXQ1_DOUBLE: usize = 24;
+pub(crate) const CUR_MIN_COUNT_INT: usize = 32;
+pub(crate) const AUX_COUNT_INT: usize = 36;
+pub(crate) const HLL_BYTE_ARR_START: usize = 40;
+
+pub(crate) const LIST_PREINTS: u8 = 2;
+pub(crate) const HASH_SET_PREINTS: u8 = 3;
+pub(crate) const HLL_PREINTS: u8 = 10;
+pub(crate) const SER_VER: u8 = 1;
+pub(crate) const FAMILY_ID: u8 = 7;
+
+/// The bounds of the `lg_config_k` of valid images, the ones of the C++
+/// library. They are wider than the bounds of the sketches this crate
+/// configures, so that sketches of any valid `lg_config_k` can be read back.
+pub(crate) const MIN_LG_K: u8 = 4;
+pub(crate) const MAX_LG_K: u8 = 21;
+
+pub(crate) const EMPTY_FLAG_MASK: u8 = 4;
+pub(crate) const COMPACT_FLAG_MASK: u8 = 8;
+pub(crate) const OUT_OF_ORDER_FLAG_MASK: u8 = 16;
+#[cfg_attr(not(feature = "pure-rust"), allow(dead_code))]
+pub(crate) const FULL_SIZE_FLAG_MASK: u8 = 32;
+
+pub(crate) const CUR_MODE_LIST: u8 = 0;
+pub(crate) const CUR_MODE_SET: u8 = 1;
+pub(crate) const CUR_MODE_HLL: u8 = 2;
+
+/// Coupons and the pairs of the auxiliary array of HLL4 sketches hold an
+/// address in their 26 low bits and a register value above them.
+pub(crate) const KEY_BITS_26: u32 = 26;
+pub(crate) const KEY_MASK_26: u32 = (1 << KEY_BITS_26) - 1;
+
+/// Returns the current mode, stored in the two low bits of the mode byte.
+#[inline]
+pub(crate) fn cur_mode(bytes: &[u8]) -> u8 {
+    bytes[MODE_BYTE] & 3
+}
+
+/// Returns the mode byte for the given current mode and target type.
+#[inline]
+pub(crate) fn mode_byte(cur_mode: u8, tgt_type: HllType) -> u8 {
+    let tgt_type = match tgt_type {
+        HllType::HLL4 => 0,
+        HllType::HLL6 => 1,
+        HllType::HLL8 => 2,
+    };
+    (tgt_type << 2) | cur_mode
+}
+
+/// Returns the target HLL type, stored in the two bits above the current mode.
+#[inline]
+pub(crate) fn tgt_hll_type(bytes: &[u8]) -> HllType {
+    match (bytes[MODE_BYTE] >> 2) & 3 {
+        0 => HllType::HLL4,
+        1 => HllType::HLL6,
+        _ => HllType::HLL8,
+    }
+}
@@ -24,14 +24,49 @@
 //! accuracy than the Theta Sketches mentioned above, and the CPC sketch is
 //! another 30 to 40% smaller still.
 
-use datasketches_sys::hll::ffi::target_hll_type;
+use backend::target_hll_type;
+pub(crate) use backend::MAX_LG_CONFIG_K;
+#[cfg(feature = "cpp")]
+pub(crate) use backend::MIN_LG_CONFIG_K;
 
+pub(crate) mod ertl;
+pub(crate) mod format;
+#[cfg(feature = "pure-rust")]
+mod native;
+pub mod redis;
+pub mod registers;
 pub mod sketch;
+pub mod summary;
 pub mod union;
 
+pub use redis::RedisHll;
+pub use registers::*;
 pub use sketch::*;
+pub use summary::*;
 pub use union::*;
 
+/// The implementation of the sketch and union wrapped by [HllSketch] and
+/// [HllUnion]: the C++ library, or its native port with the `pure-rust`
+/// feature.
+#[cfg(not(feature = "pure-rust"))]
+mod backend {
+    pub(crate) use cxx::UniquePtr;
+    pub(crate) use datasketches_sys::hll::ffi::*;
+    pub(crate) use datasketches_sys::hll::{DEFAULT_LG_CONFIG_K, MAX_LG_CONFIG_K, MIN_LG_CONFIG_K};
+
+    use crate::error::Error;
+
+    /// Reconstructs a sketch, reporting the exceptions the C++ library throws
+    /// on invalid images as errors instead of aborting.
+    pub(crate) fn hll_sketch_deserialize(bytes: &[u8]) -> Result<UniquePtr<hll_sketch>, Error> {
+        datasketches_sys::hll::ffi::hll_sketch_deserialize(bytes)
+            .map_err(|_| Error::InvalidImage("rejected by the C++ library"))
+    }
+}
+
+#[cfg(feature = "pure-rust")]
+use native as backend;
+
 /// Specifies the target type of HLL sketch to be created. It is a target in that the actual
 /// allocation of the HLL array is deferred until sufficient number of items have been received by
 /// the warm-up phases.
@@ -44,6 +79,8 @@ pub use union::*;
 /// out very small (8 bytes, when empty) and then grows in increments of 4 bytes as required
 /// until the full HLL array is allocated.  This transition point occurs at about 10% of K for
 /// sketches where `lg_config_k` is > 8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HllType {
     /// This uses a 4-bit field per HLL bucket and for large counts may require
     /// the use of a small internal auxiliary array for storing statistical exceptions, which are rare.
@@ -70,3 +107,14 @@ impl From<HllType> for target_hll_type {
         }
     }
 }
+
+impl From<target_hll_type> for HllType {
+    fn from(value: target_hll_type) -> Self {
+        match value {
+            target_hll_type::HLL_4 => Self::HLL4,
+            target_hll_type::HLL_6 => Self::HLL6,
+            target_hll_type::HLL_8 => Self::HLL8,
+            _ => unreachable!("unknown target_hll_type {}", value.repr),
+        }
+    }
+}
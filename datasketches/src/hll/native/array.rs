@@ -0,0 +1,624 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The array of registers of a sketch in HLL mode, packed in 4, 6 or 8 bits.
+
+use crate::error::Error;
+use crate::format::{read_f64, read_u32};
+
+use super::super::{format::*, HllType};
+use super::aux::AuxHashMap;
+use super::coupons::CouponList;
+use super::estimators::{bitmap_estimate, hll_rel_error};
+use super::{
+    check_len, check_num_std_dev, flags_byte, get_low_26, get_value, inv_pow2, write_f64,
+    write_u32, AUX_TOKEN, LG_AUX_ARR_INTS,
+};
+
+/// The largest value a register can hold, as stored in 6 bits.
+const MAX_REGISTER_VALUE: u8 = 63;
+
+/// Returns the size of the array of registers.
+pub(super) fn array_bytes(lg_config_k: u8, tgt_type: HllType) -> usize {
+    let k = 1usize << lg_config_k;
+    match tgt_type {
+        HllType::HLL4 => k / 2,
+        HllType::HLL6 => k * 3 / 4 + 1,
+        HllType::HLL8 => k,
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct HllArray {
+    pub(super) lg_config_k: u8,
+    pub(super) tgt_type: HllType,
+    pub(super) start_full_size: bool,
+    pub(super) out_of_order: bool,
+    pub(super) hip_accum: f64,
+    /// The smallest register value, always 0 for HLL6 and HLL8 arrays
+    /// outside of unions.
+    cur_min: u8,
+    num_at_cur_min: u32,
+    kxq0: f64,
+    kxq1: f64,
+    bytes: Vec<u8>,
+    aux: Option<AuxHashMap>,
+}
+
+impl HllArray {
+    pub(super) fn new(lg_config_k: u8, tgt_type: HllType, start_full_size: bool) -> Self {
+        Self {
+            lg_config_k,
+            tgt_type,
+            start_full_size,
+            out_of_order: false,
+            hip_accum: 0.0,
+            cur_min: 0,
+            num_at_cur_min: 1 << lg_config_k,
+            kxq0: f64::from(1u32 << lg_config_k),
+            kxq1: 0.0,
+            bytes: vec![0; array_bytes(lg_config_k, tgt_type)],
+            aux: None,
+        }
+    }
+
+    /// Promotes a full list or set. The HIP accumulator starts from the
+    /// estimate of the coupons.
+    pub(super) fn from_coupons(coupons: &CouponList) -> Self {
+        let mut array = Self::new(coupons.lg_config_k, coupons.tgt_type, false);
+        for coupon in coupons.iter() {
+            array.coupon_update(coupon);
+        }
+        array.hip_accum = coupons.estimate();
+        array
+    }
+
+    /// Reads a sketch out of an HLL image, whose preamble is valid.
+    pub(super) fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let lg_config_k = bytes[LG_K_BYTE];
+        let tgt_type = tgt_hll_type(bytes);
+        let flags = bytes[FLAGS_BYTE];
+        let arr_end = HLL_BYTE_ARR_START + array_bytes(lg_config_k, tgt_type);
+        check_len(bytes, arr_end)?;
+
+        let mut array = Self::new(lg_config_k, tgt_type, flags & FULL_SIZE_FLAG_MASK != 0);
+        array.out_of_order = flags & OUT_OF_ORDER_FLAG_MASK != 0;
+        array.cur_min = bytes[HLL_CUR_MIN_BYTE];
+        array.hip_accum = read_f64(bytes, HIP_ACCUM_DOUBLE);
+        array.kxq0 = read_f64(bytes, KXQ0_DOUBLE);
+        array.kxq1 = read_f64(bytes, KXQ1_DOUBLE);
+        array.num_at_cur_min = read_u32(bytes, CUR_MIN_COUNT_INT);
+        array
+            .bytes
+            .copy_from_slice(&bytes[HLL_BYTE_ARR_START..arr_end]);
+
+        let aux_count = read_u32(bytes, AUX_COUNT_INT);
+        if tgt_type == HllType::HLL4 && aux_count > 0 {
+            array.aux = Some(AuxHashMap::deserialize(
+                bytes,
+                arr_end,
+                lg_config_k,
+                aux_count,
+                bytes[LG_ARR_BYTE],
+                flags & COMPACT_FLAG_MASK != 0,
+            )?);
+        }
+        array.check_registers()?;
+        Ok(array)
+    }
+
+    /// Checks that the registers of a deserialized array agree with
+    /// `cur_min`, `num_at_cur_min` and the auxiliary map, which the updates
+    /// rely on.
+    fn check_registers(&self) -> Result<(), Error> {
+        if self.cur_min > MAX_REGISTER_VALUE {
+            return Err(Error::InvalidImage("HLL cur_min out of range"));
+        }
+        let k = 1u32 << self.lg_config_k;
+        let mut num_at_cur_min = 0;
+        let mut num_aux_tokens = 0;
+        for slot in 0..k {
+            let raw = self.get_slot(slot);
+            let value = match self.tgt_type {
+                HllType::HLL4 if raw == AUX_TOKEN => {
+                    num_aux_tokens += 1;
+                    continue;
+                }
+                HllType::HLL4 => raw + self.cur_min,
+                _ => raw,
+            };
+            if value > MAX_REGISTER_VALUE || value < self.cur_min {
+                return Err(Error::InvalidImage("HLL register value out of range"));
+            }
+            if value == self.cur_min {
+                num_at_cur_min += 1;
+            }
+        }
+
+        // HLL6 and HLL8 arrays only keep `num_at_cur_min` up to date while
+        // `cur_min` is 0, see `update_slot_with_kxq`
+        let exact = self.tgt_type == HllType::HLL4 || self.cur_min == 0;
+        if (exact && self.num_at_cur_min != num_at_cur_min) || self.num_at_cur_min > k {
+            return Err(Error::InvalidImage(
+                "HLL cur_min count does not match the registers",
+            ));
+        }
+
+        if self.aux.as_ref().map_or(0, AuxHashMap::aux_count) != num_aux_tokens {
+            return Err(Error::InvalidImage(
+                "HLL auxiliary map does not match the registers",
+            ));
+        }
+        for pair in self.aux.iter().flat_map(AuxHashMap::iter) {
+            let value = get_value(pair);
+            if self.get_slot(get_low_26(pair) & (k - 1)) != AUX_TOKEN
+                || value < self.cur_min + AUX_TOKEN
+                || value > MAX_REGISTER_VALUE
+            {
+                return Err(Error::InvalidImage(
+                    "HLL auxiliary map does not match the registers",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(super) fn is_empty(&self) -> bool {
+        self.cur_min == 0 && self.num_at_cur_min == 1 << self.lg_config_k
+    }
+
+    /// Returns the raw content of a slot: an offset from `cur_min` or
+    /// [AUX_TOKEN] for HLL4 arrays, the value of the register otherwise.
+    fn get_slot(&self, slot: u32) -> u8 {
+        let slot = slot as usize;
+        match self.tgt_type {
+            HllType::HLL4 => (self.bytes[slot >> 1] >> ((slot & 1) << 2)) & 0xf,
+            HllType::HLL6 => {
+                let start_bit = slot * 6;
+                let index = start_bit >> 3;
+                let two_bytes = u16::from_le_bytes([self.bytes[index], self.bytes[index + 1]]);
+                ((two_bytes >> (start_bit & 7)) & 0x3f) as u8
+            }
+            HllType::HLL8 => self.bytes[slot],
+        }
+    }
+
+    fn put_slot(&mut self, slot: u32, value: u8) {
+        let slot = slot as usize;
+        match self.tgt_type {
+            HllType::HLL4 => {
+                let byte = &mut self.bytes[slot >> 1];
+                *byte = if slot & 1 == 0 {
+                    (*byte & 0xf0) | (value & 0x0f)
+                } else {
+                    (*byte & 0x0f) | (value << 4)
+                };
+            }
+            HllType::HLL6 => {
+                let start_bit = slot * 6;
+                let index = start_bit >> 3;
+                let shift = start_bit & 7;
+                let mut two_bytes = u16::from_le_bytes([self.bytes[index], self.bytes[index + 1]]);
+                two_bytes &= !(0x3f << shift);
+                two_bytes |= u16::from(value & 0x3f) << shift;
+                self.bytes[index..index + 2].copy_from_slice(&two_bytes.to_le_bytes());
+            }
+            HllType::HLL8 => self.bytes[slot] = value & 0x3f,
+        }
+    }
+
+    /// Returns the value of the register at `slot`.
+    pub(super) fn get_value(&self, slot: u32) -> u8 {
+        let raw = self.get_slot(slot);
+        match self.tgt_type {
+            HllType::HLL4 if raw == AUX_TOKEN => self
+                .aux
+                .as_ref()
+                .expect("auxiliary map of an HLL4 array with exceptions")
+                .must_find_value_for(slot),
+            HllType::HLL4 => raw + self.cur_min,
+            _ => raw,
+        }
+    }
+
+    /// Returns the slots of the array with their values, skipping empty
+    /// registers unless `all`.
+    pub(super) fn iter(&self, all: bool) -> impl Iterator<Item = (u32, u8)> + '_ {
+        (0..1u32 << self.lg_config_k)
+            .map(|slot| (slot, self.get_value(slot)))
+            .filter(move |(_, value)| all || *value != 0)
+    }
+
+    pub(super) fn aux(&self) -> Option<&AuxHashMap> {
+        self.aux.as_ref()
+    }
+
+    pub(super) fn coupon_update(&mut self, coupon: u32) {
+        let value = get_value(coupon);
+        if self.tgt_type == HllType::HLL4 && value <= self.cur_min {
+            return;
+        }
+        let slot = get_low_26(coupon) & ((1 << self.lg_config_k) - 1);
+        match self.tgt_type {
+            HllType::HLL4 => self.hll4_update(slot, value),
+            _ => self.update_slot_with_kxq(slot, value),
+        }
+    }
+
+    /// Raises the register at `slot` of an HLL6 or HLL8 array to `value`.
+    fn update_slot_with_kxq(&mut self, slot: u32, value: u8) {
+        let old_value = self.get_slot(slot);
+        if value > old_value {
+            self.put_slot(slot, value);
+            self.hip_and_kxq_incremental_update(old_value, value);
+            if old_value == 0 {
+                // cur_min stays at 0, num_at_cur_min counts the empty registers
+                self.num_at_cur_min -= 1;
+            }
+        }
+    }
+
+    fn hll4_update(&mut self, slot: u32, value: u8) {
+        let cur_min = self.cur_min;
+        let raw_stored_old_nibble = self.get_slot(slot);
+        let lb0n_old_value = raw_stored_old_nibble + cur_min;
+        if value <= lb0n_old_value {
+            return;
+        }
+
+        let actual_old_value = if raw_stored_old_nibble < AUX_TOKEN {
+            lb0n_old_value
+        } else {
+            self.get_value(slot)
+        };
+        if value <= actual_old_value {
+            return;
+        }
+
+        self.hip_and_kxq_incremental_update(actual_old_value, value);
+        let shifted_new_value = value - cur_min;
+        if raw_stored_old_nibble == AUX_TOKEN {
+            // both the old and the new value are exceptions, as cur_min did
+            // not change and the new value is larger
+            self.aux
+                .as_mut()
+                .expect("auxiliary map of an HLL4 array with exceptions")
+                .must_replace(slot, value);
+        } else if shifted_new_value >= AUX_TOKEN {
+            self.put_slot(slot, AUX_TOKEN);
+            let lg_config_k = self.lg_config_k;
+            self.aux
+                .get_or_insert_with(|| {
+                    AuxHashMap::new(LG_AUX_ARR_INTS[lg_config_k as usize], lg_config_k)
+                })
+                .must_add(slot, value);
+        } else {
+            self.put_slot(slot, shifted_new_value);
+        }
+
+        if actual_old_value == cur_min {
+            self.num_at_cur_min -= 1;
+            while self.num_at_cur_min == 0 {
+                self.shift_to_bigger_cur_min();
+            }
+        }
+    }
+
+    /// Increments `cur_min` of an HLL4 array once no register holds it,
+    /// decrementing the stored offsets and dropping the exceptions which now
+    /// fit in a nibble.
+    fn shift_to_bigger_cur_min(&mut self) {
+        let new_cur_min = self.cur_min + 1;
+        let k = 1u32 << self.lg_config_k;
+        let mut num_at_new_cur_min = 0;
+        let mut num_aux_tokens = 0;
+
+        for slot in 0..k {
+            let old_nibble = self.get_slot(slot);
+            assert_ne!(
+                old_nibble, 0,
+                "HLL4 array cannot hold 0 when shifting cur_min"
+            );
+            if old_nibble < AUX_TOKEN {
+                let new_nibble = old_nibble - 1;
+                self.put_slot(slot, new_nibble);
+                if new_nibble == 0 {
+                    num_at_new_cur_min += 1;
+                }
+            } else {
+                num_aux_tokens += 1;
+            }
+        }
+
+        let mut new_aux: Option<AuxHashMap> = None;
+        if let Some(aux) = self.aux.take() {
+            for pair in aux.iter() {
+                let slot = get_low_26(pair) & (k - 1);
+                let old_actual_value = get_value(pair);
+                let new_shifted_value = old_actual_value - new_cur_min;
+                if new_shifted_value < AUX_TOKEN {
+                    // the former exception now fits in a nibble
+                    self.put_slot(slot, new_shifted_value);
+                    num_aux_tokens -= 1;
+                } else {
+                    new_aux
+                        .get_or_insert_with(|| {
+                            AuxHashMap::new(
+                                LG_AUX_ARR_INTS[self.lg_config_k as usize],
+                                self.lg_config_k,
+                            )
+                        })
+                        .must_add(slot, old_actual_value);
+                }
+            }
+        }
+        assert_eq!(
+            new_aux.as_ref().map_or(0, AuxHashMap::aux_count),
+            num_aux_tokens,
+            "inconsistent auxiliary map of HLL4 array"
+        );
+
+        self.aux = new_aux;
+        self.cur_min = new_cur_min;
+        self.num_at_cur_min = num_at_new_cur_min;
+    }
+
+    fn hip_and_kxq_incremental_update(&mut self, old_value: u8, new_value: u8) {
+        let k = f64::from(1u32 << self.lg_config_k);
+        // the HIP accumulator must be updated before kxq
+        self.hip_accum += k / (self.kxq0 + self.kxq1);
+        if old_value < 32 {
+            self.kxq0 -= inv_pow2(old_value);
+        } else {
+            self.kxq1 -= inv_pow2(old_value);
+        }
+        if new_value < 32 {
+            self.kxq0 += inv_pow2(new_value);
+        } else {
+            self.kxq1 += inv_pow2(new_value);
+        }
+    }
+
+    /// Returns a copy of the array with another target type. The HIP
+    /// accumulator and the out of order flag are carried over.
+    pub(super) fn copy_as(&self, tgt_type: HllType) -> Self {
+        if tgt_type == self.tgt_type {
+            return self.clone();
+        }
+
+        let mut array = Self::new(self.lg_config_k, tgt_type, self.start_full_size);
+        array.out_of_order = self.out_of_order;
+        if tgt_type == HllType::HLL4 {
+            let (cur_min, num_at_cur_min) = self.cur_min_and_num();
+            let lg_config_k = self.lg_config_k;
+            for (slot, value) in self.iter(false) {
+                if value >= cur_min + AUX_TOKEN {
+                    array.put_slot(slot, AUX_TOKEN);
+                    array
+                        .aux
+                        .get_or_insert_with(|| {
+                            AuxHashMap::new(LG_AUX_ARR_INTS[lg_config_k as usize], lg_config_k)
+                        })
+                        .must_add(slot, value);
+                } else {
+                    array.put_slot(slot, value - cur_min);
+                }
+            }
+            array.cur_min = cur_min;
+            array.num_at_cur_min = num_at_cur_min;
+            array.kxq0 = self.kxq0;
+            array.kxq1 = self.kxq1;
+        } else {
+            let mut num_zeros = 1 << self.lg_config_k;
+            for (slot, value) in self.iter(false) {
+                num_zeros -= 1;
+                array.update_slot_with_kxq(slot, value);
+            }
+            array.num_at_cur_min = num_zeros;
+        }
+        array.hip_accum = self.hip_accum;
+        array
+    }
+
+    fn cur_min_and_num(&self) -> (u8, u32) {
+        let mut cur_min = 64;
+        let mut num_at_cur_min = 0;
+        for (_, value) in self.iter(true) {
+            if value < cur_min {
+                cur_min = value;
+                num_at_cur_min = 1;
+            } else if value == cur_min {
+                num_at_cur_min += 1;
+            }
+        }
+        (cur_min, num_at_cur_min)
+    }
+
+    /// Returns a copy of an array into an HLL8 array for a union configured
+    /// with `tgt_lg_k`, folding larger arrays onto `2^tgt_lg_k` registers.
+    pub(super) fn copy_or_downsample(&self, tgt_lg_k: u8) -> Self {
+        if self.lg_config_k <= tgt_lg_k {
+            return self.copy_as(HllType::HLL8);
+        }
+        let mut array = Self::new(tgt_lg_k, HllType::HLL8, false);
+        array.merge_hll(self);
+        array.hip_accum = self.hip_accum;
+        array.out_of_order = self.out_of_order;
+        array
+    }
+
+    /// Merges the registers of `src`, whose `lg_config_k` is at least as
+    /// large, into this HLL8 array.
+    pub(super) fn merge_hll(&mut self, src: &HllArray) {
+        let mask = (1 << self.lg_config_k) - 1;
+        for (slot, value) in src.iter(false) {
+            let index = (slot & mask) as usize;
+            if value > self.bytes[index] {
+                self.bytes[index] = value;
+            }
+        }
+        self.rebuild_kxq_cur_min();
+    }
+
+    /// Recomputes kxq, `cur_min` and `num_at_cur_min` from the registers.
+    fn rebuild_kxq_cur_min(&mut self) {
+        let mut cur_min = 64;
+        let mut num_at_cur_min = 0;
+        let mut kxq0 = f64::from(1u32 << self.lg_config_k);
+        let mut kxq1 = 0.0;
+        for (_, value) in self.iter(true) {
+            if value > 0 {
+                if value < 32 {
+                    kxq0 += inv_pow2(value) - 1.0;
+                } else {
+                    kxq1 += inv_pow2(value) - 1.0;
+                }
+            }
+            if value < cur_min {
+                cur_min = value;
+                num_at_cur_min = 1;
+            } else if value == cur_min {
+                num_at_cur_min += 1;
+            }
+        }
+        self.kxq0 = kxq0;
+        self.kxq1 = kxq1;
+        self.cur_min = cur_min;
+        self.num_at_cur_min = num_at_cur_min;
+    }
+
+    pub(super) fn estimate(&self) -> f64 {
+        if self.out_of_order {
+            self.composite_estimate()
+        } else {
+            self.hip_accum
+        }
+    }
+
+    fn raw_estimate(&self) -> f64 {
+        let k = f64::from(1u32 << self.lg_config_k);
+        let correction_factor = match self.lg_config_k {
+            4 => 0.673,
+            5 => 0.697,
+            6 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / k),
+        };
+        correction_factor * k * k / (self.kxq0 + self.kxq1)
+    }
+
+    pub(super) fn composite_estimate(&self) -> f64 {
+        // without the empirical bias tables of the C++ library the raw
+        // estimate overshoots for small counts, so linear counting is kept
+        // for longer, see the module documentation
+        let raw = self.raw_estimate();
+        if raw > 2.5 * f64::from(1u32 << self.lg_config_k) || self.cur_min > 0 {
+            return raw;
+        }
+        // with a `cur_min` of 0, `num_at_cur_min` counts the unhit registers
+        let k = 1u32 << self.lg_config_k;
+        bitmap_estimate(k, k - self.num_at_cur_min)
+    }
+
+    fn num_non_zeros(&self) -> f64 {
+        let k = 1u32 << self.lg_config_k;
+        f64::from(if self.cur_min == 0 {
+            k - self.num_at_cur_min
+        } else {
+            k
+        })
+    }
+
+    pub(super) fn lower_bound(&self, num_std_dev: u8) -> f64 {
+        check_num_std_dev(num_std_dev);
+        let rel_err = hll_rel_error(false, self.out_of_order, self.lg_config_k, num_std_dev);
+        (self.estimate() / (1.0 + rel_err)).max(self.num_non_zeros())
+    }
+
+    pub(super) fn upper_bound(&self, num_std_dev: u8) -> f64 {
+        check_num_std_dev(num_std_dev);
+        let rel_err = hll_rel_error(true, self.out_of_order, self.lg_config_k, num_std_dev);
+        self.estimate() / (1.0 + rel_err)
+    }
+
+    pub(super) fn updatable_bytes(&self) -> usize {
+        let aux_bytes = match (self.tgt_type, &self.aux) {
+            (HllType::HLL4, Some(aux)) => aux.updatable_bytes(),
+            (HllType::HLL4, None) => 4 << LG_AUX_ARR_INTS[self.lg_config_k as usize],
+            _ => 0,
+        };
+        HLL_BYTE_ARR_START + self.bytes.len() + aux_bytes
+    }
+
+    pub(super) fn compact_bytes(&self) -> usize {
+        HLL_BYTE_ARR_START
+            + self.bytes.len()
+            + self.aux.as_ref().map_or(0, AuxHashMap::compact_bytes)
+    }
+
+    pub(super) fn serialize(&self, compact: bool, header_size_bytes: usize) -> Vec<u8> {
+        let size = if compact {
+            self.compact_bytes()
+        } else {
+            self.updatable_bytes()
+        };
+        let mut bytes = vec![0u8; header_size_bytes + size];
+        let image = &mut bytes[header_size_bytes..];
+
+        image[PREAMBLE_INTS_BYTE] = HLL_PREINTS;
+        image[SER_VER_BYTE] = SER_VER;
+        image[FAMILY_BYTE] = FAMILY_ID;
+        image[LG_K_BYTE] = self.lg_config_k;
+        image[LG_ARR_BYTE] = self.aux.as_ref().map_or(0, AuxHashMap::lg_aux_arr_ints);
+        image[FLAGS_BYTE] = flags_byte(
+            self.is_empty(),
+            compact,
+            self.out_of_order,
+            self.start_full_size,
+        );
+        image[HLL_CUR_MIN_BYTE] = self.cur_min;
+        image[MODE_BYTE] = mode_byte(CUR_MODE_HLL, self.tgt_type);
+        write_f64(image, HIP_ACCUM_DOUBLE, self.hip_accum);
+        write_f64(image, KXQ0_DOUBLE, self.kxq0);
+        write_f64(image, KXQ1_DOUBLE, self.kxq1);
+        write_u32(image, CUR_MIN_COUNT_INT, self.num_at_cur_min);
+        write_u32(
+            image,
+            AUX_COUNT_INT,
+            self.aux.as_ref().map_or(0, AuxHashMap::aux_count),
+        );
+
+        let arr_end = HLL_BYTE_ARR_START + self.bytes.len();
+        image[HLL_BYTE_ARR_START..arr_end].copy_from_slice(&self.bytes);
+        // updatable HLL4 images without exceptions keep a zeroed auxiliary map
+        if let Some(aux) = &self.aux {
+            aux.write(&mut image[arr_end..], compact);
+        }
+        bytes
+    }
+
+    /// Appends the summary lines specific to HLL mode.
+    pub(super) fn summary(&self, out: &mut String) {
+        use std::fmt::Write;
+
+        let _ = writeln!(out, "  CurMin         : {}", self.cur_min);
+        let _ = writeln!(out, "  NumAtCurMin    : {}", self.num_at_cur_min);
+        let _ = writeln!(out, "  HipAccum       : {}", self.hip_accum);
+        let _ = writeln!(out, "  KxQ0           : {}", self.kxq0);
+        let _ = writeln!(out, "  KxQ1           : {}", self.kxq1);
+        if self.tgt_type == HllType::HLL4 {
+            let _ = writeln!(out, "  Aux table?     : {}", self.aux.is_some());
+        }
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The auxiliary map of HLL4 arrays, holding the registers whose value does
+//! not fit in a nibble as `(value, slot)` pairs.
+
+use crate::error::Error;
+use crate::format::read_u32;
+
+use super::super::format::CUR_MODE_HLL;
+use super::{
+    check_len, compute_lg_arr_ints, get_low_26, get_value, pair, write_u32, EMPTY, RESIZE_DENOM,
+    RESIZE_NUMER,
+};
+
+#[derive(Clone)]
+pub(super) struct AuxHashMap {
+    lg_aux_arr_ints: u8,
+    lg_config_k: u8,
+    aux_count: u32,
+    entries: Vec<u32>,
+}
+
+/// Looks `slot` up with open addressing, returning the index of its pair or
+/// the index of the empty entry where it belongs.
+fn find(entries: &[u32], lg_aux_arr_ints: u8, slot: u32) -> Result<usize, usize> {
+    let mask = (1 << lg_aux_arr_ints) - 1;
+    let mut probe = slot & mask;
+    let loop_index = probe;
+    loop {
+        let entry = entries[probe as usize];
+        if entry == EMPTY {
+            return Err(probe as usize);
+        }
+        if get_low_26(entry) == slot {
+            return Ok(probe as usize);
+        }
+        let stride = (slot >> lg_aux_arr_ints) | 1;
+        probe = (probe + stride) & mask;
+        if probe == loop_index {
+            panic!("auxiliary map is full");
+        }
+    }
+}
+
+impl AuxHashMap {
+    pub(super) fn new(lg_aux_arr_ints: u8, lg_config_k: u8) -> Self {
+        Self {
+            lg_aux_arr_ints,
+            lg_config_k,
+            aux_count: 0,
+            entries: vec![EMPTY; 1 << lg_aux_arr_ints],
+        }
+    }
+
+    /// Rebuilds the map out of the pairs stored in an image, which are packed
+    /// if `compact` and laid out as the entries of the map otherwise. The
+    /// image starts at `offset` of `bytes`.
+    pub(super) fn deserialize(
+        bytes: &[u8],
+        offset: usize,
+        lg_config_k: u8,
+        aux_count: u32,
+        lg_aux_arr_ints: u8,
+        compact: bool,
+    ) -> Result<Self, Error> {
+        // the map cannot hold more pairs than there are registers
+        if aux_count > 1 << lg_config_k {
+            return Err(Error::InvalidImage("HLL auxiliary map count out of range"));
+        }
+        let (lg_aux_arr_ints, items) = if compact {
+            (
+                compute_lg_arr_ints(CUR_MODE_HLL, aux_count, lg_config_k),
+                aux_count as usize,
+            )
+        } else if lg_aux_arr_ints <= lg_config_k + 1 {
+            (lg_aux_arr_ints, 1 << lg_aux_arr_ints)
+        } else {
+            return Err(Error::InvalidImage("HLL auxiliary map size out of range"));
+        };
+        check_len(bytes, offset + items * 4)?;
+
+        let mut map = Self::new(lg_aux_arr_ints, lg_config_k);
+        let mask = (1 << lg_config_k) - 1;
+        for index in 0..items {
+            let pair = read_u32(bytes, offset + index * 4);
+            if pair == EMPTY {
+                continue;
+            }
+            let slot = get_low_26(pair) & mask;
+            if find(&map.entries, map.lg_aux_arr_ints, slot).is_ok() {
+                return Err(Error::InvalidImage("slot twice in HLL auxiliary map"));
+            }
+            map.must_add(slot, get_value(pair));
+        }
+        if map.aux_count != aux_count {
+            return Err(Error::InvalidImage(
+                "HLL auxiliary map count does not match its pairs",
+            ));
+        }
+        Ok(map)
+    }
+
+    #[inline]
+    pub(super) fn lg_aux_arr_ints(&self) -> u8 {
+        self.lg_aux_arr_ints
+    }
+
+    #[inline]
+    pub(super) fn aux_count(&self) -> u32 {
+        self.aux_count
+    }
+
+    pub(super) fn updatable_bytes(&self) -> usize {
+        4 << self.lg_aux_arr_ints
+    }
+
+    pub(super) fn compact_bytes(&self) -> usize {
+        4 * self.aux_count as usize
+    }
+
+    /// Returns the non-empty pairs in the order they are stored.
+    pub(super) fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.entries.iter().copied().filter(|pair| *pair != EMPTY)
+    }
+
+    /// Returns all the entries, including the empty ones.
+    pub(super) fn entries(&self) -> &[u32] {
+        &self.entries
+    }
+
+    pub(super) fn must_add(&mut self, slot: u32, value: u8) {
+        match find(&self.entries, self.lg_aux_arr_ints, slot) {
+            Ok(_) => panic!("slot {slot} is already in the auxiliary map"),
+            Err(index) => {
+                self.entries[index] = pair(slot, value);
+                self.aux_count += 1;
+                self.check_grow();
+            }
+        }
+    }
+
+    pub(super) fn must_replace(&mut self, slot: u32, value: u8) {
+        match find(&self.entries, self.lg_aux_arr_ints, slot) {
+            Ok(index) => self.entries[index] = pair(slot, value),
+            Err(_) => panic!("slot {slot} is not in the auxiliary map"),
+        }
+    }
+
+    pub(super) fn must_find_value_for(&self, slot: u32) -> u8 {
+        match find(&self.entries, self.lg_aux_arr_ints, slot) {
+            Ok(index) => get_value(self.entries[index]),
+            Err(_) => panic!("slot {slot} is not in the auxiliary map"),
+        }
+    }
+
+    /// Writes the pairs to `bytes`, packed if `compact`.
+    pub(super) fn write(&self, bytes: &mut [u8], compact: bool) {
+        if compact {
+            for (index, pair) in self.iter().enumerate() {
+                write_u32(bytes, index * 4, pair);
+            }
+        } else {
+            for (index, entry) in self.entries.iter().enumerate() {
+                write_u32(bytes, index * 4, *entry);
+            }
+        }
+    }
+
+    fn check_grow(&mut self) {
+        if RESIZE_DENOM * self.aux_count > RESIZE_NUMER * (1 << self.lg_aux_arr_ints) {
+            self.grow();
+        }
+    }
+
+    fn grow(&mut self) {
+        let lg_aux_arr_ints = self.lg_aux_arr_ints + 1;
+        let mask = (1 << self.lg_config_k) - 1;
+        let mut entries = vec![EMPTY; 1 << lg_aux_arr_ints];
+        for pair in self.iter() {
+            let slot = get_low_26(pair) & mask;
+            match find(&entries, lg_aux_arr_ints, slot) {
+                Err(index) => entries[index] = pair,
+                Ok(_) => panic!("slot {slot} is twice in the auxiliary map"),
+            }
+        }
+        self.lg_aux_arr_ints = lg_aux_arr_ints;
+        self.entries = entries;
+    }
+}
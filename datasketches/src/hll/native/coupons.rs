@@ -0,0 +1,313 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The coupon list and coupon hash set which hold the coupons of a sketch
+//! while it warms up.
+
+use crate::error::Error;
+use crate::format::read_u32;
+
+use super::super::{format::*, HllType};
+use super::estimators::{coupon_estimate, COUPON_RSE};
+use super::{
+    check_len, check_num_std_dev, compute_lg_arr_ints, flags_byte, write_u32, EMPTY,
+    LG_INIT_LIST_SIZE, LG_INIT_SET_SIZE, RESIZE_DENOM, RESIZE_NUMER,
+};
+
+#[derive(Clone)]
+pub(super) struct CouponList {
+    pub(super) lg_config_k: u8,
+    pub(super) tgt_type: HllType,
+    /// Either [CUR_MODE_LIST] or [CUR_MODE_SET].
+    pub(super) mode: u8,
+    pub(super) out_of_order: bool,
+    lg_coupon_arr_ints: u8,
+    coupon_count: u32,
+    coupons: Vec<u32>,
+}
+
+/// Looks `coupon` up in a hash set with open addressing, returning its index
+/// or the index of the empty entry where it belongs.
+fn find(coupons: &[u32], lg_coupon_arr_ints: u8, coupon: u32) -> Result<usize, usize> {
+    let mask = (1 << lg_coupon_arr_ints) - 1;
+    let mut probe = coupon & mask;
+    let loop_index = probe;
+    loop {
+        let entry = coupons[probe as usize];
+        if entry == EMPTY {
+            return Err(probe as usize);
+        }
+        if entry == coupon {
+            return Ok(probe as usize);
+        }
+        let stride = ((coupon & KEY_MASK_26) >> lg_coupon_arr_ints) | 1;
+        probe = (probe + stride) & mask;
+        if probe == loop_index {
+            panic!("coupon hash set is full");
+        }
+    }
+}
+
+impl CouponList {
+    pub(super) fn new(lg_config_k: u8, tgt_type: HllType, mode: u8) -> Self {
+        let lg_coupon_arr_ints = if mode == CUR_MODE_LIST {
+            LG_INIT_LIST_SIZE
+        } else {
+            LG_INIT_SET_SIZE
+        };
+        Self {
+            lg_config_k,
+            tgt_type,
+            mode,
+            // the order of the coupons is lost once they are hashed
+            out_of_order: mode == CUR_MODE_SET,
+            lg_coupon_arr_ints,
+            coupon_count: 0,
+            coupons: vec![EMPTY; 1 << lg_coupon_arr_ints],
+        }
+    }
+
+    /// Reads a sketch out of a list or set image, whose preamble is valid.
+    pub(super) fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let lg_config_k = bytes[LG_K_BYTE];
+        let mode = cur_mode(bytes);
+        let flags = bytes[FLAGS_BYTE];
+        let compact = flags & COMPACT_FLAG_MASK != 0;
+        let empty = flags & EMPTY_FLAG_MASK != 0;
+
+        let mut sketch = Self::new(lg_config_k, tgt_hll_type(bytes), mode);
+        sketch.out_of_order = flags & OUT_OF_ORDER_FLAG_MASK != 0;
+        if mode == CUR_MODE_LIST {
+            let coupon_count = u32::from(bytes[LIST_COUNT_BYTE]);
+            let count = if empty { 0 } else { coupon_count as usize };
+            // a full list is promoted before it can be serialized
+            if count >= sketch.coupons.len() {
+                return Err(Error::InvalidImage("HLL coupon list count out of range"));
+            }
+            check_len(bytes, LIST_INT_ARR_START + count * 4)?;
+            for index in 0..count {
+                sketch.coupons[index] = read_u32(bytes, LIST_INT_ARR_START + index * 4);
+            }
+            sketch.coupon_count = coupon_count;
+            return Ok(sketch);
+        }
+
+        check_len(bytes, HASH_SET_INT_ARR_START)?;
+        let coupon_count = read_u32(bytes, HASH_SET_COUNT_INT);
+        let max_lg_coupon_arr_ints = (lg_config_k - 3).max(LG_INIT_SET_SIZE);
+        if coupon_count > 1 << max_lg_coupon_arr_ints {
+            return Err(Error::InvalidImage("HLL coupon set count out of range"));
+        }
+        let mut lg_coupon_arr_ints = bytes[LG_ARR_BYTE];
+        if lg_coupon_arr_ints < LG_INIT_SET_SIZE {
+            lg_coupon_arr_ints = compute_lg_arr_ints(CUR_MODE_SET, coupon_count, lg_config_k);
+        }
+        if lg_coupon_arr_ints > max_lg_coupon_arr_ints {
+            return Err(Error::InvalidImage("HLL coupon set size out of range"));
+        }
+        if compact {
+            check_len(bytes, HASH_SET_INT_ARR_START + coupon_count as usize * 4)?;
+            for index in 0..coupon_count as usize {
+                let coupon = read_u32(bytes, HASH_SET_INT_ARR_START + index * 4);
+                if coupon == EMPTY {
+                    return Err(Error::InvalidImage("empty coupon in HLL coupon set"));
+                }
+                // a full set is promoted before it can be serialized
+                if sketch.update(coupon) {
+                    return Err(Error::InvalidImage("HLL coupon set count out of range"));
+                }
+            }
+        } else {
+            let len = 1 << lg_coupon_arr_ints;
+            check_len(bytes, HASH_SET_INT_ARR_START + len * 4)?;
+            sketch.coupons = (0..len)
+                .map(|index| read_u32(bytes, HASH_SET_INT_ARR_START + index * 4))
+                .collect();
+            sketch.lg_coupon_arr_ints = lg_coupon_arr_ints;
+            sketch.coupon_count = sketch.iter().count() as u32;
+            // the hash set keeps empty entries for its probes to end
+            if RESIZE_DENOM * sketch.coupon_count > RESIZE_NUMER * len as u32 {
+                return Err(Error::InvalidImage("HLL coupon set too full for its size"));
+            }
+        }
+        if sketch.coupon_count != coupon_count {
+            return Err(Error::InvalidImage(
+                "HLL coupon set count does not match its coupons",
+            ));
+        }
+        Ok(sketch)
+    }
+
+    /// Returns a copy of the sketch with another target type.
+    pub(super) fn copy_as(&self, tgt_type: HllType) -> Self {
+        Self {
+            tgt_type,
+            ..self.clone()
+        }
+    }
+
+    #[inline]
+    pub(super) fn coupon_count(&self) -> u32 {
+        self.coupon_count
+    }
+
+    #[inline]
+    pub(super) fn is_empty(&self) -> bool {
+        self.coupon_count == 0
+    }
+
+    /// Returns the non-empty coupons in the order they are stored.
+    pub(super) fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.coupons
+            .iter()
+            .copied()
+            .filter(|coupon| *coupon != EMPTY)
+    }
+
+    /// Returns all the entries, including the empty ones.
+    pub(super) fn entries(&self) -> &[u32] {
+        &self.coupons
+    }
+
+    /// Adds `coupon`, returning whether the sketch is full and must be
+    /// promoted to the next mode.
+    pub(super) fn update(&mut self, coupon: u32) -> bool {
+        if self.mode == CUR_MODE_LIST {
+            for index in 0..self.coupons.len() {
+                let entry = self.coupons[index];
+                if entry == EMPTY {
+                    self.coupons[index] = coupon;
+                    self.coupon_count += 1;
+                    return self.coupon_count as usize >= self.coupons.len();
+                }
+                if entry == coupon {
+                    return false;
+                }
+            }
+            panic!("coupon list is full");
+        }
+
+        match find(&self.coupons, self.lg_coupon_arr_ints, coupon) {
+            Ok(_) => false,
+            Err(index) => {
+                self.coupons[index] = coupon;
+                self.coupon_count += 1;
+                self.check_grow_or_promote()
+            }
+        }
+    }
+
+    /// Promotes a full list to a set, for sketches large enough to use one.
+    pub(super) fn to_set(&self) -> Option<Self> {
+        if self.mode != CUR_MODE_LIST || self.lg_config_k < 8 {
+            return None;
+        }
+        let mut set = Self::new(self.lg_config_k, self.tgt_type, CUR_MODE_SET);
+        for coupon in self.iter() {
+            set.update(coupon);
+        }
+        Some(set)
+    }
+
+    fn check_grow_or_promote(&mut self) -> bool {
+        if RESIZE_DENOM * self.coupon_count <= RESIZE_NUMER * (1 << self.lg_coupon_arr_ints) {
+            return false;
+        }
+        if self.lg_coupon_arr_ints == self.lg_config_k - 3 {
+            return true;
+        }
+
+        let lg_coupon_arr_ints = self.lg_coupon_arr_ints + 1;
+        let mut coupons = vec![EMPTY; 1 << lg_coupon_arr_ints];
+        for coupon in self.iter() {
+            match find(&coupons, lg_coupon_arr_ints, coupon) {
+                Err(index) => coupons[index] = coupon,
+                Ok(_) => panic!("coupon {coupon} is twice in the hash set"),
+            }
+        }
+        self.lg_coupon_arr_ints = lg_coupon_arr_ints;
+        self.coupons = coupons;
+        false
+    }
+
+    pub(super) fn estimate(&self) -> f64 {
+        let count = f64::from(self.coupon_count);
+        coupon_estimate(self.coupon_count).max(count)
+    }
+
+    pub(super) fn lower_bound(&self, num_std_dev: u8) -> f64 {
+        check_num_std_dev(num_std_dev);
+        let count = f64::from(self.coupon_count);
+        let estimate = coupon_estimate(self.coupon_count);
+        (estimate / (1.0 + f64::from(num_std_dev) * COUPON_RSE)).max(count)
+    }
+
+    pub(super) fn upper_bound(&self, num_std_dev: u8) -> f64 {
+        check_num_std_dev(num_std_dev);
+        let count = f64::from(self.coupon_count);
+        let estimate = coupon_estimate(self.coupon_count);
+        (estimate / (1.0 - f64::from(num_std_dev) * COUPON_RSE)).max(count)
+    }
+
+    fn mem_data_start(&self) -> usize {
+        if self.mode == CUR_MODE_LIST {
+            LIST_INT_ARR_START
+        } else {
+            HASH_SET_INT_ARR_START
+        }
+    }
+
+    pub(super) fn updatable_bytes(&self) -> usize {
+        self.mem_data_start() + (4 << self.lg_coupon_arr_ints)
+    }
+
+    pub(super) fn compact_bytes(&self) -> usize {
+        self.mem_data_start() + 4 * self.coupon_count as usize
+    }
+
+    pub(super) fn serialize(&self, compact: bool, header_size_bytes: usize) -> Vec<u8> {
+        let size = if compact {
+            self.compact_bytes()
+        } else {
+            self.updatable_bytes()
+        };
+        let mut bytes = vec![0u8; header_size_bytes + size];
+        let image = &mut bytes[header_size_bytes..];
+
+        let list = self.mode == CUR_MODE_LIST;
+        image[PREAMBLE_INTS_BYTE] = if list { LIST_PREINTS } else { HASH_SET_PREINTS };
+        image[SER_VER_BYTE] = SER_VER;
+        image[FAMILY_BYTE] = FAMILY_ID;
+        image[LG_K_BYTE] = self.lg_config_k;
+        image[LG_ARR_BYTE] = self.lg_coupon_arr_ints;
+        image[FLAGS_BYTE] = flags_byte(self.is_empty(), compact, self.out_of_order, false);
+        image[LIST_COUNT_BYTE] = if list { self.coupon_count as u8 } else { 0 };
+        image[MODE_BYTE] = mode_byte(self.mode, self.tgt_type);
+        if !list {
+            write_u32(image, HASH_SET_COUNT_INT, self.coupon_count);
+        }
+
+        let start = self.mem_data_start();
+        if compact {
+            for (index, coupon) in self.iter().enumerate() {
+                write_u32(image, start + index * 4, coupon);
+            }
+        } else {
+            for (index, coupon) in self.coupons.iter().enumerate() {
+                write_u32(image, start + index * 4, *coupon);
+            }
+        }
+        bytes
+    }
+}
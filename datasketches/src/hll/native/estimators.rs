@@ -0,0 +1,106 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The estimators shared by the modes of the sketch.
+
+use super::super::format::KEY_BITS_26;
+use super::{check_lg_k, check_num_std_dev, inv_pow2};
+
+/// The relative standard error of the coupon estimator of the list and set
+/// modes.
+pub(super) const COUPON_RSE: f64 = 0.409 / (1 << 13) as f64;
+
+/// `sqrt(ln(2))`, the relative standard error of the HIP estimator times `sqrt(K)`.
+const HLL_HIP_RSE_FACTOR: f64 = 0.832_554_611_157_697_7;
+/// `sqrt(3 * ln(2) - 1)`, the relative standard error of the composite
+/// estimator times `sqrt(K)`.
+const HLL_NON_HIP_RSE_FACTOR: f64 = 1.038_961_761_413_611_6;
+
+const EULER_MASCHERONI: f64 = 0.577_215_664_901_532_9;
+const NUM_EXACT_HARMONIC_NUMBERS: u32 = 25;
+
+/// Returns the relative error of the bounds of a sketch configured with
+/// `lg_config_k`, negative for the upper bound like in the C++ library.
+pub(crate) fn hll_rel_error(upper: bool, unioned: bool, lg_config_k: u8, num_std_dev: u8) -> f64 {
+    check_lg_k(lg_config_k);
+    check_num_std_dev(num_std_dev);
+    let rse_factor = if unioned {
+        HLL_NON_HIP_RSE_FACTOR
+    } else {
+        HLL_HIP_RSE_FACTOR
+    };
+    let rel_err = f64::from(num_std_dev) * rse_factor / f64::from(1u32 << lg_config_k).sqrt();
+    if upper {
+        -rel_err
+    } else {
+        rel_err
+    }
+}
+
+/// Returns the expected number of distinct coupons out of `n` distinct items,
+/// and its derivative. Coupons collide when two items share both the 26 bits
+/// of their address and their value, which is geometrically distributed.
+fn expected_coupons(n: f64) -> (f64, f64) {
+    let addresses = f64::from(1u32 << KEY_BITS_26);
+    let (mut expected, mut derivative) = (0.0, 0.0);
+    for value in 1..=63u8 {
+        // values count the leading zeros of a 64-bit hash plus one, capped at 63
+        let ln_miss = (-inv_pow2(value.min(62)) / addresses).ln_1p();
+        expected -= addresses * (n * ln_miss).exp_m1();
+        derivative -= addresses * ln_miss * (n * ln_miss).exp();
+    }
+    (expected, derivative)
+}
+
+/// Returns the number of distinct items expected to produce `coupon_count`
+/// distinct coupons.
+pub(super) fn coupon_estimate(coupon_count: u32) -> f64 {
+    let target = f64::from(coupon_count);
+    let mut n = target;
+    // expected_coupons is concave, so Newton's method climbs monotonically
+    for _ in 0..16 {
+        let (expected, derivative) = expected_coupons(n);
+        let step = (target - expected) / derivative;
+        n += step;
+        if step <= n * f64::EPSILON {
+            break;
+        }
+    }
+    n
+}
+
+fn harmonic_number(x: u32) -> f64 {
+    if x < NUM_EXACT_HARMONIC_NUMBERS {
+        return (1..=x).map(|i| 1.0 / f64::from(i)).sum();
+    }
+    let x = f64::from(x);
+    let inv_sq = 1.0 / (x * x);
+    let mut sum = x.ln() + EULER_MASCHERONI + 1.0 / (2.0 * x);
+    let mut pow = inv_sq;
+    sum -= pow / 12.0;
+    pow *= inv_sq;
+    sum += pow / 120.0;
+    pow *= inv_sq;
+    sum -= pow / 252.0;
+    pow *= inv_sq;
+    sum += pow / 240.0;
+    sum
+}
+
+/// Returns the linear counting estimate of a bitmap of `bit_vector_length`
+/// bits of which `num_bits_set` are set.
+pub(super) fn bitmap_estimate(bit_vector_length: u32, num_bits_set: u32) -> f64 {
+    f64::from(bit_vector_length)
+        * (harmonic_number(bit_vector_length) - harmonic_number(bit_vector_length - num_bits_set))
+}
@@ -0,0 +1,581 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The sketch, switching from a coupon list to a coupon hash set and to an
+//! array of registers as it fills up.
+
+use std::fmt::Write;
+
+use crate::config::Family;
+use crate::error::Error;
+use crate::hash::{murmur3_x64_128, DEFAULT_SEED};
+use crate::header::inspect_family;
+use crate::item::canonical_f64_bits;
+
+use super::super::{format::*, HllType};
+use super::array::HllArray;
+use super::coupons::CouponList;
+use super::{check_lg_k, get_low_26, get_value, target_hll_type, UniquePtr};
+
+#[derive(Clone)]
+pub(super) enum HllImpl {
+    Coupons(CouponList),
+    Array(HllArray),
+}
+
+impl HllImpl {
+    pub(super) fn lg_config_k(&self) -> u8 {
+        match self {
+            Self::Coupons(coupons) => coupons.lg_config_k,
+            Self::Array(array) => array.lg_config_k,
+        }
+    }
+
+    pub(super) fn tgt_type(&self) -> HllType {
+        match self {
+            Self::Coupons(coupons) => coupons.tgt_type,
+            Self::Array(array) => array.tgt_type,
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        match self {
+            Self::Coupons(coupons) => coupons.is_empty(),
+            Self::Array(array) => array.is_empty(),
+        }
+    }
+
+    fn out_of_order(&self) -> bool {
+        match self {
+            Self::Coupons(coupons) => coupons.out_of_order,
+            Self::Array(array) => array.out_of_order,
+        }
+    }
+
+    pub(super) fn coupon_update(&mut self, coupon: u32) {
+        match self {
+            Self::Coupons(coupons) => {
+                if coupons.update(coupon) {
+                    *self = match coupons.to_set() {
+                        Some(set) => Self::Coupons(set),
+                        None => Self::Array(HllArray::from_coupons(coupons)),
+                    };
+                }
+            }
+            Self::Array(array) => array.coupon_update(coupon),
+        }
+    }
+
+    pub(super) fn copy_as(&self, tgt_type: HllType) -> Self {
+        match self {
+            Self::Coupons(coupons) => Self::Coupons(coupons.copy_as(tgt_type)),
+            Self::Array(array) => Self::Array(array.copy_as(tgt_type)),
+        }
+    }
+
+    pub(super) fn estimate(&self) -> f64 {
+        match self {
+            Self::Coupons(coupons) => coupons.estimate(),
+            Self::Array(array) => array.estimate(),
+        }
+    }
+
+    pub(super) fn composite_estimate(&self) -> f64 {
+        match self {
+            Self::Coupons(coupons) => coupons.estimate(),
+            Self::Array(array) => array.composite_estimate(),
+        }
+    }
+
+    pub(super) fn lower_bound(&self, num_std_dev: u8) -> f64 {
+        match self {
+            Self::Coupons(coupons) => coupons.lower_bound(num_std_dev),
+            Self::Array(array) => array.lower_bound(num_std_dev),
+        }
+    }
+
+    pub(super) fn upper_bound(&self, num_std_dev: u8) -> f64 {
+        match self {
+            Self::Coupons(coupons) => coupons.upper_bound(num_std_dev),
+            Self::Array(array) => array.upper_bound(num_std_dev),
+        }
+    }
+
+    fn serialize(&self, compact: bool, header_size_bytes: usize) -> Vec<u8> {
+        match self {
+            Self::Coupons(coupons) => coupons.serialize(compact, header_size_bytes),
+            Self::Array(array) => array.serialize(compact, header_size_bytes),
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone)]
+pub(crate) struct hll_sketch(pub(super) HllImpl);
+
+pub(crate) fn hll_sketch_new(
+    lg_config_k: u8,
+    tgt_type: target_hll_type,
+    start_full_size: bool,
+) -> UniquePtr<hll_sketch> {
+    check_lg_k(lg_config_k);
+    UniquePtr::new(hll_sketch::new(
+        lg_config_k,
+        tgt_type.into(),
+        start_full_size,
+    ))
+}
+
+pub(crate) fn hll_sketch_copy(sketch: &hll_sketch) -> UniquePtr<hll_sketch> {
+    UniquePtr::new(sketch.clone())
+}
+
+pub(crate) fn hll_sketch_copy_with_target(
+    sketch: &hll_sketch,
+    tgt_type: target_hll_type,
+) -> UniquePtr<hll_sketch> {
+    UniquePtr::new(hll_sketch(sketch.0.copy_as(tgt_type.into())))
+}
+
+/// Reconstructs a sketch, reporting invalid images as errors like the C++
+/// backend does.
+pub(crate) fn hll_sketch_deserialize(bytes: &[u8]) -> Result<UniquePtr<hll_sketch>, Error> {
+    // validates the family, version, mode, preamble size and lg_config_k
+    inspect_family(bytes, Family::Hll)?;
+    let sketch = if cur_mode(bytes) == CUR_MODE_HLL {
+        HllImpl::Array(HllArray::deserialize(bytes)?)
+    } else {
+        HllImpl::Coupons(CouponList::deserialize(bytes)?)
+    };
+    Ok(UniquePtr::new(hll_sketch(sketch)))
+}
+
+pub(crate) fn hll_sketch_serialize_compact(sketch: &hll_sketch, header_size_bytes: u32) -> Vec<u8> {
+    sketch.0.serialize(true, header_size_bytes as usize)
+}
+
+pub(crate) fn hll_sketch_serialize_updatable(sketch: &hll_sketch) -> Vec<u8> {
+    sketch.0.serialize(false, 0)
+}
+
+/// Formats the sketch like the C++ `hll_sketch::to_string`.
+pub(crate) fn hll_sketch_to_string(
+    sketch: &hll_sketch,
+    summary: bool,
+    detail: bool,
+    aux_detail: bool,
+    all: bool,
+) -> String {
+    let mut out = String::new();
+    let sketch = &sketch.0;
+    let lg_config_k = sketch.lg_config_k();
+    let mask = (1u32 << lg_config_k) - 1;
+
+    if summary {
+        let tgt_type = match sketch.tgt_type() {
+            HllType::HLL4 => "HLL_4",
+            HllType::HLL6 => "HLL_6",
+            HllType::HLL8 => "HLL_8",
+        };
+        let mode = match sketch {
+            HllImpl::Coupons(coupons) if coupons.mode == CUR_MODE_LIST => "LIST",
+            HllImpl::Coupons(_) => "SET",
+            HllImpl::Array(_) => "HLL",
+        };
+        let _ = writeln!(out, "### HLL sketch summary:");
+        let _ = writeln!(out, "  Log Config K   : {lg_config_k}");
+        let _ = writeln!(out, "  Hll Target     : {tgt_type}");
+        let _ = writeln!(out, "  Current Mode   : {mode}");
+        let _ = writeln!(out, "  LB             : {}", sketch.lower_bound(1));
+        let _ = writeln!(out, "  Estimate       : {}", sketch.estimate());
+        let _ = writeln!(out, "  UB             : {}", sketch.upper_bound(1));
+        let _ = writeln!(out, "  OutOfOrder flag: {}", sketch.out_of_order());
+        match sketch {
+            HllImpl::Coupons(coupons) => {
+                let _ = writeln!(out, "  Coupon count   : {}", coupons.coupon_count());
+            }
+            HllImpl::Array(array) => array.summary(&mut out),
+        }
+        let _ = writeln!(out, "### End HLL sketch summary");
+    }
+
+    if detail {
+        let _ = writeln!(out, "### HLL sketch data detail:");
+        match sketch {
+            HllImpl::Coupons(coupons) => {
+                let _ = writeln!(
+                    out,
+                    "{:<10}{:<10}{:<10}{:<6}",
+                    "Index", "Key", "Slot", "Value"
+                );
+                for (index, coupon) in coupons
+                    .entries()
+                    .iter()
+                    .filter(|coupon| all || **coupon != 0)
+                    .enumerate()
+                {
+                    let key = get_low_26(*coupon);
+                    let value = get_value(*coupon);
+                    let _ = writeln!(out, "{index:<10}{key:<10}{:<10}{value:<6}", key & mask);
+                }
+            }
+            HllImpl::Array(array) => {
+                let _ = writeln!(out, "{:<10}{:<6}", "Slot", "Value");
+                for (slot, value) in array.iter(all) {
+                    let _ = writeln!(out, "{slot:<10}{value:<6}");
+                }
+            }
+        }
+        let _ = writeln!(out, "### End HLL sketch data detail");
+    }
+
+    if aux_detail {
+        if let Some(aux) = match sketch {
+            HllImpl::Array(array) => array.aux(),
+            HllImpl::Coupons(_) => None,
+        } {
+            let _ = writeln!(out, "### HLL sketch aux detail:");
+            let _ = writeln!(
+                out,
+                "{:<10}{:<10}{:<10}{:<6}",
+                "Index", "Key", "Slot", "Value"
+            );
+            for (index, pair) in aux
+                .entries()
+                .iter()
+                .filter(|pair| all || **pair != 0)
+                .enumerate()
+            {
+                let key = get_low_26(*pair);
+                let value = get_value(*pair);
+                let _ = writeln!(out, "{index:<10}{key:<10}{:<10}{value:<6}", key & mask);
+            }
+            let _ = writeln!(out, "### End HLL sketch aux detail");
+        }
+    }
+    out
+}
+
+pub(crate) fn hll_sketch_update_bytes(sketch: &mut hll_sketch, bytes: &[u8]) {
+    // empty slices are ignored like in the Java library and in cpc_sketch
+    if !bytes.is_empty() {
+        sketch.update_hashed(bytes);
+    }
+}
+
+pub(crate) fn hll_sketch_update_u64_slice(sketch: &mut hll_sketch, data: &[u64]) {
+    for datum in data {
+        sketch.update_u64(*datum);
+    }
+}
+
+pub(crate) fn hll_sketch_update_i64_slice(sketch: &mut hll_sketch, data: &[i64]) {
+    for datum in data {
+        sketch.update_i64(*datum);
+    }
+}
+
+pub(crate) fn hll_sketch_update_f64_slice(sketch: &mut hll_sketch, data: &[f64]) {
+    for datum in data {
+        sketch.update_f64(*datum);
+    }
+}
+
+/// Updates the sketch with the strings packed back to back in `data`, the
+/// i-th one spanning `offsets[i]..offsets[i + 1]`.
+pub(crate) fn hll_sketch_update_strings(sketch: &mut hll_sketch, data: &[u8], offsets: &[usize]) {
+    for bounds in offsets.windows(2) {
+        hll_sketch_update_bytes(sketch, &data[bounds[0]..bounds[1]]);
+    }
+}
+
+impl hll_sketch {
+    pub(super) fn new(lg_config_k: u8, tgt_type: HllType, start_full_size: bool) -> Self {
+        Self(if start_full_size {
+            HllImpl::Array(HllArray::new(lg_config_k, tgt_type, true))
+        } else {
+            HllImpl::Coupons(CouponList::new(lg_config_k, tgt_type, CUR_MODE_LIST))
+        })
+    }
+
+    /// Hashes the bytes of an item and presents its coupon to the sketch.
+    fn update_hashed(&mut self, bytes: &[u8]) {
+        let (hash0, hash1) = murmur3_x64_128(bytes, DEFAULT_SEED);
+        let value = hash1.leading_zeros().min(62) + 1;
+        self.0
+            .coupon_update((value << KEY_BITS_26) | (hash0 as u32 & KEY_MASK_26));
+    }
+
+    pub(crate) fn reset(&mut self) {
+        let start_full_size = matches!(&self.0, HllImpl::Array(array) if array.start_full_size);
+        *self = Self::new(self.get_lg_config_k(), self.0.tgt_type(), start_full_size);
+    }
+
+    pub(crate) fn update_u64(&mut self, datum: u64) {
+        self.update_hashed(&datum.to_le_bytes());
+    }
+
+    // narrower integers are sign extended to 64 bits like in the C++ library
+    pub(crate) fn update_u32(&mut self, datum: u32) {
+        self.update_i64(i64::from(datum as i32));
+    }
+
+    pub(crate) fn update_u16(&mut self, datum: u16) {
+        self.update_i64(i64::from(datum as i16));
+    }
+
+    pub(crate) fn update_u8(&mut self, datum: u8) {
+        self.update_i64(i64::from(datum as i8));
+    }
+
+    pub(crate) fn update_i64(&mut self, datum: i64) {
+        self.update_hashed(&datum.to_le_bytes());
+    }
+
+    pub(crate) fn update_i32(&mut self, datum: i32) {
+        self.update_i64(i64::from(datum));
+    }
+
+    pub(crate) fn update_i16(&mut self, datum: i16) {
+        self.update_i64(i64::from(datum));
+    }
+
+    pub(crate) fn update_i8(&mut self, datum: i8) {
+        self.update_i64(i64::from(datum));
+    }
+
+    pub(crate) fn update_f64(&mut self, datum: f64) {
+        self.update_hashed(&canonical_f64_bits(datum).to_le_bytes());
+    }
+
+    pub(crate) fn update_f32(&mut self, datum: f32) {
+        self.update_f64(f64::from(datum));
+    }
+
+    pub(crate) fn get_estimate(&self) -> f64 {
+        self.0.estimate()
+    }
+
+    pub(crate) fn get_composite_estimate(&self) -> f64 {
+        self.0.composite_estimate()
+    }
+
+    pub(crate) fn get_lower_bound(&self, num_std_dev: u8) -> f64 {
+        self.0.lower_bound(num_std_dev)
+    }
+
+    pub(crate) fn get_upper_bound(&self, num_std_dev: u8) -> f64 {
+        self.0.upper_bound(num_std_dev)
+    }
+
+    pub(crate) fn get_lg_config_k(&self) -> u8 {
+        self.0.lg_config_k()
+    }
+
+    pub(crate) fn get_target_type(&self) -> target_hll_type {
+        self.0.tgt_type().into()
+    }
+
+    /// Sketches are always kept in updatable form, like in the C++ library.
+    pub(crate) fn is_compact(&self) -> bool {
+        false
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn get_compact_serialization_bytes(&self) -> u32 {
+        match &self.0 {
+            HllImpl::Coupons(coupons) => coupons.compact_bytes() as u32,
+            HllImpl::Array(array) => array.compact_bytes() as u32,
+        }
+    }
+
+    pub(crate) fn get_updatable_serialization_bytes(&self) -> u32 {
+        match &self.0 {
+            HllImpl::Coupons(coupons) => coupons.updatable_bytes() as u32,
+            HllImpl::Array(array) => array.updatable_bytes() as u32,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "cpp"))]
+mod tests {
+    use datasketches_sys::hll::ffi;
+
+    use super::super::{hll_sketch_serialize_compact as compact, *};
+    use crate::format::{read_f64, read_u32};
+    use crate::hll::format::*;
+
+    const TYPES: [(HllType, ffi::target_hll_type); 3] = [
+        (HllType::HLL4, ffi::target_hll_type::HLL_4),
+        (HllType::HLL6, ffi::target_hll_type::HLL_6),
+        (HllType::HLL8, ffi::target_hll_type::HLL_8),
+    ];
+
+    /// Compares the images of a native and of a C++ sketch. The HIP
+    /// accumulator of arrays promoted from coupons starts from the coupon
+    /// estimate, which the C++ library interpolates out of tables that are not
+    /// ported, so it only has to agree to a relative 1e-7. Arrays allocated
+    /// in full start from 0 and must match bit for bit.
+    fn assert_same_image(native: &[u8], cxx: &[u8]) {
+        assert_eq!(native.len(), cxx.len());
+        let promoted =
+            cur_mode(native) == CUR_MODE_HLL && native[FLAGS_BYTE] & FULL_SIZE_FLAG_MASK == 0;
+        if promoted {
+            let hip = HIP_ACCUM_DOUBLE..HIP_ACCUM_DOUBLE + 8;
+            assert_eq!(native[..hip.start], cxx[..hip.start]);
+            assert_eq!(native[hip.end..], cxx[hip.end..]);
+            let native_hip = read_f64(native, HIP_ACCUM_DOUBLE);
+            let cxx_hip = read_f64(cxx, HIP_ACCUM_DOUBLE);
+            assert!(
+                (native_hip - cxx_hip).abs() <= cxx_hip * 1e-7,
+                "HIP accumulator {native_hip}, expected {cxx_hip}"
+            );
+        } else {
+            assert_eq!(native, cxx);
+        }
+    }
+
+    #[test]
+    fn same_images_as_cxx() {
+        let configs = [
+            (4, false),
+            (7, false),
+            (8, false),
+            (12, false),
+            (4, true),
+            (12, true),
+        ];
+        for (lg_config_k, start_full_size) in configs {
+            for (tgt_type, cxx_type) in TYPES {
+                let mut native = hll_sketch_new(lg_config_k, tgt_type.into(), start_full_size);
+                let mut cxx = ffi::hll_sketch_new(lg_config_k, cxx_type, start_full_size);
+                for n in [0u64, 3, 10, 100, 1_000, 100_000] {
+                    let data: Vec<u64> = (n..2 * n).collect();
+                    hll_sketch_update_u64_slice(native.pin_mut(), &data);
+                    ffi::hll_sketch_update_u64_slice(cxx.pin_mut(), &data);
+
+                    assert_same_image(
+                        &compact(&native, 0),
+                        &ffi::hll_sketch_serialize_compact(&cxx, 0),
+                    );
+                    assert_same_image(
+                        &hll_sketch_serialize_updatable(&native),
+                        &ffi::hll_sketch_serialize_updatable(&cxx),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_cxx_images() {
+        for (tgt_type, cxx_type) in TYPES {
+            let mut cxx = ffi::hll_sketch_new(10, cxx_type, false);
+            for n in [0u64, 5, 50, 500, 50_000] {
+                let data: Vec<u64> = (0..n).collect();
+                ffi::hll_sketch_update_u64_slice(cxx.pin_mut(), &data);
+
+                for image in [
+                    ffi::hll_sketch_serialize_compact(&cxx, 0),
+                    ffi::hll_sketch_serialize_updatable(&cxx),
+                ] {
+                    let native = hll_sketch_deserialize(&image).unwrap();
+                    assert_eq!(HllType::from(native.get_target_type()), tgt_type);
+                    if cur_mode(&image) == CUR_MODE_HLL {
+                        assert_eq!(native.get_estimate(), cxx.get_estimate());
+                    }
+                    assert_eq!(
+                        compact(&native, 0),
+                        ffi::hll_sketch_serialize_compact(&cxx, 0)
+                    );
+
+                    let back =
+                        ffi::hll_sketch_deserialize(&hll_sketch_serialize_updatable(&native))
+                            .unwrap();
+                    assert_eq!(
+                        ffi::hll_sketch_serialize_compact(&back, 0),
+                        ffi::hll_sketch_serialize_compact(&cxx, 0)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let data: Vec<u64> = (0..100_000).collect();
+        for (tgt_type, cxx_type) in TYPES {
+            let mut cxx = ffi::hll_sketch_new(11, cxx_type, false);
+            ffi::hll_sketch_update_u64_slice(cxx.pin_mut(), &data);
+            let native =
+                hll_sketch_deserialize(&ffi::hll_sketch_serialize_updatable(&cxx)).unwrap();
+            for (other, cxx_other) in TYPES {
+                assert_eq!(
+                    compact(&hll_sketch_copy_with_target(&native, other.into()), 0),
+                    ffi::hll_sketch_serialize_compact(
+                        &ffi::hll_sketch_copy_with_target(&cxx, cxx_other),
+                        0
+                    ),
+                    "{tgt_type:?} to {other:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn aux_exceptions() {
+        // small HLL4 sketches fed many items hold values far above cur_min
+        let mut native = hll_sketch_new(7, target_hll_type::HLL_4, false);
+        for datum in 0..1_000_000u64 {
+            native.pin_mut().update_u64(datum);
+        }
+        let image = compact(&native, 0);
+        assert!(read_u32(&image, AUX_COUNT_INT) > 0);
+
+        let registers = crate::hll::registers::registers(&image);
+        let copy = hll_sketch_deserialize(&image).unwrap();
+        assert_eq!(compact(&copy, 0), image);
+        for (slot, value) in registers.iter().enumerate() {
+            let nibble = (image[HLL_BYTE_ARR_START + slot / 2] >> ((slot & 1) * 4)) & 0xf;
+            assert_eq!(
+                nibble == AUX_TOKEN,
+                *value >= image[HLL_CUR_MIN_BYTE] + AUX_TOKEN
+            );
+        }
+        let estimate = native.get_estimate();
+        assert!((estimate - 1e6).abs() < 1e6 * 0.3, "{estimate}");
+    }
+
+    #[test]
+    fn estimates() {
+        let mut native = hll_sketch_new(12, target_hll_type::HLL_8, false);
+        assert_eq!(native.get_estimate(), 0.0);
+        for datum in 0..3u64 {
+            native.pin_mut().update_u64(datum);
+        }
+        assert_eq!(native.get_estimate().floor(), 3.0);
+
+        for datum in 3..100_000u64 {
+            native.pin_mut().update_u64(datum);
+        }
+        for estimate in [native.get_estimate(), native.get_composite_estimate()] {
+            assert!((estimate - 1e5).abs() < 1e5 * 0.05, "{estimate}");
+        }
+        assert!(native.get_lower_bound(2) < native.get_estimate());
+        assert!(native.get_upper_bound(2) > native.get_estimate());
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The union, merging sketches into an HLL8 gadget sketch.
+
+use super::super::HllType;
+use super::sketch::{hll_sketch, hll_sketch_update_bytes, HllImpl};
+use super::{check_lg_k, target_hll_type, UniquePtr};
+
+#[allow(non_camel_case_types)]
+#[derive(Clone)]
+pub(crate) struct hll_union {
+    lg_max_k: u8,
+    gadget: hll_sketch,
+}
+
+pub(crate) fn hll_union_new(lg_max_k: u8) -> UniquePtr<hll_union> {
+    check_lg_k(lg_max_k);
+    UniquePtr::new(hll_union {
+        lg_max_k,
+        gadget: hll_sketch::new(lg_max_k, HllType::HLL8, false),
+    })
+}
+
+pub(crate) fn hll_union_copy(union: &hll_union) -> UniquePtr<hll_union> {
+    UniquePtr::new(union.clone())
+}
+
+pub(crate) fn hll_union_get_result(
+    union: &hll_union,
+    tgt_type: target_hll_type,
+) -> UniquePtr<hll_sketch> {
+    UniquePtr::new(hll_sketch(union.gadget.0.copy_as(tgt_type.into())))
+}
+
+pub(crate) fn hll_union_update_bytes(union: &mut hll_union, bytes: &[u8]) {
+    hll_sketch_update_bytes(&mut union.gadget, bytes);
+}
+
+macro_rules! delegate {
+    ($(fn $name:ident(&mut self $(, $v:ident: $t:ty)?);)+) => {
+        $(
+            pub(crate) fn $name(&mut self $(, $v: $t)?) {
+                self.gadget.$name($($v)?)
+            }
+        )+
+    };
+    ($(fn $name:ident(&self $(, $v:ident: $t:ty)?) -> $ret:ty;)+) => {
+        $(
+            pub(crate) fn $name(&self $(, $v: $t)?) -> $ret {
+                self.gadget.$name($($v)?)
+            }
+        )+
+    };
+}
+
+impl hll_union {
+    /// Merges `sketch` into the gadget, following the C++ `hll_union::union_impl`.
+    pub(crate) fn update_sketch(&mut self, sketch: &hll_sketch) {
+        if sketch.is_empty() {
+            return;
+        }
+
+        let lg_max_k = self.lg_max_k;
+        let dst = &mut self.gadget.0;
+        match &sketch.0 {
+            HllImpl::Coupons(src) => {
+                if dst.is_empty() && src.lg_config_k == dst.lg_config_k() {
+                    *dst = HllImpl::Coupons(src.copy_as(HllType::HLL8));
+                } else {
+                    for coupon in src.iter() {
+                        dst.coupon_update(coupon);
+                    }
+                }
+            }
+            HllImpl::Array(src) if dst.is_empty() => {
+                *dst = HllImpl::Array(src.copy_or_downsample(lg_max_k));
+            }
+            HllImpl::Array(src) => match dst {
+                HllImpl::Coupons(coupons) => {
+                    // coupons carry 26 bits of address, so they fit any array
+                    let mut array = src.copy_or_downsample(lg_max_k);
+                    for coupon in coupons.iter() {
+                        array.coupon_update(coupon);
+                    }
+                    array.out_of_order = true;
+                    array.hip_accum = 0.0;
+                    *dst = HllImpl::Array(array);
+                }
+                HllImpl::Array(gadget) => {
+                    if src.lg_config_k < gadget.lg_config_k {
+                        *gadget = gadget.copy_or_downsample(src.lg_config_k);
+                    }
+                    gadget.merge_hll(src);
+                    gadget.out_of_order = true;
+                    gadget.hip_accum = 0.0;
+                }
+            },
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.gadget = hll_sketch::new(self.lg_max_k, HllType::HLL8, false);
+    }
+
+    delegate! {
+        fn update_u64(&mut self, datum: u64);
+        fn update_u32(&mut self, datum: u32);
+        fn update_u16(&mut self, datum: u16);
+        fn update_u8(&mut self, datum: u8);
+        fn update_i64(&mut self, datum: i64);
+        fn update_i32(&mut self, datum: i32);
+        fn update_i16(&mut self, datum: i16);
+        fn update_i8(&mut self, datum: i8);
+        fn update_f64(&mut self, datum: f64);
+        fn update_f32(&mut self, datum: f32);
+    }
+
+    delegate! {
+        fn get_estimate(&self) -> f64;
+        fn get_composite_estimate(&self) -> f64;
+        fn get_lower_bound(&self, num_std_dev: u8) -> f64;
+        fn get_upper_bound(&self, num_std_dev: u8) -> f64;
+        fn get_lg_config_k(&self) -> u8;
+        fn get_target_type(&self) -> target_hll_type;
+        fn is_empty(&self) -> bool;
+    }
+}
+
+#[cfg(all(test, feature = "cpp"))]
+mod tests {
+    use datasketches_sys::hll::ffi;
+
+    use super::super::*;
+
+    fn sketches(
+        lg_config_k: u8,
+        n: u64,
+    ) -> (UniquePtr<hll_sketch>, cxx::UniquePtr<ffi::hll_sketch>) {
+        let data: Vec<u64> = (n..2 * n).collect();
+        let mut native = hll_sketch_new(lg_config_k, target_hll_type::HLL_4, false);
+        hll_sketch_update_u64_slice(native.pin_mut(), &data);
+        let mut cxx = ffi::hll_sketch_new(lg_config_k, ffi::target_hll_type::HLL_4, false);
+        ffi::hll_sketch_update_u64_slice(cxx.pin_mut(), &data);
+        (native, cxx)
+    }
+
+    #[test]
+    fn same_results_as_cxx() {
+        let mut native = hll_union_new(12);
+        let mut cxx = ffi::hll_union_new(12);
+        for (lg_config_k, n) in [(12, 10), (10, 100), (12, 10_000), (11, 100_000), (12, 3)] {
+            let (native_sketch, cxx_sketch) = sketches(lg_config_k, n);
+            native.pin_mut().update_sketch(&native_sketch);
+            cxx.pin_mut().update_sketch(&cxx_sketch);
+
+            let native_result = hll_union_get_result(&native, target_hll_type::HLL_8);
+            let cxx_result = ffi::hll_union_get_result(&cxx, ffi::target_hll_type::HLL_8);
+            assert_eq!(native.get_lg_config_k(), cxx.get_lg_config_k());
+            assert_eq!(
+                crate::hll::registers::registers(&hll_sketch_serialize_compact(&native_result, 0)),
+                crate::hll::registers::registers(&ffi::hll_sketch_serialize_compact(
+                    &cxx_result,
+                    0
+                )),
+            );
+        }
+        assert!((native.get_estimate() - cxx.get_estimate()).abs() < cxx.get_estimate() * 0.02);
+    }
+
+    #[test]
+    fn downsampling() {
+        let mut union = hll_union_new(10);
+        let (small, _) = sketches(10, 5_000);
+        let (large, _) = sketches(12, 5_000);
+        union.pin_mut().update_sketch(&small);
+        union.pin_mut().update_sketch(&large);
+        assert_eq!(union.get_lg_config_k(), 10);
+        assert!((union.get_estimate() - 5_000.0).abs() < 5_000.0 * 0.1);
+
+        union.pin_mut().reset();
+        assert!(union.is_empty());
+        assert_eq!(HllType::from(union.get_target_type()), HllType::HLL8);
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [RedisHll].
+
+use crate::error::Error;
+
+use super::{ertl, HllSketch, HllType, HllUnion};
+
+/// The log2 of the number of registers of Redis HyperLogLogs.
+pub const REDIS_LG_K: u8 = 14;
+
+const REGISTERS: usize = 1 << REDIS_LG_K;
+/// The number of bits of the hash left once the register index is taken.
+const HASH_BITS: u32 = 64 - REDIS_LG_K as u32;
+/// The largest value a register can take.
+const MAX_VALUE: u8 = HASH_BITS as u8 + 1;
+const HASH_SEED: u64 = 0xadc83b19;
+
+const MAGIC: &[u8; 4] = b"HYLL";
+const HEADER_BYTES: usize = 16;
+const ENCODING_BYTE: usize = 4;
+const CARDINALITY_BYTE: usize = 8;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+const DENSE_BYTES: usize = HEADER_BYTES + REGISTERS * 6 / 8;
+
+/// The default `hll-sparse-max-bytes` of Redis: larger sparse strings are
+/// converted to the dense encoding.
+const SPARSE_MAX_BYTES: usize = 3000;
+const SPARSE_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = REGISTERS;
+
+/// A HyperLogLog in the format of Redis, as stored by `PFADD` and returned by
+/// `GET` on the key of a counter.
+///
+/// Redis HyperLogLogs have `2^14` registers, the same layout as an
+/// [HllSketch] of `lg_config_k` 14. Both pick the register of an item with the
+/// low bits of a 64-bit hash and store one plus the number of zeros preceding
+/// the first set bit of the remaining bits, so their registers follow the
+/// same distribution and can be converted as-is: an [HllSketch] converted
+/// from a Redis counter estimates the number of items added to the counter
+/// with the accuracy of an HLL sketch.
+///
+/// The hash functions differ, though: Redis uses MurmurHash64A while this
+/// crate uses MurmurHash3, see [hash](crate::hash). The same item thus lands
+/// in unrelated registers of a sketch converted from Redis and of a sketch
+/// updated by this crate, so merging them counts the items they share
+/// twice. Converted sketches must only be merged with other sketches
+/// converted from Redis, or with sketches of items known to be disjoint.
+/// New items can be added to a [RedisHll] with [RedisHll::update], which
+/// hashes them like `PFADD` does.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RedisHll {
+    registers: Vec<u8>,
+}
+
+impl Default for RedisHll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RedisHll {
+    /// Creates an empty HyperLogLog.
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    /// Parses a Redis HyperLogLog string, in either the dense or the sparse
+    /// encoding. The cached cardinality of the header is ignored.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_BYTES {
+            return Err(Error::Truncated {
+                expected: HEADER_BYTES,
+                found: bytes.len(),
+            });
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidImage("missing HYLL magic"));
+        }
+        match bytes[ENCODING_BYTE] {
+            DENSE => Self::from_dense(bytes),
+            SPARSE => Self::from_sparse(&bytes[HEADER_BYTES..]),
+            _ => Err(Error::InvalidImage("unknown Redis HLL encoding")),
+        }
+    }
+
+    fn from_dense(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != DENSE_BYTES {
+            return Err(Error::Truncated {
+                expected: DENSE_BYTES,
+                found: bytes.len(),
+            });
+        }
+        let data = &bytes[HEADER_BYTES..];
+        let registers = (0..REGISTERS)
+            .map(|index| {
+                let (byte, shift) = (index * 6 / 8, index * 6 % 8);
+                let next = data.get(byte + 1).copied().unwrap_or(0);
+                let word = u16::from_le_bytes([data[byte], next]);
+                (word >> shift) as u8 & 0x3f
+            })
+            .collect();
+        Ok(Self { registers })
+    }
+
+    fn from_sparse(data: &[u8]) -> Result<Self, Error> {
+        let mut registers = Vec::with_capacity(REGISTERS);
+        let mut opcodes = data.iter();
+        while let Some(&opcode) = opcodes.next() {
+            let (value, len) = match opcode >> 6 {
+                // ZERO: 00xxxxxx, a run of up to 64 empty registers.
+                0b00 => (0, (opcode & 0x3f) as usize + 1),
+                // XZERO: 01xxxxxx yyyyyyyy, a run of up to 16384 empty registers.
+                0b01 => {
+                    let Some(&low) = opcodes.next() else {
+                        return Err(Error::InvalidImage("truncated Redis XZERO opcode"));
+                    };
+                    (0, (((opcode & 0x3f) as usize) << 8 | low as usize) + 1)
+                }
+                // VAL: 1vvvvvxx, a run of up to 4 registers holding vvvvv + 1.
+                _ => (((opcode >> 2) & 0x1f) + 1, (opcode & 0x03) as usize + 1),
+            };
+            if registers.len() + len > REGISTERS {
+                return Err(Error::InvalidImage("too many Redis HLL registers"));
+            }
+            registers.resize(registers.len() + len, value);
+        }
+        if registers.len() != REGISTERS {
+            return Err(Error::InvalidImage("too few Redis HLL registers"));
+        }
+        Ok(Self { registers })
+    }
+
+    /// Serializes the HyperLogLog to a Redis string, which can be stored with
+    /// `SET` and then used with `PFCOUNT` and `PFMERGE`. The sparse encoding
+    /// is used when Redis would use it itself, the dense one otherwise. The
+    /// cached cardinality is flagged as stale, so Redis recomputes it.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(DENSE_BYTES);
+        bytes.extend_from_slice(MAGIC);
+        bytes.resize(HEADER_BYTES, 0);
+        bytes[CARDINALITY_BYTE + 7] = 0x80;
+
+        match self.sparse() {
+            Some(data) => {
+                bytes[ENCODING_BYTE] = SPARSE;
+                bytes.extend_from_slice(&data);
+            }
+            None => {
+                bytes[ENCODING_BYTE] = DENSE;
+                bytes.resize(DENSE_BYTES, 0);
+                let data = &mut bytes[HEADER_BYTES..];
+                for (index, &value) in self.registers.iter().enumerate() {
+                    let (byte, shift) = (index * 6 / 8, index * 6 % 8);
+                    let [low, high] = (u16::from(value) << shift).to_le_bytes();
+                    data[byte] |= low;
+                    if high != 0 {
+                        data[byte + 1] |= high;
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Returns the sparse encoding of the registers, or [None] if a register
+    /// is too large for it or if it would exceed [SPARSE_MAX_BYTES].
+    fn sparse(&self) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut index = 0;
+        while index < REGISTERS {
+            let value = self.registers[index];
+            if value > SPARSE_MAX_VALUE {
+                return None;
+            }
+            let run = self.registers[index..]
+                .iter()
+                .take_while(|r| **r == value)
+                .count();
+            index += run;
+
+            let mut run = run;
+            while run > 0 {
+                if value > 0 {
+                    let len = run.min(SPARSE_VAL_MAX_LEN);
+                    data.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                    run -= len;
+                } else if run > SPARSE_ZERO_MAX_LEN {
+                    let len = run.min(SPARSE_XZERO_MAX_LEN) - 1;
+                    data.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]);
+                    run -= len + 1;
+                } else {
+                    data.push((run - 1) as u8);
+                    run = 0;
+                }
+            }
+            if HEADER_BYTES + data.len() > SPARSE_MAX_BYTES {
+                return None;
+            }
+        }
+        Some(data)
+    }
+
+    /// Converts a sketch into a Redis HyperLogLog. Sketches of `lg_config_k`
+    /// larger than 14 are downsampled, as by an [HllUnion]. Registers larger
+    /// than the largest value Redis can produce, which only a sketch of about
+    /// `2^50` items would hold, are capped to it.
+    ///
+    /// Fails if `lg_config_k` is smaller than 14.
+    pub fn from_sketch(sketch: &HllSketch) -> Result<Self, Error> {
+        let lg_config_k = sketch.get_lg_config_k();
+        if lg_config_k < REDIS_LG_K {
+            return Err(Error::IncompatibleLgK {
+                expected: REDIS_LG_K,
+                found: lg_config_k,
+            });
+        }
+        let mut redis = Self::new();
+        for (slot, value) in sketch.registers().into_iter().enumerate() {
+            let register = &mut redis.registers[slot % REGISTERS];
+            *register = (*register).max(value.min(MAX_VALUE));
+        }
+        Ok(redis)
+    }
+
+    /// Converts the HyperLogLog into a sketch of `lg_config_k` 14. The
+    /// registers are copied as-is: the [HllType::HLL6] and [HllType::HLL8]
+    /// target types hold them exactly like the 6-bit registers of Redis,
+    /// while [HllType::HLL4] stores the large ones in its auxiliary map.
+    pub fn to_sketch(&self, tgt_type: HllType) -> HllSketch {
+        HllSketch::from_registers(REDIS_LG_K, &self.registers, tgt_type)
+    }
+
+    /// Returns the values of the `2^14` registers.
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Present the given bytes as a potential unique item, hashed like
+    /// `PFADD` does. Returns whether a register was updated, which is when
+    /// `PFADD` replies 1.
+    pub fn update(&mut self, datum: &[u8]) -> bool {
+        let hash = murmur_hash64a(datum, HASH_SEED);
+        let index = (hash & (REGISTERS as u64 - 1)) as usize;
+        let value = ((hash >> REDIS_LG_K) | 1 << HASH_BITS).trailing_zeros() as u8 + 1;
+        if self.registers[index] < value {
+            self.registers[index] = value;
+            return true;
+        }
+        false
+    }
+
+    /// Merges another HyperLogLog into this one, like `PFMERGE`.
+    pub fn merge(&mut self, other: &RedisHll) {
+        for (register, &value) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(value);
+        }
+    }
+
+    /// Returns the cardinality estimate `PFCOUNT` replies for this
+    /// HyperLogLog, computed with the estimator of Otmar Ertl used by Redis.
+    pub fn count(&self) -> u64 {
+        ertl::estimate(&self.registers, HASH_BITS as usize).round() as u64
+    }
+}
+
+impl HllUnion {
+    /// Update this union operator with a Redis HyperLogLog, converted with
+    /// [RedisHll::to_sketch]. See [RedisHll] for the items the result counts:
+    /// the union must only be given sketches converted from Redis, or
+    /// sketches of items disjoint from the Redis ones.
+    pub fn update_redis(&mut self, sketch: &RedisHll) {
+        self.update_sketch(&sketch.to_sketch(HllType::HLL8));
+    }
+}
+
+/// The 64-bit MurmurHash2 of Austin Appleby, as implemented by Redis.
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut blocks = data.chunks_exact(8);
+    for block in &mut blocks {
+        let mut k = u64::from_le_bytes(block.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= u64::from(byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redis(items: std::ops::Range<u32>) -> RedisHll {
+        let mut redis = RedisHll::new();
+        for i in items {
+            redis.update(format!("item-{i}").as_bytes());
+        }
+        redis
+    }
+
+    #[test]
+    fn encodings() {
+        let empty = RedisHll::new();
+        let image = empty.serialize();
+        assert_eq!(&image[..5], b"HYLL\x01");
+        // A single XZERO opcode covers the 16384 empty registers.
+        assert_eq!(&image[HEADER_BYTES..], &[0x7f, 0xff]);
+        assert_eq!(empty.count(), 0);
+
+        for (n, encoding) in [(0, SPARSE), (100, SPARSE), (100_000, DENSE)] {
+            let redis = redis(0..n);
+            let image = redis.serialize();
+            assert_eq!(image[ENCODING_BYTE], encoding);
+            assert_eq!(RedisHll::deserialize(&image).unwrap(), redis);
+        }
+
+        assert!(RedisHll::deserialize(b"HYLL").is_err());
+        assert!(
+            RedisHll::deserialize(b"HYLX\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80").is_err()
+        );
+        let mut image = redis(0..100).serialize();
+        image.pop();
+        assert!(RedisHll::deserialize(&image).is_err());
+    }
+
+    #[test]
+    fn estimates() {
+        for n in [1000, 100_000] {
+            let redis = redis(0..n);
+            let count = redis.count() as f64;
+            assert!(
+                (count - n as f64).abs() < n as f64 * 0.03,
+                "{count} for {n}"
+            );
+
+            let sketch = redis.to_sketch(HllType::HLL6);
+            let estimate = sketch.get_estimate();
+            assert!(
+                (estimate - n as f64).abs() < n as f64 * 0.03,
+                "{estimate} for {n}"
+            );
+            assert_eq!(RedisHll::from_sketch(&sketch).unwrap(), redis);
+        }
+    }
+
+    #[test]
+    fn sketches() {
+        let mut hll = HllSketch::new(16, HllType::HLL8, false);
+        hll.update_u64_slice(&(0..10_000).collect::<Vec<_>>());
+        let converted = RedisHll::from_sketch(&hll).unwrap();
+        let mut union = HllUnion::new(REDIS_LG_K);
+        union.update_sketch(&hll);
+        assert_eq!(
+            converted.registers(),
+            union.get_result(HllType::HLL8).registers()
+        );
+
+        assert_eq!(
+            RedisHll::from_sketch(&HllSketch::new(12, HllType::HLL8, false)),
+            Err(Error::IncompatibleLgK {
+                expected: 14,
+                found: 12
+            })
+        );
+
+        let (a, b) = (redis(0..1000), redis(500..1500));
+        let mut union = HllUnion::new(REDIS_LG_K);
+        union.update_redis(&a);
+        union.update_redis(&b);
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert_eq!(
+            union.get_result(HllType::HLL8).registers(),
+            merged.registers()
+        );
+        assert!((merged.count() as f64 - 1500.0).abs() < 1500.0 * 0.03);
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [HllCoupon].
+
+use super::{format::*, HllType};
+
+/// A coupon collected by an HLL sketch in [HllMode::List](super::HllMode::List)
+/// or [HllMode::Set](super::HllMode::Set), see
+/// [HllSketch::coupons](super::HllSketch::coupons).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HllCoupon {
+    /// The 26 low bits of the hash of the item. The register the coupon
+    /// belongs to is given by its `lg_config_k` low bits.
+    pub address: u32,
+    /// The value of the register, the number of leading zeros of the second
+    /// half of the hash plus one.
+    pub value: u8,
+}
+
+impl HllCoupon {
+    /// Returns the index of the register the coupon belongs to in a sketch
+    /// configured with `lg_config_k`.
+    #[inline]
+    pub fn slot(&self, lg_config_k: u8) -> usize {
+        (self.address & ((1 << lg_config_k) - 1)) as usize
+    }
+}
+
+impl From<u32> for HllCoupon {
+    fn from(coupon: u32) -> Self {
+        Self {
+            address: coupon & KEY_MASK_26,
+            value: (coupon >> KEY_BITS_26) as u8,
+        }
+    }
+}
+
+/// Reads the non-empty 32-bit entries stored from `start` to the end of the image.
+fn read_pairs(bytes: &[u8], start: usize) -> impl Iterator<Item = u32> + '_ {
+    bytes[start..]
+        .chunks_exact(4)
+        .map(|pair| u32::from_le_bytes(pair.try_into().unwrap()))
+        .filter(|pair| *pair != 0)
+}
+
+/// Returns the coupons held by the serialized image of a sketch in list or
+/// set mode. Sketches in HLL mode hold no coupons.
+pub(crate) fn coupons(bytes: &[u8]) -> Vec<HllCoupon> {
+    let start = match cur_mode(bytes) {
+        CUR_MODE_LIST => LIST_INT_ARR_START,
+        CUR_MODE_SET => HASH_SET_INT_ARR_START,
+        _ => return Vec::new(),
+    };
+    read_pairs(bytes, start).map(HllCoupon::from).collect()
+}
+
+/// Returns the values of the registers of the serialized image of a sketch,
+/// promoting the coupons of sketches in list or set mode.
+pub(crate) fn registers(bytes: &[u8]) -> Vec<u8> {
+    let lg_config_k = bytes[LG_K_BYTE];
+    let mut registers = vec![0u8; 1 << lg_config_k];
+
+    if cur_mode(bytes) != CUR_MODE_HLL {
+        for coupon in coupons(bytes) {
+            let register = &mut registers[coupon.slot(lg_config_k)];
+            *register = (*register).max(coupon.value);
+        }
+        return registers;
+    }
+
+    let array = &bytes[HLL_BYTE_ARR_START..];
+    match tgt_hll_type(bytes) {
+        HllType::HLL4 => {
+            // registers are stored as 4-bit offsets from cur_min, two per byte
+            // starting from the low nibble. Values that do not fit are stored
+            // in the auxiliary array that follows, as (value, slot) pairs.
+            let cur_min = bytes[HLL_CUR_MIN_BYTE];
+            for (slot, register) in registers.iter_mut().enumerate() {
+                *register = cur_min + ((array[slot >> 1] >> ((slot & 1) << 2)) & 0xf);
+            }
+            for pair in read_pairs(array, registers.len() / 2) {
+                let pair = HllCoupon::from(pair);
+                registers[pair.address as usize] = pair.value;
+            }
+        }
+        HllType::HLL6 => {
+            for (slot, register) in registers.iter_mut().enumerate() {
+                let start_bit = slot * 6;
+                let index = start_bit >> 3;
+                let two_bytes = u16::from_le_bytes([array[index], array[index + 1]]);
+                *register = ((two_bytes >> (start_bit & 7)) & 0x3f) as u8;
+            }
+        }
+        HllType::HLL8 => {
+            let len = registers.len();
+            registers.copy_from_slice(&array[..len]);
+        }
+    }
+
+    registers
+}
+
+/// Returns a canonical encoding of the logical state of the serialized image
+/// of a sketch: its `lg_config_k` followed by either its sorted coupons or,
+/// once in HLL mode, its registers. Empty sketches hold no coupons, whatever
+/// their mode.
+pub(crate) fn canonical_state(bytes: &[u8]) -> Vec<u8> {
+    let mut state = vec![FAMILY_ID, bytes[LG_K_BYTE]];
+    if cur_mode(bytes) == CUR_MODE_HLL {
+        let registers = registers(bytes);
+        if registers.iter().any(|r| *r != 0) {
+            state.push(CUR_MODE_HLL);
+            state.extend(registers);
+            return state;
+        }
+    }
+
+    state.push(CUR_MODE_LIST);
+    let mut coupons = coupons(bytes);
+    coupons.sort_unstable();
+    for coupon in coupons {
+        state.extend(((u32::from(coupon.value) << KEY_BITS_26) | coupon.address).to_le_bytes());
+    }
+    state
+}
+
+/// Builds the updatable image of an [HllType::HLL8] sketch holding the given
+/// registers.
+///
+/// The history of the updates is unknown, so the image is flagged as out of
+/// order and the sketch uses the composite estimator rather than HIP.
+pub(crate) fn hll8_image(lg_config_k: u8, registers: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0u8; HLL_BYTE_ARR_START + registers.len()];
+    bytes[PREAMBLE_INTS_BYTE] = HLL_PREINTS;
+    bytes[SER_VER_BYTE] = SER_VER;
+    bytes[FAMILY_BYTE] = FAMILY_ID;
+    bytes[LG_K_BYTE] = lg_config_k;
+    bytes[FLAGS_BYTE] = OUT_OF_ORDER_FLAG_MASK;
+    bytes[MODE_BYTE] = mode_byte(CUR_MODE_HLL, HllType::HLL8);
+
+    // HLL6 and HLL8 arrays keep cur_min at 0 and count the empty registers
+    let (mut kxq0, mut kxq1, mut num_at_cur_min) = (0.0, 0.0, 0u32);
+    for register in registers {
+        let inv_pow2 = 1.0 / (1u64 << register) as f64;
+        if *register < 32 {
+            kxq0 += inv_pow2;
+        } else {
+            kxq1 += inv_pow2;
+        }
+        if *register == 0 {
+            num_at_cur_min += 1;
+        }
+    }
+    bytes[KXQ0_DOUBLE..KXQ0_DOUBLE + 8].copy_from_slice(&kxq0.to_le_bytes());
+    bytes[KXQ1_DOUBLE..KXQ1_DOUBLE + 8].copy_from_slice(&kxq1.to_le_bytes());
+    bytes[CUR_MIN_COUNT_INT..CUR_MIN_COUNT_INT + 4].copy_from_slice(&num_at_cur_min.to_le_bytes());
+    bytes[HLL_BYTE_ARR_START..].copy_from_slice(registers);
+    bytes
+}
@@ -14,13 +14,19 @@
 
 //! Refer to [HllSketch].
 
+use crate::config::{Confidence, Family, Sizing, SketchConfig};
+#[cfg(feature = "cpp")]
+use crate::cpc::sketch::CpcSketch;
+use crate::error::Error;
+use crate::hash::{murmur3_x64_128, preimage, DEFAULT_SEED};
+use crate::header::inspect_family;
+use crate::item::{check_packed, pack_strings, ItemSink, SketchItem};
 use crate::macros::*;
-use std::ops::Deref;
+use crate::traits::CardinalitySketch;
 
-use cxx::{let_cxx_string, UniquePtr};
-use datasketches_sys::hll::{ffi::*, DEFAULT_LG_CONFIG_K};
-
-use super::HllType;
+use super::backend::*;
+use super::registers::{canonical_state, coupons, hll8_image, registers};
+use super::{HllCoupon, HllSummary, HllType};
 
 /// This is a high performance implementation of Phillipe Flajolet's HLL sketch but with
 /// significantly improved error behavior.  If the ONLY use case for sketching is counting
@@ -41,6 +47,12 @@ use super::HllType;
 /// (up to about 10% of `K`), this implementation leverages a new class of estimator
 /// algorithms with significantly better accuracy.
 ///
+/// With the `pure-rust` feature, the sketch is backed by a native port of the
+/// C++ library which goes through the same modes and registers, but not the
+/// empirical tables of these estimators: estimates of sketches in warmup, and
+/// the HIP estimate of sketches which left it, differ slightly from the ones
+/// of the C++ library, and so do the serialized HIP estimator states.
+///
 /// This sketch also offers the capability of operating off-heap. Given a WritableMemory object
 /// created by the user, the sketch will perform all of its updates and internal phase transitions
 /// in that object, which can actually reside either on-heap or off-heap based on how it is
@@ -66,6 +78,46 @@ impl Clone for HllSketch {
     }
 }
 
+impl<T: SketchItem> Extend<T> for HllSketch {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.update(&item);
+        }
+    }
+}
+
+impl<T: SketchItem> FromIterator<T> for HllSketch {
+    /// Builds a sketch with the default configuration out of the given items.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut sketch = Self::default();
+        sketch.extend(iter);
+        sketch
+    }
+}
+
+/// Two sketches are equal when they share `lg_config_k` and hold the same
+/// coupons or, once in HLL mode, the same registers. The target type, the
+/// compact or updatable form and the state of the HIP estimator are ignored.
+impl PartialEq for HllSketch {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical_state() == other.canonical_state()
+    }
+}
+
+impl Eq for HllSketch {}
+
+impl std::hash::Hash for HllSketch {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.content_hash());
+    }
+}
+
+impl std::fmt::Debug for HllSketch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("HllSketch").field(&self.summary()).finish()
+    }
+}
+
 impl std::fmt::Display for HllSketch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.to_string(true, false, false, false))
@@ -88,10 +140,149 @@ impl HllSketch {
         ))
     }
 
+    /// Returns the theoretical relative error of the estimate of a sketch
+    /// configured with `lg_config_k`, for the given number of standard
+    /// deviations (1, 2 or 3).
+    /// - `unioned`: whether the sketch went through union operations, in which
+    ///   case the less accurate composite estimator is used instead of HIP
+    /// - `upper`: whether to return the error of the upper or the lower bound
+    ///
+    /// The sign of the result follows the C++ library, only its magnitude
+    /// should be relied upon.
+    ///
+    /// Panics if `lg_config_k` is not between [MIN_LG_CONFIG_K] and
+    /// [MAX_LG_CONFIG_K] or if `num_std_dev` is not 1, 2 or 3.
+    pub fn relative_error(lg_config_k: u8, num_std_dev: u8, unioned: bool, upper: bool) -> f64 {
+        assert!(
+            (MIN_LG_CONFIG_K..=MAX_LG_CONFIG_K).contains(&lg_config_k),
+            "lg_config_k must be between {MIN_LG_CONFIG_K} and {MAX_LG_CONFIG_K}, got {lg_config_k}"
+        );
+        assert!(
+            (1..=3).contains(&num_std_dev),
+            "num_std_dev must be 1, 2 or 3, got {num_std_dev}"
+        );
+        hll_rel_error(upper, unioned, lg_config_k, num_std_dev)
+    }
+
+    /// Returns the maximum size of a sketch serialized without compaction for
+    /// the given configuration.
+    #[inline]
+    pub fn get_max_updatable_serialization_bytes(lg_config_k: u8, tgt_type: HllType) -> u32 {
+        hll_max_updatable_serialization_bytes(lg_config_k, tgt_type.into())
+    }
+
+    /// Returns the smallest `lg_config_k` whose relative error, at the given
+    /// confidence, is within `target_rse`, together with the size of the
+    /// resulting sketches, or [None] if no valid `lg_config_k` is accurate
+    /// enough.
+    ///
+    /// The error accounts for sketches that go through union operations,
+    /// which are less accurate than sketches that have only been updated.
+    pub fn lg_k_for_error(
+        target_rse: f64,
+        confidence: Confidence,
+        tgt_type: HllType,
+    ) -> Option<Sizing> {
+        let num_std_dev = confidence.num_std_dev();
+        (MIN_LG_CONFIG_K..=MAX_LG_CONFIG_K).find_map(|lg_k| {
+            let relative_error = [false, true]
+                .iter()
+                .map(|upper| Self::relative_error(lg_k, num_std_dev, true, *upper).abs())
+                .fold(0.0, f64::max);
+            (relative_error <= target_rse).then(|| Sizing {
+                lg_k,
+                relative_error,
+                max_serialized_bytes: Self::get_max_updatable_serialization_bytes(lg_k, tgt_type),
+            })
+        })
+    }
+
     /// Reconstructs a sketch from a serialized image in a byte array.
+    ///
+    /// Panics if the image is invalid, see [CardinalitySketch::deserialize]
+    /// for a fallible version.
     #[inline]
     pub fn deserialize(bytes: &[u8]) -> Self {
-        Self(hll_sketch_deserialize(bytes))
+        Self(hll_sketch_deserialize(bytes).unwrap_or_else(|err| panic!("{err}")))
+    }
+
+    /// Builds a sketch from the values of its `2^lg_config_k` registers, as
+    /// returned by [HllSketch::registers]. The sketch is flagged as out of
+    /// order, so its estimate is always the composite estimate.
+    ///
+    /// Panics if the number of registers does not match `lg_config_k` or if
+    /// a register exceeds 63.
+    pub fn from_registers(lg_config_k: u8, registers: &[u8], tgt_type: HllType) -> Self {
+        assert_eq!(
+            registers.len(),
+            1 << lg_config_k,
+            "expected 2^lg_config_k registers"
+        );
+        assert!(
+            registers.iter().all(|r| *r < 64),
+            "register values must be lower than 64"
+        );
+
+        if registers.iter().all(|r| *r == 0) {
+            return Self::new(lg_config_k, tgt_type, false);
+        }
+
+        let hll8 = Self::deserialize(&hll8_image(lg_config_k, registers));
+        match tgt_type {
+            HllType::HLL8 => hll8,
+            _ => hll8.clone_with_type(tgt_type),
+        }
+    }
+
+    /// Converts a CPC sketch into an HLL sketch holding the same items, as
+    /// if they had been presented to an HLL sketch of `lg_config_k` equal to
+    /// the `lg_k` of the CPC sketch.
+    ///
+    /// Both families hash items the same way, so the register of each HLL
+    /// slot is derived from the highest column the CPC sketch collected in
+    /// the matching row. CPC sketches do not expose their coupons, which are
+    /// thus found by probing a copy of the sketch: the conversion costs up to
+    /// 64 updates per row, about `64 * 2^lg_k` in total.
+    ///
+    /// Fails if the CPC sketch does not use [DEFAULT_SEED], the only seed of
+    /// HLL sketches, or if its `lg_k` exceeds [MAX_LG_CONFIG_K].
+    #[cfg(feature = "cpp")]
+    pub fn from_cpc(sketch: &CpcSketch, tgt_type: HllType) -> Result<Self, Error> {
+        let (expected, found) = (crate::hash::seed_hash(DEFAULT_SEED), sketch.seed_hash());
+        if found != expected {
+            return Err(Error::IncompatibleSeed { expected, found });
+        }
+        let lg_config_k = sketch.get_lg_k();
+        if lg_config_k > MAX_LG_CONFIG_K {
+            return Err(Error::UnsupportedLgK {
+                max: MAX_LG_CONFIG_K,
+                found: lg_config_k,
+            });
+        }
+
+        if sketch.is_empty() {
+            return Ok(Self::new(lg_config_k, tgt_type, false));
+        }
+
+        let mut registers = vec![0u8; 1 << lg_config_k];
+        let mut probe = sketch.clone();
+        let mut num_coupons = probe.get_num_coupons();
+        for row in 0..1u64 << lg_config_k {
+            // The column of a hash is the number of leading zeros of its
+            // second half, so probing from the top finds the highest column
+            // of the row first. Coupons added by missed probes sit above it
+            // and never affect the other rows.
+            for col in (0..64u8).rev() {
+                probe.update_hash((row, 1 << (63 - col)));
+                let count = probe.get_num_coupons();
+                if count == num_coupons {
+                    registers[row as usize] = col.min(62) + 1;
+                    break;
+                }
+                num_coupons = count;
+            }
+        }
+        Ok(Self::from_registers(lg_config_k, &registers, tgt_type))
     }
 
     /// Serializes the sketch to a byte array, compacting data structures
@@ -126,13 +317,97 @@ impl HllSketch {
         hll_sketch_to_string(&self.0, summary, detail, aux_detail, all)
     }
 
+    /// Returns a machine-readable summary of the state of the sketch.
+    pub fn summary(&self) -> HllSummary {
+        HllSummary::from_image(&self.serialize_updatable(), self.get_estimate())
+    }
+
+    /// Returns the values of the `2^lg_config_k` registers of the sketch,
+    /// whatever its target type. Sketches still in list or set mode return
+    /// the registers their coupons would be promoted to.
+    pub fn registers(&self) -> Vec<u8> {
+        registers(&self.serialize_updatable())
+    }
+
+    /// Returns a hash of the logical state of the sketch, which is equal for
+    /// sketches that are equal. It is stable across processes and versions of
+    /// this crate, so it can be used to deduplicate stored sketches.
+    pub fn content_hash(&self) -> u64 {
+        murmur3_x64_128(&self.canonical_state(), DEFAULT_SEED).0
+    }
+
+    fn canonical_state(&self) -> Vec<u8> {
+        canonical_state(&self.serialize_updatable())
+    }
+
+    /// Returns an iterator over the coupons collected by a sketch in list or
+    /// set mode. Sketches in HLL mode yield nothing, use
+    /// [HllSketch::registers] instead.
+    pub fn coupons(&self) -> impl Iterator<Item = HllCoupon> {
+        coupons(&self.serialize_updatable()).into_iter()
+    }
+
     /// Present the given string as a potential unique item.
     /// The string is converted to a byte array using UTF8 encoding.
     /// If the string is null or empty no update attempt is made and the method returns.
     #[inline]
     pub fn update_string(&mut self, datum: &str) {
-        let_cxx_string!(raw_datum = datum);
-        self.0.pin_mut().update_string(raw_datum.deref());
+        self.update_bytes(datum.as_bytes());
+    }
+
+    /// Present the given bytes as a potential unique item.
+    /// The bytes are hashed exactly as by the C++ `update(const void*, size_t)`
+    /// and the Java `update(byte[])` methods, so binary keys can be shared with
+    /// sketches built in other languages.
+    /// If the slice is empty no update attempt is made and the method returns.
+    #[inline]
+    pub fn update_bytes(&mut self, datum: &[u8]) {
+        hll_sketch_update_bytes(self.0.pin_mut(), datum);
+    }
+
+    /// Present an item by its hash, as returned by the functions of
+    /// [hash](crate::hash) with [DEFAULT_SEED](crate::hash::DEFAULT_SEED). The item is counted exactly as
+    /// if it had been presented itself.
+    #[inline]
+    pub fn update_hash(&mut self, hash: (u64, u64)) {
+        self.update_bytes(&preimage(hash, DEFAULT_SEED));
+    }
+
+    /// Present every unsigned 64-bit integer in the slice as a potential unique item.
+    /// The whole slice is handed to the C++ library in a single call, which is
+    /// much faster than calling [HllSketch::update_u64] for each element.
+    #[inline]
+    pub fn update_u64_slice(&mut self, data: &[u64]) {
+        hll_sketch_update_u64_slice(self.0.pin_mut(), data);
+    }
+
+    /// Present every signed 64-bit integer in the slice as a potential unique item.
+    /// The whole slice is handed to the C++ library in a single call.
+    #[inline]
+    pub fn update_i64_slice(&mut self, data: &[i64]) {
+        hll_sketch_update_i64_slice(self.0.pin_mut(), data);
+    }
+
+    /// Present every 64-bit floating point value in the slice as a potential unique item.
+    /// The whole slice is handed to the C++ library in a single call.
+    #[inline]
+    pub fn update_f64_slice(&mut self, data: &[f64]) {
+        hll_sketch_update_f64_slice(self.0.pin_mut(), data);
+    }
+
+    /// Present every given string as a potential unique item, as
+    /// [HllSketch::update_string] does. The strings are packed into a single buffer
+    /// and handed to the C++ library in one call.
+    pub fn update_strings<'a>(&mut self, data: impl IntoIterator<Item = &'a str>) {
+        let (buf, offsets) = pack_strings(data);
+        hll_sketch_update_strings(self.0.pin_mut(), &buf, &offsets);
+    }
+
+    /// Present the given item as a potential unique item. The item is hashed
+    /// exactly as the C++ and Java libraries hash it, see [SketchItem].
+    #[inline]
+    pub fn update<T: SketchItem + ?Sized>(&mut self, item: &T) {
+        item.update_into(self);
     }
 
     wrap_mut!(
@@ -197,6 +472,26 @@ impl HllSketch {
         /// Indicates if the sketch is currently empty.
         pub fn is_empty() -> bool);
 
+    wrap!(
+        /// Returns the configured log2 of the number of buckets K.
+        pub fn get_lg_config_k() -> u8);
+
+    /// Returns the [HllType] the sketch uses once it reaches HLL mode.
+    #[inline]
+    pub fn get_target_type(&self) -> HllType {
+        self.0.get_target_type().into()
+    }
+
+    /// Describes the configuration of the sketch.
+    pub fn config(&self) -> SketchConfig {
+        SketchConfig {
+            family: Family::Hll,
+            lg_k: self.get_lg_config_k(),
+            hll_type: Some(self.get_target_type()),
+            seed: DEFAULT_SEED,
+        }
+    }
+
     wrap!(
         /// Returns the size of the sketch serialized in compact form.
         pub fn get_compact_serialization_bytes() -> u32);
@@ -205,9 +500,91 @@ impl HllSketch {
         pub fn get_updatable_serialization_bytes() -> u32);
 }
 
+impl ItemSink for HllSketch {
+    #[inline]
+    fn update_u64(&mut self, datum: u64) {
+        HllSketch::update_u64(self, datum)
+    }
+
+    #[inline]
+    fn update_i64(&mut self, datum: i64) {
+        HllSketch::update_i64(self, datum)
+    }
+
+    #[inline]
+    fn update_f64(&mut self, datum: f64) {
+        HllSketch::update_f64(self, datum)
+    }
+
+    #[inline]
+    fn update_bytes(&mut self, datum: &[u8]) {
+        HllSketch::update_bytes(self, datum)
+    }
+
+    #[inline]
+    fn update_u64_slice(&mut self, data: &[u64]) {
+        HllSketch::update_u64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_i64_slice(&mut self, data: &[i64]) {
+        HllSketch::update_i64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_f64_slice(&mut self, data: &[f64]) {
+        HllSketch::update_f64_slice(self, data)
+    }
+
+    #[inline]
+    fn update_packed_bytes(&mut self, data: &[u8], offsets: &[usize]) {
+        check_packed(data, offsets);
+        hll_sketch_update_strings(self.0.pin_mut(), data, offsets);
+    }
+}
+
+impl CardinalitySketch for HllSketch {
+    #[inline]
+    fn config(&self) -> SketchConfig {
+        HllSketch::config(self)
+    }
+
+    #[inline]
+    fn get_estimate(&self) -> f64 {
+        HllSketch::get_estimate(self)
+    }
+
+    #[inline]
+    fn get_lower_bound(&self, num_std_dev: u8) -> f64 {
+        HllSketch::get_lower_bound(self, num_std_dev)
+    }
+
+    #[inline]
+    fn get_upper_bound(&self, num_std_dev: u8) -> f64 {
+        HllSketch::get_upper_bound(self, num_std_dev)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        HllSketch::is_empty(self)
+    }
+
+    /// HLL sketches are serialized in their compact form.
+    #[inline]
+    fn serialize(&self) -> Vec<u8> {
+        self.serialize_compact(0)
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        inspect_family(bytes, Family::Hll)?;
+        Ok(Self(hll_sketch_deserialize(bytes)?))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hll::HllMode;
 
     fn dummy_hll() -> HllSketch {
         let mut hll = HllSketch::default();
@@ -239,4 +616,308 @@ mod tests {
             updatable_hll.to_string(true, true, true, true),
         )
     }
+
+    #[test]
+    fn generic_update() {
+        let hll = dummy_hll();
+
+        let mut generic_hll = HllSketch::default();
+        generic_hll.update(&-1i8);
+        generic_hll.update(&1u64);
+        generic_hll.update(&7i32);
+        assert!(hll == generic_hll);
+
+        let mut string_hll = HllSketch::default();
+        string_hll.update_string("datasketches");
+        let mut bytes_hll = HllSketch::default();
+        bytes_hll.update("datasketches".as_bytes());
+        assert!(string_hll == bytes_hll);
+    }
+
+    #[test]
+    fn batch_update() {
+        let integers: Vec<u64> = (0..1000).collect();
+        let strings: Vec<String> = integers.iter().map(|i| i.to_string()).collect();
+
+        let mut hll = HllSketch::default();
+        for (integer, string) in integers.iter().zip(&strings) {
+            hll.update_u64(*integer);
+            hll.update_string(string);
+        }
+        hll.update_string("");
+
+        let mut batch_hll = HllSketch::default();
+        batch_hll.update_u64_slice(&integers);
+        batch_hll.update_strings(strings.iter().map(String::as_str).chain([""]));
+        assert!(hll == batch_hll);
+    }
+
+    #[test]
+    fn hash_update() {
+        use crate::hash::*;
+
+        let mut hll = HllSketch::default();
+        let mut hashed_hll = HllSketch::default();
+        for i in 0..1000 {
+            hll.update_i64(i);
+            hll.update_f64(i as f64);
+            hll.update_string(&i.to_string());
+            hashed_hll.update_hash(hash_i64(i, DEFAULT_SEED));
+            hashed_hll.update_hash(hash_f64(i as f64, DEFAULT_SEED));
+            hashed_hll.update_hash(hash_str(&i.to_string(), DEFAULT_SEED));
+        }
+        assert_eq!(hll.serialize_updatable(), hashed_hll.serialize_updatable());
+
+        let mut union = crate::hll::HllUnion::default();
+        union.update_hash(hash_u64(42, DEFAULT_SEED));
+        let mut sketch = HllSketch::default();
+        sketch.update_u64(42);
+        assert_eq!(union.get_result(HllType::HLL4), sketch);
+    }
+
+    #[test]
+    fn equality() {
+        let hll = dummy_hll();
+        let compact_hll = HllSketch::deserialize(&hll.serialize_compact(0));
+        assert!(hll == compact_hll);
+        assert_eq!(hll.content_hash(), compact_hll.content_hash());
+        assert!(hll != HllSketch::default());
+        assert!(
+            HllSketch::new(10, HllType::HLL4, false) != HllSketch::new(11, HllType::HLL4, false)
+        );
+        assert!(
+            HllSketch::new(10, HllType::HLL4, true) == HllSketch::new(10, HllType::HLL8, false)
+        );
+
+        let mut hll4 = HllSketch::new(10, HllType::HLL4, false);
+        hll4.update_u64_slice(&(0..100_000).collect::<Vec<_>>());
+        let hll8 = hll4.clone_with_type(HllType::HLL8);
+        assert!(hll4 == hll8);
+        assert_eq!(hll4.content_hash(), hll8.content_hash());
+        assert_ne!(hll4.content_hash(), hll.content_hash());
+
+        let mut other = HllSketch::new(10, HllType::HLL8, false);
+        other.update_u64_slice(&(0..50_000).collect::<Vec<_>>());
+        assert!(hll8 != other);
+    }
+
+    #[test]
+    fn collect() {
+        let ids: Vec<u64> = (0..1000).collect();
+
+        let mut hll = HllSketch::default();
+        hll.update_u64_slice(&ids);
+        hll.extend(["a", "b", "c"]);
+
+        let mut collected_hll: HllSketch = ids.iter().collect();
+        collected_hll.extend(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert!(hll == collected_hll);
+    }
+
+    #[test]
+    fn config() {
+        let hll = HllSketch::new(10, HllType::HLL6, false);
+        assert_eq!(hll.get_lg_config_k(), 10);
+        assert_eq!(hll.get_target_type(), HllType::HLL6);
+
+        let hll8 = hll.clone_with_type(HllType::HLL8);
+        assert_eq!(hll8.get_target_type(), HllType::HLL8);
+        assert!(hll.config().is_compatible_with(&hll8.config()));
+        #[cfg(feature = "cpp")]
+        assert!(!hll
+            .config()
+            .is_compatible_with(&crate::cpc::sketch::CpcSketch::default().config()));
+    }
+
+    #[test]
+    fn sizing() {
+        let small = HllSketch::relative_error(10, 2, false, false).abs();
+        let large = HllSketch::relative_error(14, 2, false, false).abs();
+        assert!(large < small);
+
+        let sizing = HllSketch::lg_k_for_error(0.02, Confidence::TwoStdDev, HllType::HLL8).unwrap();
+        assert!(sizing.relative_error <= 0.02);
+        assert!(HllSketch::relative_error(sizing.lg_k - 1, 2, true, false).abs() > 0.02);
+        assert!(sizing.max_serialized_bytes >= 1 << sizing.lg_k);
+
+        assert!(HllSketch::lg_k_for_error(1e-6, Confidence::ThreeStdDev, HllType::HLL4).is_none());
+        assert!(
+            HllSketch::lg_k_for_error(f64::NAN, Confidence::OneStdDev, HllType::HLL4).is_none()
+        );
+
+        assert_eq!(Confidence::at_least(0.68), Some(Confidence::OneStdDev));
+        assert_eq!(Confidence::at_least(0.95), Some(Confidence::TwoStdDev));
+        assert_eq!(Confidence::at_least(0.997), Some(Confidence::ThreeStdDev));
+        assert_eq!(Confidence::at_least(0.999), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "num_std_dev must be 1, 2 or 3")]
+    fn relative_error_num_std_dev() {
+        HllSketch::relative_error(12, 4, false, false);
+    }
+
+    #[test]
+    fn summary() {
+        let summary = dummy_hll().summary();
+        assert_eq!(summary.lg_config_k, DEFAULT_LG_CONFIG_K);
+        assert_eq!(summary.target_type, HllType::HLL4);
+        assert_eq!(summary.mode, HllMode::List);
+        assert_eq!(summary.coupon_count, Some(3));
+        assert!(summary.array.is_none());
+        assert!(!summary.is_empty);
+
+        let mut hll = HllSketch::new(10, HllType::HLL8, false);
+        hll.update_u64_slice(&(0..10_000).collect::<Vec<_>>());
+        let summary = hll.summary();
+        assert_eq!(summary.mode, HllMode::Hll);
+        assert_eq!(summary.coupon_count, None);
+        let array = summary.array.unwrap();
+        assert_eq!(array.hip_accum, hll.get_estimate());
+        assert!(array.kxq0 + array.kxq1 > 0.0);
+        assert!(!summary.out_of_order);
+
+        assert!(HllSketch::default().summary().is_empty);
+    }
+
+    #[test]
+    fn registers() {
+        let hll = dummy_hll();
+        let coupons: Vec<_> = hll.coupons().collect();
+        assert_eq!(coupons.len(), 3);
+
+        let registers = hll.registers();
+        assert_eq!(registers.len(), 1 << DEFAULT_LG_CONFIG_K);
+        for coupon in &coupons {
+            assert!(registers[coupon.slot(DEFAULT_LG_CONFIG_K)] >= coupon.value);
+        }
+        assert_eq!(registers.iter().filter(|r| **r > 0).count(), 3);
+
+        for tgt_type in [HllType::HLL4, HllType::HLL6, HllType::HLL8] {
+            let mut hll = HllSketch::new(10, tgt_type, false);
+            hll.update_u64_slice(&(0..100_000).collect::<Vec<_>>());
+            assert_eq!(hll.coupons().count(), 0);
+
+            let registers = hll.registers();
+            let expected = hll.clone_with_type(HllType::HLL8).registers();
+            assert_eq!(registers, expected);
+
+            let rebuilt = HllSketch::from_registers(10, &registers, tgt_type);
+            assert_eq!(rebuilt.get_target_type(), tgt_type);
+            assert_eq!(rebuilt.registers(), registers);
+            let expected = hll.get_composite_estimate();
+            assert!((rebuilt.get_estimate() - expected).abs() < expected * 1e-9);
+            assert!(rebuilt.summary().out_of_order);
+        }
+
+        let empty = HllSketch::from_registers(10, &[0; 1 << 10], HllType::HLL4);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn small_lg_config_k() {
+        // the C++ library accepts an lg_config_k as small as 4
+        for n in [0u64, 3, 1000] {
+            let mut hll = HllSketch::new(4, HllType::HLL4, false);
+            hll.update_u64_slice(&(0..n).collect::<Vec<_>>());
+            for image in [hll.serialize_compact(0), hll.serialize_updatable()] {
+                let Ok(crate::SketchHeader::Hll(header)) = crate::inspect(&image) else {
+                    panic!("not an HLL image");
+                };
+                assert_eq!(header.lg_config_k, 4);
+                let sketch = <HllSketch as CardinalitySketch>::deserialize(&image).unwrap();
+                assert_eq!(sketch.registers(), hll.registers());
+                assert_eq!(HllSketch::deserialize(&image), sketch);
+            }
+        }
+    }
+
+    /// Images whose preamble is intact but whose body is inconsistent are
+    /// rejected by the native backend instead of panicking.
+    #[test]
+    #[cfg(feature = "pure-rust")]
+    fn corrupted() {
+        use crate::hll::format::*;
+
+        fn corrupt(image: &[u8], offset: usize, bytes: &[u8]) -> Result<HllSketch, Error> {
+            let mut image = image.to_vec();
+            image[offset..offset + bytes.len()].copy_from_slice(bytes);
+            <HllSketch as CardinalitySketch>::deserialize(&image)
+        }
+
+        let list = dummy_hll().serialize_compact(0);
+        assert!(corrupt(&list, LIST_COUNT_BYTE, &[8]).is_err());
+        assert!(corrupt(&list, LIST_COUNT_BYTE, &[255]).is_err());
+
+        let mut set = HllSketch::default();
+        set.update_u64_slice(&(0..100).collect::<Vec<_>>());
+        assert_eq!(set.summary().mode, HllMode::Set);
+        for image in [set.serialize_compact(0), set.serialize_updatable()] {
+            let count = u32::MAX.to_le_bytes();
+            assert!(corrupt(&image, HASH_SET_COUNT_INT, &count).is_err());
+            assert!(corrupt(&image, HASH_SET_COUNT_INT, &101u32.to_le_bytes()).is_err());
+        }
+        let compact = set.serialize_compact(0);
+        assert!(corrupt(&compact, HASH_SET_INT_ARR_START, &[0; 4]).is_err());
+        assert!(corrupt(&set.serialize_updatable(), LG_ARR_BYTE, &[255]).is_err());
+
+        // one register is kept in the auxiliary map of the HLL4 array
+        let mut registers = vec![1; 1 << 8];
+        registers[3] = 40;
+        let hll4 = HllSketch::from_registers(8, &registers, HllType::HLL4);
+        let aux_start = HLL_BYTE_ARR_START + (1 << 8) / 2;
+        // the pair of slot 3 comes first in the compact image and at its hash
+        // in the updatable one
+        for (image, aux_start) in [
+            (hll4.serialize_compact(0), aux_start),
+            (hll4.serialize_updatable(), aux_start + 3 * 4),
+        ] {
+            assert!(corrupt(&image, HLL_CUR_MIN_BYTE, &[255]).is_err());
+            assert!(corrupt(&image, CUR_MIN_COUNT_INT, &[0; 4]).is_err());
+            assert!(corrupt(&image, AUX_COUNT_INT, &[0; 4]).is_err());
+            assert!(corrupt(&image, AUX_COUNT_INT, &u32::MAX.to_le_bytes()).is_err());
+            assert!(corrupt(&image, HLL_BYTE_ARR_START, &[0xff]).is_err());
+            assert!(corrupt(&image, aux_start, &[0; 4]).is_err());
+            let low_value = (3u32 | 2 << KEY_BITS_26).to_le_bytes();
+            assert!(corrupt(&image, aux_start, &low_value).is_err());
+        }
+        assert!(corrupt(&hll4.serialize_updatable(), LG_ARR_BYTE, &[255]).is_err());
+
+        let hll8 = HllSketch::from_registers(8, &registers, HllType::HLL8);
+        let image = hll8.serialize_compact(0);
+        assert!(corrupt(&image, HLL_BYTE_ARR_START, &[255]).is_err());
+        assert!(corrupt(&image, CUR_MIN_COUNT_INT, &u32::MAX.to_le_bytes()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "cpp")]
+    fn from_cpc() {
+        for (lg_k, n) in [(10, 100), (10, 100_000), (8, 0), (12, 1000)] {
+            let items: Vec<u64> = (0..n).collect();
+            let mut cpc = CpcSketch::new(lg_k, DEFAULT_SEED);
+            cpc.update_u64_slice(&items);
+            let mut hll = HllSketch::new(lg_k, HllType::HLL8, false);
+            hll.update_u64_slice(&items);
+
+            let converted = HllSketch::from_cpc(&cpc, HllType::HLL6).unwrap();
+            assert_eq!(converted.get_lg_config_k(), lg_k);
+            assert_eq!(converted.get_target_type(), HllType::HLL6);
+            assert_eq!(converted.is_empty(), n == 0);
+            assert_eq!(converted.registers(), hll.registers());
+        }
+
+        let cpc = CpcSketch::new(10, 42);
+        assert!(matches!(
+            HllSketch::from_cpc(&cpc, HllType::HLL4),
+            Err(Error::IncompatibleSeed { .. })
+        ));
+        let cpc = CpcSketch::new(MAX_LG_CONFIG_K + 1, DEFAULT_SEED);
+        assert_eq!(
+            HllSketch::from_cpc(&cpc, HllType::HLL4),
+            Err(Error::UnsupportedLgK {
+                max: MAX_LG_CONFIG_K,
+                found: MAX_LG_CONFIG_K + 1
+            })
+        );
+    }
 }
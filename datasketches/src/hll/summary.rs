@@ -0,0 +1,189 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [HllSummary].
+
+use crate::config::Family;
+use crate::error::Error;
+use crate::format::{read_f64, read_u32};
+
+use super::{format::*, HllType};
+
+/// The representation an HLL sketch currently uses for its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HllMode {
+    /// Coupons are kept in a small list while the sketch warms up.
+    List,
+    /// Coupons are kept in a hash set.
+    Set,
+    /// The array of HLL registers is allocated.
+    Hll,
+}
+
+/// The state of the array of registers of an HLL sketch in [HllMode::Hll].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HllArraySummary {
+    /// The smallest value held by the registers. [HllType::HLL4] sketches
+    /// store the registers as offsets from this value.
+    pub cur_min: u8,
+    /// The number of registers holding `cur_min`.
+    pub num_at_cur_min: u32,
+    /// The accumulator of the HIP estimator.
+    pub hip_accum: f64,
+    /// The sum of `1 / 2^v` over the register values `v` below 32.
+    pub kxq0: f64,
+    /// The sum of `1 / 2^v` over the register values `v` from 32 up.
+    pub kxq1: f64,
+    /// The number of exceptions held in the auxiliary array of
+    /// [HllType::HLL4] sketches.
+    pub aux_count: u32,
+}
+
+/// A machine-readable summary of the state of an HLL sketch, see
+/// [HllSketch::summary](super::HllSketch::summary).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HllSummary {
+    /// The log2 of the number of buckets K.
+    pub lg_config_k: u8,
+    /// The target type of the sketch.
+    pub target_type: HllType,
+    /// The current representation of the sketch.
+    pub mode: HllMode,
+    /// Whether the sketch is empty.
+    pub is_empty: bool,
+    /// Whether the sketch went through union operations, in which case the
+    /// composite estimator is used instead of HIP.
+    pub out_of_order: bool,
+    /// The current cardinality estimate.
+    pub estimate: f64,
+    /// The number of coupons collected, only in [HllMode::List] and
+    /// [HllMode::Set].
+    pub coupon_count: Option<u32>,
+    /// The state of the registers, only in [HllMode::Hll].
+    pub array: Option<HllArraySummary>,
+}
+
+impl HllSummary {
+    /// Reads the summary out of a serialized image of a sketch with the given
+    /// estimate.
+    pub(crate) fn from_image(bytes: &[u8], estimate: f64) -> Self {
+        let flags = bytes[FLAGS_BYTE];
+        let (mode, coupon_count, array) = match cur_mode(bytes) {
+            CUR_MODE_LIST => (HllMode::List, Some(bytes[LIST_COUNT_BYTE] as u32), None),
+            CUR_MODE_SET => (
+                HllMode::Set,
+                Some(read_u32(bytes, HASH_SET_COUNT_INT)),
+                None,
+            ),
+            _ => (
+                HllMode::Hll,
+                None,
+                Some(HllArraySummary {
+                    cur_min: bytes[HLL_CUR_MIN_BYTE],
+                    num_at_cur_min: read_u32(bytes, CUR_MIN_COUNT_INT),
+                    hip_accum: read_f64(bytes, HIP_ACCUM_DOUBLE),
+                    kxq0: read_f64(bytes, KXQ0_DOUBLE),
+                    kxq1: read_f64(bytes, KXQ1_DOUBLE),
+                    aux_count: read_u32(bytes, AUX_COUNT_INT),
+                }),
+            ),
+        };
+
+        Self {
+            lg_config_k: bytes[LG_K_BYTE],
+            target_type: tgt_hll_type(bytes),
+            mode,
+            is_empty: flags & EMPTY_FLAG_MASK != 0,
+            out_of_order: flags & OUT_OF_ORDER_FLAG_MASK != 0,
+            estimate,
+            coupon_count,
+            array,
+        }
+    }
+}
+
+/// The preamble of a serialized HLL sketch, see [inspect](crate::inspect).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HllHeader {
+    /// The serialization version of the image.
+    pub serial_version: u8,
+    /// The log2 of the number of buckets K.
+    pub lg_config_k: u8,
+    /// The target type of the sketch.
+    pub target_type: HllType,
+    /// The representation of the sketch in the image.
+    pub mode: HllMode,
+    /// Whether the sketch is empty.
+    pub is_empty: bool,
+    /// Whether the image was serialized in compact form.
+    pub is_compact: bool,
+    /// Whether the sketch went through union operations.
+    pub out_of_order: bool,
+}
+
+impl HllHeader {
+    /// Parses and validates the preamble of an image whose family byte is
+    /// the one of HLL sketches.
+    pub(crate) fn from_image(bytes: &[u8]) -> Result<Self, Error> {
+        let serial_version = bytes[SER_VER_BYTE];
+        if serial_version != SER_VER {
+            return Err(Error::UnsupportedSerialVersion {
+                family: Family::Hll,
+                version: serial_version,
+            });
+        }
+
+        let (mode, pre_ints) = match cur_mode(bytes) {
+            CUR_MODE_LIST => (HllMode::List, LIST_PREINTS),
+            CUR_MODE_SET => (HllMode::Set, HASH_SET_PREINTS),
+            CUR_MODE_HLL => (HllMode::Hll, HLL_PREINTS),
+            _ => return Err(Error::InvalidImage("unknown HLL mode")),
+        };
+        if bytes[PREAMBLE_INTS_BYTE] != pre_ints {
+            return Err(Error::InvalidImage(
+                "HLL preamble size does not match its mode",
+            ));
+        }
+        let expected = pre_ints as usize * 4;
+        if bytes.len() < expected {
+            return Err(Error::Truncated {
+                expected,
+                found: bytes.len(),
+            });
+        }
+
+        let lg_config_k = bytes[LG_K_BYTE];
+        if !(MIN_LG_K..=MAX_LG_K).contains(&lg_config_k) {
+            return Err(Error::InvalidImage("HLL lg_config_k out of range"));
+        }
+        if (bytes[MODE_BYTE] >> 2) & 3 == 3 {
+            return Err(Error::InvalidImage("unknown HLL target type"));
+        }
+
+        let flags = bytes[FLAGS_BYTE];
+        Ok(Self {
+            serial_version,
+            lg_config_k,
+            target_type: tgt_hll_type(bytes),
+            mode,
+            is_empty: flags & EMPTY_FLAG_MASK != 0,
+            is_compact: flags & COMPACT_FLAG_MASK != 0,
+            out_of_order: flags & OUT_OF_ORDER_FLAG_MASK != 0,
+        })
+    }
+}
@@ -14,12 +14,12 @@
 
 //! Refer to [HllUnion].
 
+use crate::hash::{preimage, DEFAULT_SEED};
+use crate::item::{ItemSink, SketchItem};
 use crate::macros::*;
-use std::ops::Deref;
-
-use cxx::{let_cxx_string, UniquePtr};
-use datasketches_sys::hll::{ffi::*, DEFAULT_LG_CONFIG_K};
+use crate::traits::{Mergeable, SketchUnion};
 
+use super::backend::*;
 use super::{sketch::HllSketch, HllType};
 
 /// This performs union operations for HLL sketches. This union operator is configured with a
@@ -59,6 +59,23 @@ impl Clone for HllUnion {
     }
 }
 
+impl<'a> Extend<&'a HllSketch> for HllUnion {
+    fn extend<I: IntoIterator<Item = &'a HllSketch>>(&mut self, iter: I) {
+        for sketch in iter {
+            self.update_sketch(sketch);
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a HllSketch> for HllUnion {
+    /// Builds a union with the default configuration out of the given sketches.
+    fn from_iter<I: IntoIterator<Item = &'a HllSketch>>(iter: I) -> Self {
+        let mut union = Self::default();
+        union.extend(iter);
+        union
+    }
+}
+
 impl HllUnion {
     /// Construct an [HllUnion] operator with the given maximum log2 of k.
     ///
@@ -85,8 +102,32 @@ impl HllUnion {
     /// If the string is null or empty no update attempt is made and the method returns.
     #[inline]
     pub fn update_string(&mut self, datum: &str) {
-        let_cxx_string!(raw_datum = datum);
-        self.0.pin_mut().update_string(raw_datum.deref());
+        self.update_bytes(datum.as_bytes());
+    }
+
+    /// Present the given bytes as a potential unique item.
+    /// The bytes are hashed exactly as by the C++ `update(const void*, size_t)`
+    /// and the Java `update(byte[])` methods, so binary keys can be shared with
+    /// sketches built in other languages.
+    /// If the slice is empty no update attempt is made and the method returns.
+    #[inline]
+    pub fn update_bytes(&mut self, datum: &[u8]) {
+        hll_union_update_bytes(self.0.pin_mut(), datum);
+    }
+
+    /// Present an item by its hash, as returned by the functions of
+    /// [hash](crate::hash) with [DEFAULT_SEED](crate::hash::DEFAULT_SEED). The item is counted exactly as
+    /// if it had been presented itself.
+    #[inline]
+    pub fn update_hash(&mut self, hash: (u64, u64)) {
+        self.update_bytes(&preimage(hash, DEFAULT_SEED));
+    }
+
+    /// Present the given item as a potential unique item. The item is hashed
+    /// exactly as the C++ and Java libraries hash it, see [SketchItem].
+    #[inline]
+    pub fn update<T: SketchItem + ?Sized>(&mut self, item: &T) {
+        item.update_into(self);
     }
 
     wrap_mut!(
@@ -147,6 +188,72 @@ impl HllUnion {
     wrap!(
         /// Indicates if the union is currently empty.
         pub fn is_empty() -> bool);
+    wrap!(
+        /// Returns the current log2 of the number of buckets K, which is the
+        /// smallest of `lg_max_k` and the `lg_config_k` of the sketches merged so far.
+        pub fn get_lg_config_k() -> u8);
+
+    /// Returns the [HllType] of the internal sketch of the union.
+    #[inline]
+    pub fn get_target_type(&self) -> HllType {
+        self.0.get_target_type().into()
+    }
+}
+
+impl ItemSink for HllUnion {
+    #[inline]
+    fn update_u64(&mut self, datum: u64) {
+        HllUnion::update_u64(self, datum)
+    }
+
+    #[inline]
+    fn update_i64(&mut self, datum: i64) {
+        HllUnion::update_i64(self, datum)
+    }
+
+    #[inline]
+    fn update_f64(&mut self, datum: f64) {
+        HllUnion::update_f64(self, datum)
+    }
+
+    #[inline]
+    fn update_bytes(&mut self, datum: &[u8]) {
+        HllUnion::update_bytes(self, datum)
+    }
+}
+
+impl SketchUnion for HllUnion {
+    type Sketch = HllSketch;
+
+    #[inline]
+    fn update_sketch(&mut self, sketch: &HllSketch) {
+        HllUnion::update_sketch(self, sketch)
+    }
+
+    /// The result has the [HllType::HLL4] target type, like the C++ default.
+    #[inline]
+    fn get_result(&self) -> HllSketch {
+        HllUnion::get_result(self, HllType::HLL4)
+    }
+
+    #[inline]
+    fn get_estimate(&self) -> f64 {
+        HllUnion::get_estimate(self)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        HllUnion::is_empty(self)
+    }
+}
+
+impl Mergeable for HllSketch {
+    type Union = HllUnion;
+
+    #[inline]
+    fn new_union(&self) -> HllUnion {
+        HllUnion::new(self.get_lg_config_k())
+    }
 }
 
 #[cfg(test)]
@@ -176,6 +283,25 @@ mod tests {
         union.update_sketch(&a);
         union.update_sketch(&b);
         let estimate = union.get_estimate().floor();
-        assert!(estimate >= 224.0 && estimate <= 226.0);
+        assert!((224.0..=226.0).contains(&estimate));
+    }
+
+    #[test]
+    fn update_bytes() {
+        let mut hll = HllSketch::default();
+        hll.update_bytes(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let mut union = HllUnion::default();
+        union.update_bytes(&[0xde, 0xad, 0xbe, 0xef]);
+        union.update_bytes(&[]);
+        assert_eq!(union.get_estimate(), hll.get_estimate());
+    }
+
+    #[test]
+    fn collect() {
+        let sketches = [dummy_hll(200, 0), dummy_hll(50, 175)];
+        let union: HllUnion = sketches.iter().collect();
+        let estimate = union.get_estimate().floor();
+        assert!((224.0..=226.0).contains(&estimate));
     }
 }
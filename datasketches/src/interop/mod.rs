@@ -0,0 +1,24 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Imports of the sketches of other distinct counting libraries.
+//!
+//! Other libraries hash items with other functions than DataSketches, so
+//! their sketches can be converted into [HllSketch](crate::hll::HllSketch)es
+//! with the same accuracy, but count the same items in unrelated registers.
+//! Converted sketches must only be merged with sketches of items known to be
+//! disjoint from theirs. Redis HyperLogLogs are handled by
+//! [RedisHll](crate::hll::RedisHll).
+
+pub mod zetasketch;
@@ -0,0 +1,539 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [ZetaSketch].
+
+use crate::error::Error;
+use crate::hll::{ertl, HllSketch, HllType, MAX_LG_CONFIG_K};
+
+/// The `AggregatorType` of HLL++ sketches.
+const HYPERLOGLOG_PLUS_UNIQUE: u64 = 112;
+
+// Fields of `AggregatorStateProto`.
+const TYPE_FIELD: u32 = 1;
+const NUM_VALUES_FIELD: u32 = 2;
+/// The extension holding the `HyperLogLogPlusUniqueStateProto`.
+const HLL_STATE_FIELD: u32 = 112;
+
+// Fields of `HyperLogLogPlusUniqueStateProto`.
+const PRECISION_FIELD: u32 = 3;
+const SPARSE_PRECISION_FIELD: u32 = 4;
+const DATA_FIELD: u32 = 5;
+const SPARSE_DATA_FIELD: u32 = 6;
+
+const MIN_PRECISION: u8 = 10;
+const MAX_PRECISION: u8 = 24;
+const MAX_SPARSE_PRECISION: u8 = 25;
+/// The number of low bits of rho-encoded sparse values holding the register.
+const RHO_W_BITS: u32 = 6;
+
+/// An HLL++ sketch of ZetaSketch, the library behind the `HLL_COUNT`
+/// functions of BigQuery, as exported by `HLL_COUNT.INIT` or
+/// `HLL_COUNT.MERGE_PARTIAL`.
+///
+/// The registers of HLL++ sketches follow the same distribution as the ones
+/// of [HllSketch]es, so the sketches can be converted with [ZetaSketch::to_sketch]
+/// and then merged in an [HllUnion](crate::hll::HllUnion). ZetaSketch hashes
+/// items with Fingerprint2011 however, see [interop](crate::interop): BigQuery
+/// sketches must only be merged with each other, or with sketches of items
+/// disjoint from theirs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZetaSketch {
+    num_values: u64,
+    precision: u8,
+    sparse_precision: u8,
+    representation: Representation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Representation {
+    /// The sorted sparse values, each encoding an index at the sparse
+    /// precision or the index and register of a normal precision bucket.
+    Sparse(Vec<u32>),
+    /// One byte per register.
+    Dense(Vec<u8>),
+}
+
+impl ZetaSketch {
+    /// Parses a serialized `AggregatorStateProto` holding an HLL++ sketch.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let (mut aggregator_type, mut num_values, mut state) = (None, 0, None);
+        let mut fields = Fields(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match (field, value) {
+                (TYPE_FIELD, Value::Varint(value)) => aggregator_type = Some(value),
+                (NUM_VALUES_FIELD, Value::Varint(value)) => num_values = value,
+                (HLL_STATE_FIELD, Value::Bytes(bytes)) => state = Some(bytes),
+                _ => {}
+            }
+        }
+        if aggregator_type != Some(HYPERLOGLOG_PLUS_UNIQUE) {
+            return Err(Error::InvalidImage("not an HLL++ aggregator state"));
+        }
+
+        let (mut precision, mut sparse_precision) = (0, 0);
+        let (mut data, mut sparse_data) = (None, None);
+        let mut fields = Fields(state.ok_or(Error::InvalidImage("missing HLL++ state"))?);
+        while let Some((field, value)) = fields.next()? {
+            match (field, value) {
+                (PRECISION_FIELD, Value::Varint(value)) => precision = value,
+                (SPARSE_PRECISION_FIELD, Value::Varint(value)) => sparse_precision = value,
+                (DATA_FIELD, Value::Bytes(bytes)) => data = Some(bytes),
+                (SPARSE_DATA_FIELD, Value::Bytes(bytes)) => sparse_data = Some(bytes),
+                _ => {}
+            }
+        }
+
+        if !(MIN_PRECISION as u64..=MAX_PRECISION as u64).contains(&precision) {
+            return Err(Error::InvalidImage("HLL++ precision out of range"));
+        }
+        let precision = precision as u8;
+        // A sparse precision of 0 means that the sketch was never sparse.
+        if sparse_precision != 0
+            && !(precision as u64..=MAX_SPARSE_PRECISION as u64).contains(&sparse_precision)
+        {
+            return Err(Error::InvalidImage("HLL++ sparse precision out of range"));
+        }
+        let sparse_precision = sparse_precision as u8;
+
+        let mut sketch = Self {
+            num_values,
+            precision,
+            sparse_precision,
+            representation: Representation::Sparse(Vec::new()),
+        };
+        match (data, sparse_data) {
+            (Some(_), Some(_)) => {
+                return Err(Error::InvalidImage("HLL++ state both dense and sparse"));
+            }
+            (Some(data), None) => {
+                if data.len() != 1 << precision {
+                    return Err(Error::InvalidImage("HLL++ dense data of the wrong size"));
+                }
+                if data.iter().any(|value| *value > sketch.max_value()) {
+                    return Err(Error::InvalidImage("HLL++ register out of range"));
+                }
+                sketch.representation = Representation::Dense(data.to_vec());
+            }
+            (None, Some(sparse_data)) => {
+                if sparse_precision == 0 {
+                    return Err(Error::InvalidImage("HLL++ sparse data without precision"));
+                }
+                let values = sketch.sparse_values(sparse_data)?;
+                sketch.representation = Representation::Sparse(values);
+            }
+            // A sketch which was never sparse and holds no data is empty.
+            (None, None) if sparse_precision == 0 => {
+                sketch.representation = Representation::Dense(vec![0; 1 << precision]);
+            }
+            (None, None) => {}
+        }
+        Ok(sketch)
+    }
+
+    /// Decodes the difference encoded sparse values, checking that they are
+    /// sorted and that they address a register.
+    fn sparse_values(&self, sparse_data: &[u8]) -> Result<Vec<u32>, Error> {
+        let flag = self.rho_encoded_flag();
+        let mut reader = Fields(sparse_data);
+        let (mut values, mut last) = (Vec::new(), 0u64);
+        while !reader.0.is_empty() {
+            last = last.saturating_add(reader.varint()?);
+            if last >= u64::from(flag) << 1 {
+                return Err(Error::InvalidImage("HLL++ sparse value out of range"));
+            }
+            let value = last as u32;
+            let in_range = match value & flag {
+                0 => value >> self.sparse_precision == 0,
+                _ => (value ^ flag) >> RHO_W_BITS >> self.precision == 0,
+            };
+            if !in_range {
+                return Err(Error::InvalidImage("HLL++ sparse value out of range"));
+            }
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    /// Returns the number of values presented to the sketch, duplicates
+    /// included.
+    pub fn num_values(&self) -> u64 {
+        self.num_values
+    }
+
+    /// Returns the precision of the sketch, the log2 of its number of
+    /// registers.
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// Returns the precision of the sketch while sparse, 0 if the sketch was
+    /// never sparse.
+    pub fn sparse_precision(&self) -> u8 {
+        self.sparse_precision
+    }
+
+    /// Indicates if the sketch uses the sparse representation.
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.representation, Representation::Sparse(_))
+    }
+
+    /// Indicates if the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        match &self.representation {
+            Representation::Sparse(values) => values.is_empty(),
+            Representation::Dense(registers) => registers.iter().all(|r| *r == 0),
+        }
+    }
+
+    /// Returns the `2^precision` registers of the sketch. Sparse sketches are
+    /// converted to the normal precision, as ZetaSketch does when they grow.
+    pub fn registers(&self) -> Vec<u8> {
+        match &self.representation {
+            Representation::Dense(registers) => registers.clone(),
+            Representation::Sparse(values) => {
+                let mut registers = vec![0; 1 << self.precision];
+                for value in values {
+                    let (index, rho_w) = self.decode(*value);
+                    registers[index] = registers[index].max(rho_w);
+                }
+                registers
+            }
+        }
+    }
+
+    /// Returns the cardinality estimate. Sparse sketches are estimated with
+    /// linear counting at the sparse precision, like ZetaSketch does. Dense
+    /// sketches are estimated with the estimator of Otmar Ertl instead of
+    /// the empirically corrected one of ZetaSketch, so the estimate may
+    /// slightly differ from the one of BigQuery, within the error of the
+    /// sketch.
+    pub fn get_estimate(&self) -> f64 {
+        match &self.representation {
+            Representation::Dense(registers) => {
+                ertl::estimate(registers, 64 - self.precision as usize)
+            }
+            Representation::Sparse(values) => {
+                let shift = self.sparse_precision - self.precision;
+                let flag = self.rho_encoded_flag();
+                let mut indices: Vec<u32> = values
+                    .iter()
+                    .map(|value| match value & flag {
+                        0 => *value,
+                        _ => (value ^ flag) >> RHO_W_BITS << shift,
+                    })
+                    .collect();
+                indices.sort_unstable();
+                indices.dedup();
+
+                let buckets = (1u64 << self.sparse_precision) as f64;
+                let empty = buckets - indices.len() as f64;
+                buckets * (buckets / empty).ln()
+            }
+        }
+    }
+
+    /// Converts the sketch into an [HllSketch] of type [HllType::HLL8] and
+    /// `lg_config_k` equal to the precision of the sketch. Sketches more
+    /// precise than [MAX_LG_CONFIG_K] are downgraded to it, yielding the
+    /// registers ZetaSketch would have produced at that precision.
+    pub fn to_sketch(&self) -> HllSketch {
+        let lg_config_k = self.precision.min(MAX_LG_CONFIG_K);
+        let mut registers = self.registers();
+        if lg_config_k < self.precision {
+            registers = downgrade(&registers, self.precision - lg_config_k);
+        }
+        HllSketch::from_registers(lg_config_k, &registers, HllType::HLL8)
+    }
+
+    /// Returns the normal index and register of a sparse value.
+    fn decode(&self, value: u32) -> (usize, u8) {
+        let shift = self.sparse_precision - self.precision;
+        let flag = self.rho_encoded_flag();
+        if value & flag != 0 {
+            // The sparse-only bits of the index were zero, so the register
+            // was computed from the bits following the sparse index.
+            let value = value ^ flag;
+            let rho_w = (value & ((1 << RHO_W_BITS) - 1)) as u8 + shift;
+            ((value >> RHO_W_BITS) as usize, rho_w)
+        } else {
+            ((value >> shift) as usize, rho_w(value, shift))
+        }
+    }
+
+    /// Returns the flag of rho-encoded sparse values, above the bits of both
+    /// kinds of values.
+    fn rho_encoded_flag(&self) -> u32 {
+        1 << self.sparse_precision.max(self.precision + RHO_W_BITS as u8)
+    }
+
+    /// Returns the largest register of a dense sketch.
+    fn max_value(&self) -> u8 {
+        64 - self.precision + 1
+    }
+}
+
+/// Returns one plus the number of leading zeros of the `bits` low bits of
+/// `value`, or `bits + 1` if they are all zero.
+fn rho_w(value: u32, bits: u8) -> u8 {
+    let bits = u32::from(bits);
+    match u64::from(value).checked_shl(64 - bits) {
+        Some(w) if w != 0 => w.leading_zeros() as u8 + 1,
+        _ => bits as u8 + 1,
+    }
+}
+
+/// Lowers the precision of dense registers by `shift`. The low bits of the
+/// index become the leading bits of the hash the register is computed from.
+fn downgrade(registers: &[u8], shift: u8) -> Vec<u8> {
+    let mut downgraded = vec![0; registers.len() >> shift];
+    let mask = (1 << shift) - 1;
+    for (index, &value) in registers.iter().enumerate() {
+        if value == 0 {
+            continue;
+        }
+        let rho_w = match index as u32 & mask {
+            0 => shift + value,
+            low => rho_w(low, shift),
+        };
+        let register = &mut downgraded[index >> shift];
+        *register = (*register).max(rho_w);
+    }
+    downgraded
+}
+
+/// A protobuf field value.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Reads the fields of a serialized protobuf message.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Result<Option<(u32, Value<'a>)>, Error> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => self.skip(8)?,
+            2 => {
+                let len = self.varint()? as usize;
+                let bytes = self.0.get(..len).ok_or(TRUNCATED)?;
+                self.0 = &self.0[len..];
+                Value::Bytes(bytes)
+            }
+            5 => self.skip(4)?,
+            _ => return Err(Error::InvalidImage("unsupported protobuf wire type")),
+        };
+        Ok(Some(((key >> 3) as u32, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0;
+        for (i, &byte) in self.0.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err(TRUNCATED)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<Value<'a>, Error> {
+        self.0 = self.0.get(len..).ok_or(TRUNCATED)?;
+        Ok(Value::Fixed)
+    }
+}
+
+const TRUNCATED: Error = Error::InvalidImage("truncated protobuf message");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_u64;
+
+    fn hashes(n: u64) -> impl Iterator<Item = u64> {
+        (0..n).map(|i| hash_u64(i, 0).0)
+    }
+
+    /// Computes the registers of ZetaSketch: the index is taken from the
+    /// high bits of the hash and the register from the remaining ones.
+    fn dense(n: u64, precision: u8) -> Vec<u8> {
+        let mut registers = vec![0u8; 1 << precision];
+        for hash in hashes(n) {
+            let index = (hash >> (64 - precision)) as usize;
+            let w = hash << precision;
+            let rho_w = if w == 0 {
+                64 - precision + 1
+            } else {
+                w.leading_zeros() as u8 + 1
+            };
+            registers[index] = registers[index].max(rho_w);
+        }
+        registers
+    }
+
+    /// Encodes the hashes like the sparse representation of ZetaSketch.
+    fn sparse(n: u64, precision: u8, sparse_precision: u8) -> Vec<u8> {
+        let flag = 1u32 << sparse_precision.max(precision + RHO_W_BITS as u8);
+        let shift = sparse_precision - precision;
+        let mut values: Vec<u32> = hashes(n)
+            .map(|hash| {
+                let sparse_index = (hash >> (64 - sparse_precision)) as u32;
+                if sparse_index & ((1 << shift) - 1) != 0 {
+                    return sparse_index;
+                }
+                let w = hash << sparse_precision;
+                let rho_w = if w == 0 {
+                    64 - sparse_precision as u32 + 1
+                } else {
+                    w.leading_zeros() + 1
+                };
+                flag | (sparse_index >> shift) << RHO_W_BITS | rho_w
+            })
+            .collect();
+        values.sort_unstable();
+        values.dedup();
+
+        let mut bytes = Vec::new();
+        let mut last = 0;
+        for value in values {
+            varint(&mut bytes, u64::from(value - last));
+            last = value;
+        }
+        bytes
+    }
+
+    fn varint(bytes: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+    }
+
+    fn field(bytes: &mut Vec<u8>, field: u32, data: &[u8]) {
+        varint(bytes, u64::from(field) << 3 | 2);
+        varint(bytes, data.len() as u64);
+        bytes.extend_from_slice(data);
+    }
+
+    fn image(n: u64, precision: u8, sparse_precision: u8, data: Option<Vec<u8>>) -> Vec<u8> {
+        let mut state = Vec::new();
+        varint(&mut state, u64::from(PRECISION_FIELD) << 3);
+        varint(&mut state, precision.into());
+        varint(&mut state, u64::from(SPARSE_PRECISION_FIELD) << 3);
+        varint(&mut state, sparse_precision.into());
+        match data {
+            Some(data) => field(&mut state, DATA_FIELD, &data),
+            None => field(
+                &mut state,
+                SPARSE_DATA_FIELD,
+                &sparse(n, precision, sparse_precision),
+            ),
+        }
+
+        let mut bytes = vec![TYPE_FIELD as u8 * 8, 112, NUM_VALUES_FIELD as u8 * 8];
+        varint(&mut bytes, n);
+        // encoding_version, which is not read.
+        bytes.extend_from_slice(&[3 * 8, 2]);
+        field(&mut bytes, HLL_STATE_FIELD, &state);
+        bytes
+    }
+
+    #[test]
+    fn dense_sketches() {
+        let registers = dense(100_000, 15);
+        let sketch =
+            ZetaSketch::deserialize(&image(100_000, 15, 20, Some(registers.clone()))).unwrap();
+        assert_eq!(sketch.num_values(), 100_000);
+        assert_eq!((sketch.precision(), sketch.sparse_precision()), (15, 20));
+        assert!(!sketch.is_sparse() && !sketch.is_empty());
+        assert_eq!(sketch.registers(), registers);
+        assert!((sketch.get_estimate() - 100_000.0).abs() < 100_000.0 * 0.02);
+
+        let hll = sketch.to_sketch();
+        assert_eq!(hll.get_lg_config_k(), 15);
+        assert_eq!(hll.get_target_type(), HllType::HLL8);
+        assert_eq!(hll.registers(), registers);
+        assert!((hll.get_estimate() - 100_000.0).abs() < 100_000.0 * 0.02);
+
+        let precise =
+            ZetaSketch::deserialize(&image(10_000, 24, 0, Some(dense(10_000, 24)))).unwrap();
+        let hll = precise.to_sketch();
+        assert_eq!(hll.get_lg_config_k(), MAX_LG_CONFIG_K);
+        assert_eq!(hll.registers(), dense(10_000, MAX_LG_CONFIG_K));
+
+        // an empty sketch which was never sparse, without data
+        let mut state = Vec::new();
+        varint(&mut state, u64::from(PRECISION_FIELD) << 3);
+        varint(&mut state, 15);
+        let mut bytes = vec![TYPE_FIELD as u8 * 8, 112];
+        field(&mut bytes, HLL_STATE_FIELD, &state);
+        let empty = ZetaSketch::deserialize(&bytes).unwrap();
+        assert_eq!(empty.sparse_precision(), 0);
+        assert!(!empty.is_sparse() && empty.is_empty());
+        assert_eq!(empty.get_estimate(), 0.0);
+        assert!(empty.to_sketch().is_empty());
+    }
+
+    #[test]
+    fn sparse_sketches() {
+        let sketch = ZetaSketch::deserialize(&image(1000, 15, 25, None)).unwrap();
+        assert!(sketch.is_sparse());
+        assert_eq!(sketch.registers(), dense(1000, 15));
+        assert!((sketch.get_estimate() - 1000.0).abs() < 1000.0 * 0.01);
+        assert_eq!(sketch.to_sketch().registers(), dense(1000, 15));
+
+        let empty = ZetaSketch::deserialize(&image(0, 15, 20, None)).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.get_estimate(), 0.0);
+        assert!(empty.to_sketch().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let mut theta = image(10, 15, 20, None);
+        theta[1] = 100;
+        assert_eq!(
+            ZetaSketch::deserialize(&theta),
+            Err(Error::InvalidImage("not an HLL++ aggregator state"))
+        );
+
+        let valid = image(10, 15, 20, None);
+        assert_eq!(
+            ZetaSketch::deserialize(&valid[..valid.len() - 1]),
+            Err(TRUNCATED)
+        );
+
+        assert!(ZetaSketch::deserialize(&image(10, 15, 20, Some(vec![0; 100]))).is_err());
+        assert!(ZetaSketch::deserialize(&image(10, 8, 20, None)).is_err());
+        let mut sparse_precision = image(10, 15, 20, None);
+        let at = sparse_precision
+            .windows(2)
+            .position(|w| w == [(SPARSE_PRECISION_FIELD << 3) as u8, 20])
+            .unwrap();
+        sparse_precision[at + 1] = 0;
+        assert_eq!(
+            ZetaSketch::deserialize(&sparse_precision),
+            Err(Error::InvalidImage("HLL++ sparse data without precision"))
+        );
+    }
+}
@@ -0,0 +1,498 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [SketchItem].
+
+/// A value that can be presented to a cardinality sketch or union as a
+/// potential unique item.
+///
+/// Items are hashed exactly as the C++ and Java libraries hash them, so a
+/// sketch built in Rust can be merged with one built in another language:
+///
+/// - integers are widened to 64 bits (unsigned integers narrower than 64 bits
+///   are sign-extended through their signed counterpart, like the C++
+///   `update` overloads do) and hashed as 8 little-endian bytes;
+/// - floating point values are widened to `f64`, `-0.0` is canonicalized to
+///   `0.0` and every NaN to `0x7ff8000000000000`;
+/// - strings are hashed as their UTF-8 bytes and byte slices as-is. Empty
+///   strings and slices are ignored.
+///
+/// User types can implement the trait with the [sketch_item!](crate::sketch_item)
+/// macro, which hashes the concatenation of the encodings of their fields.
+pub trait SketchItem {
+    /// Appends the canonical byte encoding of this item to `buf`.
+    fn write_bytes(&self, buf: &mut Vec<u8>);
+
+    /// Presents this item to the given sketch or union.
+    ///
+    /// The default implementation hashes the bytes produced by
+    /// [SketchItem::write_bytes].
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        let mut buf = Vec::new();
+        self.write_bytes(&mut buf);
+        sink.update_bytes(&buf);
+    }
+}
+
+/// The hashing entry points shared by every sketch and union that counts
+/// distinct items. Each method hashes its input exactly as the matching C++
+/// `update` overload does.
+pub trait ItemSink {
+    /// Present the given unsigned 64-bit integer as a potential unique item.
+    fn update_u64(&mut self, datum: u64);
+
+    /// Present the given signed 64-bit integer as a potential unique item.
+    fn update_i64(&mut self, datum: i64);
+
+    /// Present the given 64-bit floating point value as a potential unique item.
+    fn update_f64(&mut self, datum: f64);
+
+    /// Present the given bytes as a potential unique item.
+    /// If the slice is empty no update attempt is made and the method returns.
+    fn update_bytes(&mut self, datum: &[u8]);
+
+    /// Present every unsigned 64-bit integer in the slice as a potential
+    /// unique item. Sketches backed by the C++ library hand the whole slice
+    /// to it in a single call.
+    #[inline]
+    fn update_u64_slice(&mut self, data: &[u64]) {
+        for datum in data {
+            self.update_u64(*datum);
+        }
+    }
+
+    /// Present every signed 64-bit integer in the slice as a potential unique
+    /// item, see [ItemSink::update_u64_slice].
+    #[inline]
+    fn update_i64_slice(&mut self, data: &[i64]) {
+        for datum in data {
+            self.update_i64(*datum);
+        }
+    }
+
+    /// Present every 64-bit floating point value in the slice as a potential
+    /// unique item, see [ItemSink::update_u64_slice].
+    #[inline]
+    fn update_f64_slice(&mut self, data: &[f64]) {
+        for datum in data {
+            self.update_f64(*datum);
+        }
+    }
+
+    /// Present the byte strings packed back to back in `data` as potential
+    /// unique items, the i-th one spanning `data[offsets[i]..offsets[i + 1]]`.
+    /// Empty byte strings are skipped like by [ItemSink::update_bytes].
+    ///
+    /// Panics if the offsets decrease or go past the end of `data`.
+    #[inline]
+    fn update_packed_bytes(&mut self, data: &[u8], offsets: &[usize]) {
+        for bounds in offsets.windows(2) {
+            self.update_bytes(&data[bounds[0]..bounds[1]]);
+        }
+    }
+}
+
+/// Returns the bit pattern hashed for `datum`, canonicalizing `-0.0` and NaN
+/// like the C++ and Java libraries.
+#[inline]
+pub(crate) fn canonical_f64_bits(datum: f64) -> u64 {
+    if datum == 0.0 {
+        0
+    } else if datum.is_nan() {
+        0x7ff8000000000000
+    } else {
+        datum.to_bits()
+    }
+}
+
+/// Packs strings back to back into a single buffer, returning it together
+/// with the `n + 1` offsets delimiting each of the `n` strings. This lets a
+/// batch of strings cross the FFI boundary as two slices.
+pub(crate) fn pack_strings<'a>(data: impl IntoIterator<Item = &'a str>) -> (Vec<u8>, Vec<usize>) {
+    let mut buf = Vec::new();
+    let mut offsets = vec![0];
+    for datum in data {
+        buf.extend_from_slice(datum.as_bytes());
+        offsets.push(buf.len());
+    }
+    (buf, offsets)
+}
+
+/// Checks the offsets given to [ItemSink::update_packed_bytes] before they are
+/// handed to the C++ library, which does not bounds check them.
+pub(crate) fn check_packed(data: &[u8], offsets: &[usize]) {
+    assert!(
+        offsets.windows(2).all(|bounds| bounds[0] <= bounds[1])
+            && offsets.last().copied().unwrap_or(0) <= data.len(),
+        "invalid offsets of packed byte strings"
+    );
+}
+
+/// Appends the encoding of a field of a composite item to `buf`, prefixed by
+/// its length so that adjacent variable-length fields cannot collide.
+#[doc(hidden)]
+pub fn write_field<T: SketchItem + ?Sized>(field: &T, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 8]);
+    field.write_bytes(buf);
+    let len = (buf.len() - start - 8) as u64;
+    buf[start..start + 8].copy_from_slice(&len.to_le_bytes());
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),+) => {
+        $(
+            impl SketchItem for $t {
+                #[inline]
+                fn write_bytes(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&(*self as i64).to_le_bytes());
+                }
+
+                #[inline]
+                fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+                    sink.update_i64(*self as i64);
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty => $s:ty),+) => {
+        $(
+            impl SketchItem for $t {
+                #[inline]
+                fn write_bytes(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&(*self as $s as i64).to_le_bytes());
+                }
+
+                #[inline]
+                fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+                    sink.update_i64(*self as $s as i64);
+                }
+            }
+        )+
+    };
+}
+
+impl_signed!(i8, i16, i32, i64, isize);
+impl_unsigned!(u8 => i8, u16 => i16, u32 => i32);
+
+impl SketchItem for u64 {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        sink.update_u64(*self);
+    }
+}
+
+impl SketchItem for usize {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        (*self as u64).write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        sink.update_u64(*self as u64);
+    }
+}
+
+impl SketchItem for bool {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        (*self as u8).write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        (*self as u8).update_into(sink)
+    }
+}
+
+impl SketchItem for char {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        (*self as u32).write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        (*self as u32).update_into(sink)
+    }
+}
+
+impl SketchItem for f64 {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&canonical_f64_bits(*self).to_le_bytes());
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        sink.update_f64(*self);
+    }
+}
+
+impl SketchItem for f32 {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        (*self as f64).write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        sink.update_f64(*self as f64);
+    }
+}
+
+impl SketchItem for u128 {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        sink.update_bytes(&self.to_le_bytes());
+    }
+}
+
+impl SketchItem for i128 {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        sink.update_bytes(&self.to_le_bytes());
+    }
+}
+
+impl SketchItem for [u8] {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        sink.update_bytes(self);
+    }
+}
+
+impl<const N: usize> SketchItem for [u8; N] {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        self.as_slice().write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        self.as_slice().update_into(sink)
+    }
+}
+
+impl SketchItem for Vec<u8> {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        self.as_slice().write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        self.as_slice().update_into(sink)
+    }
+}
+
+impl SketchItem for str {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        self.as_bytes().write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        self.as_bytes().update_into(sink)
+    }
+}
+
+impl SketchItem for String {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        self.as_str().write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        self.as_str().update_into(sink)
+    }
+}
+
+impl<T: SketchItem + ?Sized> SketchItem for &T {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        (**self).write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        (**self).update_into(sink)
+    }
+}
+
+impl<T: SketchItem + ?Sized> SketchItem for Box<T> {
+    #[inline]
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        (**self).write_bytes(buf)
+    }
+
+    #[inline]
+    fn update_into<S: ItemSink + ?Sized>(&self, sink: &mut S) {
+        (**self).update_into(sink)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: SketchItem),+> SketchItem for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn write_bytes(&self, buf: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $(write_field($name, buf);)+
+            }
+        }
+    };
+}
+
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+
+/// Implements [SketchItem] for a struct by hashing the encodings of the
+/// listed fields, in order, as a single item.
+///
+/// ```
+/// use datasketches::hll::HllSketch;
+///
+/// struct Visit {
+///     user_id: u64,
+///     page: String,
+/// }
+///
+/// datasketches::sketch_item!(Visit { user_id, page });
+///
+/// let mut hll = HllSketch::default();
+/// hll.update(&Visit { user_id: 1, page: "/".into() });
+/// ```
+#[macro_export]
+macro_rules! sketch_item {
+    ($ty:ty { $($field:tt),+ $(,)? }) => {
+        impl $crate::SketchItem for $ty {
+            fn write_bytes(&self, buf: &mut ::std::vec::Vec<u8>) {
+                $($crate::item::write_field(&self.$field, buf);)+
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Vec<Vec<u8>>);
+
+    impl ItemSink for Recorder {
+        fn update_u64(&mut self, datum: u64) {
+            self.0.push(datum.to_le_bytes().to_vec());
+        }
+
+        fn update_i64(&mut self, datum: i64) {
+            self.0.push(datum.to_le_bytes().to_vec());
+        }
+
+        fn update_f64(&mut self, datum: f64) {
+            self.0
+                .push(canonical_f64_bits(datum).to_le_bytes().to_vec());
+        }
+
+        fn update_bytes(&mut self, datum: &[u8]) {
+            if !datum.is_empty() {
+                self.0.push(datum.to_vec());
+            }
+        }
+    }
+
+    fn encoded<T: SketchItem + ?Sized>(item: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        item.write_bytes(&mut buf);
+        buf
+    }
+
+    fn hashed<T: SketchItem + ?Sized>(item: &T) -> Vec<Vec<u8>> {
+        let mut recorder = Recorder::default();
+        item.update_into(&mut recorder);
+        recorder.0
+    }
+
+    #[test]
+    fn widening() {
+        assert_eq!(encoded(&-1i8), (-1i64).to_le_bytes());
+        assert_eq!(encoded(&u32::MAX), (-1i64).to_le_bytes());
+        assert_eq!(encoded(&1.5f32), 1.5f64.to_bits().to_le_bytes());
+        assert_eq!(encoded(&-0.0f64), 0u64.to_le_bytes());
+        assert_eq!(encoded(&f64::NAN), 0x7ff8000000000000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn encoding_matches_direct_updates() {
+        assert_eq!(hashed(&7u16), vec![encoded(&7u16)]);
+        assert_eq!(hashed(&-0.0f32), vec![encoded(&-0.0f32)]);
+        assert_eq!(hashed("abc"), vec![encoded("abc")]);
+        assert_eq!(hashed(&u128::MAX), vec![encoded(&u128::MAX)]);
+        assert!(hashed("").is_empty());
+    }
+
+    #[test]
+    fn packing() {
+        let (buf, offsets) = pack_strings(["ab", "", "c"]);
+        assert_eq!(buf, b"abc");
+        assert_eq!(offsets, [0, 2, 2, 3]);
+    }
+
+    struct Visit {
+        user_id: u64,
+        page: String,
+    }
+
+    sketch_item!(Visit { user_id, page });
+
+    #[test]
+    fn composite() {
+        let visit = Visit {
+            user_id: 1,
+            page: "/".to_string(),
+        };
+        assert_eq!(encoded(&visit), encoded(&(1u64, "/")));
+        assert_ne!(encoded(&("ab", "c")), encoded(&("a", "bc")));
+        assert_eq!(hashed(&visit), vec![encoded(&visit)]);
+    }
+}
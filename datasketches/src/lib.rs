@@ -15,6 +15,36 @@
 #![deny(missing_docs)]
 #![doc = include_str!("../../README.md")]
 
+#[cfg(not(any(feature = "cpp", feature = "pure-rust")))]
+compile_error!("either the `cpp` or the `pure-rust` feature must be enabled to back HLL sketches");
+
+#[cfg(feature = "cpp")]
+pub mod any;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod config;
 pub mod cpc;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+pub mod error;
+mod format;
+pub mod hash;
+pub mod header;
 pub mod hll;
+pub mod interop;
+pub mod item;
 mod macros;
+#[cfg(feature = "polars")]
+pub mod polars;
+#[cfg(feature = "postgres")]
+mod postgres;
+pub mod traits;
+pub mod window;
+
+#[cfg(feature = "cpp")]
+pub use any::{AnySketch, AnyUnion};
+pub use config::{Confidence, Family, Sizing, SketchConfig};
+pub use error::Error;
+pub use header::{inspect, SketchHeader};
+pub use item::{ItemSink, SketchItem};
+pub use traits::{CardinalitySketch, Mergeable, SketchUnion};
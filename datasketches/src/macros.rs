@@ -32,5 +32,19 @@ macro_rules! wrap_mut {
     };
 }
 
+#[cfg(feature = "cpp")]
+macro_rules! delegate_merge_mut {
+    ($(#[$attr:meta])* pub fn $name:tt($($v:tt: $t:ty),+) -> ()) => {
+        #[inline]
+        $(#[$attr])*
+        pub fn $name(&mut self, $($v: $t),+) {
+            self.1.$name($($v),+);
+            self.merge_items();
+        }
+    };
+}
+
+#[cfg(feature = "cpp")]
+pub(crate) use delegate_merge_mut;
 pub(crate) use wrap;
 pub(crate) use wrap_mut;
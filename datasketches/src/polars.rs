@@ -0,0 +1,338 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Polars](https://pola.rs) expressions building, merging and estimating
+//! sketches, refer to [SketchExpr].
+
+use datasketches_sys::{cpc, hll};
+use polars::prelude::*;
+
+use crate::config::Family;
+use crate::cpc::{sketch::CpcSketch, union::CpcUnion};
+use crate::header::inspect_family;
+use crate::hll::{HllSketch, HllType, HllUnion};
+use crate::item::{ItemSink, SketchItem};
+use crate::traits::CardinalitySketch;
+
+/// Presents every non-null value of `column` to `sink`, hashing values like
+/// the matching Rust types would be by [SketchItem].
+fn update_column<S: ItemSink>(sink: &mut S, column: &Column) -> PolarsResult<()> {
+    macro_rules! update {
+        ($chunked:expr) => {
+            for value in $chunked.into_iter().flatten() {
+                value.update_into(sink);
+            }
+        };
+    }
+
+    let series = column.as_materialized_series();
+    let physical = series.to_physical_repr();
+    match physical.dtype() {
+        DataType::Null => {}
+        DataType::Boolean => update!(physical.bool()?),
+        DataType::Int8 => update!(physical.i8()?),
+        DataType::Int16 => update!(physical.i16()?),
+        DataType::Int32 => update!(physical.i32()?),
+        DataType::Int64 => update!(physical.i64()?),
+        DataType::UInt8 => update!(physical.u8()?),
+        DataType::UInt16 => update!(physical.u16()?),
+        DataType::UInt32 => update!(physical.u32()?),
+        DataType::UInt64 => update!(physical.u64()?),
+        DataType::Float32 => update!(physical.f32()?),
+        DataType::Float64 => update!(physical.f64()?),
+        DataType::String => update!(physical.str()?),
+        DataType::Binary => update!(physical.binary()?),
+        dtype => polars_bail!(InvalidOperation: "cannot sketch values of type {dtype}"),
+    }
+    Ok(())
+}
+
+/// Returns the non-null sketch images of `column`.
+fn images(column: &Column) -> PolarsResult<impl Iterator<Item = &[u8]>> {
+    Ok(column.binary()?.into_iter().flatten())
+}
+
+/// Returns the largest `lg_k` of the sketch images of `column`, which must
+/// all hold sketches of `family`.
+fn max_lg_k(column: &Column, family: Family, default: u8) -> PolarsResult<u8> {
+    let mut max = None;
+    for image in images(column)? {
+        let header = inspect_family(image, family)
+            .map_err(|err| polars_err!(ComputeError: "invalid {family:?} sketch image: {err}"))?;
+        max = max.max(Some(header.lg_k()));
+    }
+    Ok(max.unwrap_or(default))
+}
+
+/// Fails unless `lg_k` is within the bounds of the sketches of `family`,
+/// which the C++ library would abort on.
+fn check_lg_k(family: Family, lg_k: u8, min: u8, max: u8) -> PolarsResult<()> {
+    if !(min..=max).contains(&lg_k) {
+        polars_bail!(ComputeError: "the lg_k of {family:?} sketches must be between {min} and {max}, got {lg_k}");
+    }
+    Ok(())
+}
+
+/// Reconstructs a sketch of `family` from an image of a column.
+fn deserialize<S: CardinalitySketch>(family: Family, image: &[u8]) -> PolarsResult<S> {
+    S::deserialize(image)
+        .map_err(|err| polars_err!(ComputeError: "invalid {family:?} sketch image: {err}"))
+}
+
+fn binary_column(name: PlSmallStr, image: Vec<u8>) -> Column {
+    BinaryChunked::from_slice(name, &[image.as_slice()]).into_column()
+}
+
+fn binary_field(_: &Schema, field: &Field) -> PolarsResult<Field> {
+    Ok(Field::new(field.name().clone(), DataType::Binary))
+}
+
+fn float_field(_: &Schema, field: &Field) -> PolarsResult<Field> {
+    Ok(Field::new(field.name().clone(), DataType::Float64))
+}
+
+/// Maps every sketch image of `column` to the estimate of its sketch of
+/// `family`, keeping nulls.
+fn estimates<S: CardinalitySketch>(column: Column, family: Family) -> PolarsResult<Column> {
+    let estimates: Float64Chunked = column
+        .binary()?
+        .into_iter()
+        .map(|image| {
+            image
+                .map(|image| deserialize::<S>(family, image).map(|sketch| sketch.get_estimate()))
+                .transpose()
+        })
+        .collect::<PolarsResult<_>>()?;
+    Ok(estimates.with_name(column.name().clone()).into_column())
+}
+
+/// Sketching expressions, implemented for [Expr].
+///
+/// The `*_sketch` and `*_union` expressions are aggregations: they produce a
+/// single sketch image for the whole column, or one per group within a
+/// `group_by`. Null values and null sketches are skipped. The `*_estimate`
+/// expressions map each sketch image of a `Binary` column to its estimate.
+pub trait SketchExpr {
+    /// Builds an [HllType::HLL4] [HllSketch] configured with `lg_config_k`
+    /// out of the values, returning its compact image.
+    fn hll_sketch(self, lg_config_k: u8) -> Expr;
+
+    /// Merges HLL sketch images with an [HllUnion] sized for the largest
+    /// `lg_config_k` among them, returning the compact image of the result.
+    fn hll_union(self) -> Expr;
+
+    /// Returns the estimate of each HLL sketch image.
+    fn hll_estimate(self) -> Expr;
+
+    /// Builds a [CpcSketch] configured with `lg_k` out of the values,
+    /// returning its image.
+    fn cpc_sketch(self, lg_k: u8) -> Expr;
+
+    /// Merges CPC sketch images with a [CpcUnion], returning the image of the
+    /// result.
+    fn cpc_union(self) -> Expr;
+
+    /// Returns the estimate of each CPC sketch image.
+    fn cpc_estimate(self) -> Expr;
+}
+
+impl SketchExpr for Expr {
+    fn hll_sketch(self, lg_config_k: u8) -> Expr {
+        self.agg_with_fmt_str(
+            move |column| {
+                check_lg_k(
+                    Family::Hll,
+                    lg_config_k,
+                    hll::MIN_LG_CONFIG_K,
+                    hll::MAX_LG_CONFIG_K,
+                )?;
+                let mut hll = HllSketch::new(lg_config_k, HllType::HLL4, false);
+                update_column(&mut hll, &column)?;
+                Ok(binary_column(
+                    column.name().clone(),
+                    hll.serialize_compact(0),
+                ))
+            },
+            binary_field,
+            "hll_sketch",
+        )
+    }
+
+    fn hll_union(self) -> Expr {
+        self.agg_with_fmt_str(
+            |column| {
+                let lg_max_k = max_lg_k(&column, Family::Hll, hll::DEFAULT_LG_CONFIG_K)?;
+                let mut union = HllUnion::new(lg_max_k);
+                for image in images(&column)? {
+                    union.update_sketch(&deserialize::<HllSketch>(Family::Hll, image)?);
+                }
+                let result = union.get_result(HllType::HLL4);
+                Ok(binary_column(
+                    column.name().clone(),
+                    result.serialize_compact(0),
+                ))
+            },
+            binary_field,
+            "hll_union",
+        )
+    }
+
+    fn hll_estimate(self) -> Expr {
+        self.map_with_fmt_str(
+            |column| estimates::<HllSketch>(column, Family::Hll),
+            float_field,
+            "hll_estimate",
+        )
+    }
+
+    fn cpc_sketch(self, lg_k: u8) -> Expr {
+        self.agg_with_fmt_str(
+            move |column| {
+                check_lg_k(Family::Cpc, lg_k, cpc::MIN_LG_K, cpc::MAX_LG_K)?;
+                let mut cpc = CpcSketch::new(lg_k, cpc::DEFAULT_SEED);
+                update_column(&mut cpc, &column)?;
+                Ok(binary_column(column.name().clone(), cpc.serialize()))
+            },
+            binary_field,
+            "cpc_sketch",
+        )
+    }
+
+    fn cpc_union(self) -> Expr {
+        self.agg_with_fmt_str(
+            |column| {
+                let lg_k = max_lg_k(&column, Family::Cpc, cpc::DEFAULT_LG_K)?;
+                let mut union = CpcUnion::new(lg_k, cpc::DEFAULT_SEED);
+                for image in images(&column)? {
+                    union
+                        .try_update_sketch(&deserialize::<CpcSketch>(Family::Cpc, image)?)
+                        .map_err(
+                            |err| polars_err!(ComputeError: "cannot merge CPC sketch: {err}"),
+                        )?;
+                }
+                Ok(binary_column(
+                    column.name().clone(),
+                    union.get_result().serialize(),
+                ))
+            },
+            binary_field,
+            "cpc_union",
+        )
+    }
+
+    fn cpc_estimate(self) -> Expr {
+        self.map_with_fmt_str(
+            |column| estimates::<CpcSketch>(column, Family::Cpc),
+            float_field,
+            "cpc_estimate",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> DataFrame {
+        df![
+            "group" => ["a", "a", "b", "b", "b"],
+            "user_id" => [Some(1i64), Some(2), Some(1), None, Some(3)],
+            "name" => ["x", "y", "x", "x", "z"],
+        ]
+        .unwrap()
+    }
+
+    fn estimates_by_group(sketch: Expr, estimate: fn(Expr) -> Expr) -> Vec<f64> {
+        let result = frame()
+            .lazy()
+            .group_by_stable([col("group")])
+            .agg([sketch.alias("sketch")])
+            .select([estimate(col("sketch"))])
+            .collect()
+            .unwrap();
+        result
+            .column("sketch")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .map(f64::round)
+            .collect()
+    }
+
+    #[test]
+    fn group_by() {
+        assert_eq!(
+            estimates_by_group(col("user_id").hll_sketch(12), Expr::hll_estimate),
+            [2.0, 2.0]
+        );
+        assert_eq!(
+            estimates_by_group(col("name").cpc_sketch(11), Expr::cpc_estimate),
+            [2.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn union() {
+        let sketches = frame()
+            .lazy()
+            .group_by_stable([col("group")])
+            .agg([
+                col("user_id").hll_sketch(10).alias("hll"),
+                col("user_id").cpc_sketch(10).alias("cpc"),
+            ])
+            .select([
+                col("hll").hll_union().hll_estimate(),
+                col("cpc").cpc_union().cpc_estimate(),
+            ])
+            .collect()
+            .unwrap();
+        assert_eq!(sketches.height(), 1);
+        for name in ["hll", "cpc"] {
+            let estimate = sketches.column(name).unwrap().f64().unwrap().get(0);
+            assert_eq!(estimate.map(f64::round), Some(3.0));
+        }
+    }
+
+    #[test]
+    fn whole_column() {
+        let images = frame()
+            .lazy()
+            .select([col("user_id").hll_sketch(12)])
+            .collect()
+            .unwrap();
+        let image = images.column("user_id").unwrap().binary().unwrap().get(0);
+        let hll = HllSketch::deserialize(image.unwrap());
+        assert_eq!(hll.get_lg_config_k(), 12);
+        assert_eq!(hll.get_estimate().round(), 3.0);
+    }
+
+    #[test]
+    fn invalid() {
+        let lg_k = frame()
+            .lazy()
+            .select([col("user_id").cpc_sketch(30)])
+            .collect();
+        assert!(lg_k.is_err());
+
+        let corrupt = df!["sketch" => [&[2u8, 1, 16, 11, 0, 0, 0, 0][..]]].unwrap();
+        for expr in [
+            col("sketch").cpc_estimate(),
+            col("sketch").cpc_union(),
+            col("sketch").hll_union(),
+        ] {
+            assert!(corrupt.clone().lazy().select([expr]).collect().is_err());
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversions between sketches and the values of the `hll_sketch` and
+//! `cpc_sketch` types of the
+//! [datasketches-postgresql](https://github.com/apache/datasketches-postgresql)
+//! extension.
+//!
+//! The extension stores the serialized image of a sketch as the payload of a
+//! varlena, and HLL sketches in their compact form. Its types only have text
+//! input and output functions, no binary ones, so their values cannot be
+//! transferred in binary form as such: sketches are exchanged as `bytea`
+//! values, which the types can be cast to and from. Queries must thus cast
+//! the sketch columns they read, as in `SELECT sketch::bytea FROM t`, and the
+//! parameters they write, as in `INSERT INTO t VALUES ($1::bytea::hll_sketch)`.
+
+use std::error::Error;
+
+use bytes::{BufMut, BytesMut};
+use postgres_types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+use crate::config::Family;
+use crate::cpc::sketch::CpcSketch;
+use crate::hash::DEFAULT_SEED;
+use crate::header::inspect_family;
+use crate::hll::HllSketch;
+use crate::traits::CardinalitySketch;
+
+impl ToSql for HllSketch {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_slice(&self.serialize_compact(0));
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::BYTEA
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for HllSketch {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(<HllSketch as CardinalitySketch>::deserialize(raw)?)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::BYTEA
+    }
+}
+
+impl ToSql for CpcSketch {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_slice(&self.serialize());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::BYTEA
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for CpcSketch {
+    /// The extension always uses the default seed.
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        inspect_family(raw, Family::Cpc)?;
+        Ok(CpcSketch::deserialize_with_seed(raw, DEFAULT_SEED)?)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::BYTEA
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use postgres_types::Kind;
+
+    /// Compact image of an HLL sketch over the `i64` values 1, 2 and 3 with
+    /// the default `lg_k` of 12 and HLL4 target type: a list of 3 coupons.
+    /// Like the next one, it was serialized by the C++ library the extension
+    /// is built on, not read from a database.
+    const HLL_FIXTURE: [u8; 20] = [
+        0x02, 0x01, 0x07, 0x0c, 0x03, 0x08, 0x03, 0x00, 0x2b, 0xf2, 0xfb, 0x06, 0x86, 0x2f, 0xf9,
+        0x0d, 0x75, 0x81, 0x66, 0x07,
+    ];
+
+    /// Image of an empty CPC sketch with the `lg_k` of 11 used by default by
+    /// the extension.
+    const CPC_FIXTURE: [u8; 8] = [0x02, 0x01, 0x10, 0x0b, 0x00, 0x06, 0xcc, 0x93];
+
+    fn extension_type(name: &str) -> Type {
+        Type::new(name.to_string(), 0, Kind::Simple, "public".to_string())
+    }
+
+    fn to_bytes<T: ToSql>(value: &T, ty: &Type) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        assert!(matches!(value.to_sql_checked(ty, &mut out), Ok(IsNull::No)));
+        out.to_vec()
+    }
+
+    #[test]
+    fn hll_values() {
+        let ty = Type::BYTEA;
+        let hll = HllSketch::from_sql(&ty, &HLL_FIXTURE).unwrap();
+        assert_eq!(hll.get_lg_config_k(), 12);
+        assert_eq!(hll.get_estimate().round(), 3.0);
+
+        let mut expected = HllSketch::new(12, crate::hll::HllType::HLL4, false);
+        expected.update_i64(1);
+        expected.update_i64(2);
+        expected.update_i64(3);
+        assert!(hll == expected);
+        assert_eq!(to_bytes(&expected, &ty), HLL_FIXTURE);
+    }
+
+    #[test]
+    fn cpc_values() {
+        let ty = Type::BYTEA;
+        let cpc = CpcSketch::from_sql(&ty, &CPC_FIXTURE).unwrap();
+        assert!(cpc.is_empty());
+        assert_eq!(
+            to_bytes(&CpcSketch::new(11, DEFAULT_SEED), &ty),
+            CPC_FIXTURE
+        );
+
+        let mut cpc = CpcSketch::default();
+        cpc.update_u64_slice(&(0..10_000).collect::<Vec<_>>());
+        let roundtrip = CpcSketch::from_sql(&ty, &to_bytes(&cpc, &ty)).unwrap();
+        assert!(cpc == roundtrip);
+    }
+
+    #[test]
+    fn rejected_values() {
+        // The extension types have no binary representation.
+        let (hll_ty, cpc_ty) = (extension_type("hll_sketch"), extension_type("cpc_sketch"));
+        assert!(!<HllSketch as FromSql>::accepts(&hll_ty));
+        assert!(!<HllSketch as ToSql>::accepts(&hll_ty));
+        assert!(!<CpcSketch as FromSql>::accepts(&cpc_ty));
+        assert!(!<CpcSketch as ToSql>::accepts(&Type::TEXT));
+
+        let ty = Type::BYTEA;
+        assert!(HllSketch::from_sql(&ty, &HLL_FIXTURE[..4]).is_err());
+        assert!(HllSketch::from_sql(&ty, &CPC_FIXTURE).is_err());
+        assert!(CpcSketch::from_sql(&ty, &HLL_FIXTURE).is_err());
+
+        let mut hll = HllSketch::default();
+        hll.update_u64_slice(&(0..10_000).collect::<Vec<_>>());
+        let image = hll.serialize_compact(0);
+        assert!(HllSketch::from_sql(&ty, &image[..image.len() / 2]).is_err());
+
+        let other_seed = CpcSketch::new(11, 42).serialize();
+        assert!(CpcSketch::from_sql(&ty, &other_seed).is_err());
+    }
+}
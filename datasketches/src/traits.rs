@@ -0,0 +1,164 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The traits shared by the sketches and unions of every family, so that
+//! code can be generic over the counting algorithm:
+//!
+//! ```no_run
+//! use datasketches::{CardinalitySketch, SketchUnion};
+//!
+//! fn merge<U: SketchUnion>(mut union: U, images: &[&[u8]]) -> f64 {
+//!     for image in images {
+//!         union.update_sketch(&U::Sketch::deserialize(image).unwrap());
+//!     }
+//!     union.get_result().get_estimate()
+//! }
+//! ```
+//!
+//! The sketches and unions keep their inherent methods, which take the
+//! parameters specific to each family.
+
+use crate::config::SketchConfig;
+use crate::error::Error;
+use crate::item::{ItemSink, SketchItem};
+
+/// A sketch counting distinct items.
+pub trait CardinalitySketch: ItemSink + Clone {
+    /// Returns the configuration of the sketch.
+    fn config(&self) -> SketchConfig;
+
+    /// Returns the current cardinality estimate.
+    fn get_estimate(&self) -> f64;
+
+    /// Returns the approximate lower error bound given the number of standard
+    /// deviations (1, 2 or 3).
+    fn get_lower_bound(&self, num_std_dev: u8) -> f64;
+
+    /// Returns the approximate upper error bound given the number of standard
+    /// deviations (1, 2 or 3).
+    fn get_upper_bound(&self, num_std_dev: u8) -> f64;
+
+    /// Indicates if the sketch is currently empty.
+    fn is_empty(&self) -> bool;
+
+    /// Serializes the sketch to a byte array.
+    fn serialize(&self) -> Vec<u8>;
+
+    /// Reconstructs a sketch from a serialized image in a byte array, after
+    /// checking its preamble with [inspect](crate::inspect).
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error>;
+
+    /// Present the given item as a potential unique item, see [SketchItem].
+    #[inline]
+    fn update<T: SketchItem + ?Sized>(&mut self, item: &T) {
+        item.update_into(self);
+    }
+}
+
+/// A union merging sketches of a family.
+pub trait SketchUnion: ItemSink {
+    /// The sketches merged by the union.
+    type Sketch: CardinalitySketch;
+
+    /// Update this union operator with the given sketch.
+    fn update_sketch(&mut self, sketch: &Self::Sketch);
+
+    /// Returns the result of this union operator as a sketch.
+    fn get_result(&self) -> Self::Sketch;
+
+    /// Returns the current cardinality estimate.
+    fn get_estimate(&self) -> f64;
+
+    /// Indicates if the union is currently empty.
+    fn is_empty(&self) -> bool;
+}
+
+/// A sketch which can create a union merging it with the sketches
+/// configured like it.
+pub trait Mergeable: CardinalitySketch {
+    /// The union merging the sketches.
+    type Union: SketchUnion<Sketch = Self>;
+
+    /// Creates an empty union for the sketches configured like this one.
+    fn new_union(&self) -> Self::Union;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Family;
+    #[cfg(feature = "cpp")]
+    use crate::cpc::{sketch::CpcSketch, union::CpcUnion};
+    use crate::hll::{HllSketch, HllUnion};
+
+    fn distinct_count<U: SketchUnion>(mut union: U, mut sketch: U::Sketch) -> (f64, Family) {
+        for i in 0..1000u64 {
+            sketch.update(&i);
+        }
+        let image = sketch.serialize();
+        let sketch = U::Sketch::deserialize(&image).unwrap();
+        assert!(sketch.get_lower_bound(2) <= sketch.get_estimate());
+        assert!(sketch.get_upper_bound(2) >= sketch.get_estimate());
+
+        assert!(union.is_empty());
+        union.update_sketch(&sketch);
+        union.update_u64(1000);
+        let result = union.get_result();
+        assert_eq!(result.get_estimate(), union.get_estimate());
+        (result.get_estimate(), result.config().family)
+    }
+
+    #[test]
+    fn generic() {
+        let (estimate, family) = distinct_count(HllUnion::new(12), HllSketch::default());
+        assert_eq!(family, Family::Hll);
+        assert!((estimate - 1001.0).abs() < 1001.0 * 0.05);
+
+        #[cfg(feature = "cpp")]
+        {
+            let (estimate, family) = distinct_count(CpcUnion::default(), CpcSketch::default());
+            assert_eq!(family, Family::Cpc);
+            assert!((estimate - 1001.0).abs() < 1001.0 * 0.05);
+        }
+    }
+
+    fn truncated<S: CardinalitySketch>(sketch: &mut S) -> Result<S, Error> {
+        for i in 0..10_000u64 {
+            sketch.update(&i);
+        }
+        let image = sketch.serialize();
+        S::deserialize(&image[..image.len() / 2])
+    }
+
+    #[test]
+    fn corrupted() {
+        // the preambles are intact, only the bodies are cut short
+        assert!(truncated(&mut HllSketch::default()).is_err());
+        #[cfg(feature = "cpp")]
+        assert!(truncated(&mut CpcSketch::default()).is_err());
+    }
+
+    #[test]
+    fn wrong_family() {
+        // the image of an empty CPC sketch
+        let image = [0x02, 0x01, 0x10, 0x0b, 0x00, 0x06, 0xcc, 0x93];
+        assert_eq!(
+            <HllSketch as CardinalitySketch>::deserialize(&image),
+            Err(Error::WrongFamily {
+                expected: Family::Hll,
+                found: Family::Cpc
+            })
+        );
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Refer to [SlidingWindow].
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::item::{ItemSink, SketchItem};
+use crate::traits::{Mergeable, SketchUnion};
+
+/// A source of time for a [SlidingWindow].
+pub trait Clock {
+    /// Returns the time elapsed since the origin of the clock, which must
+    /// not go backwards.
+    fn now(&self) -> Duration;
+}
+
+/// The monotonic system clock, counting from the first time it is read in
+/// the process, so it is not affected by changes of the wall clock. The time
+/// slices of a window are thus aligned on the start of the process rather
+/// than on wall-clock boundaries, see [WallClock].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// A monotonic clock counting from the Unix epoch, so that the time slices of
+/// a window are aligned on wall-clock boundaries, e.g. on the minute.
+///
+/// The wall clock is only read when the clock is created: the time then
+/// advances with the monotonic system clock, so changes of the wall clock
+/// afterwards are ignored and the time never goes backwards.
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    epoch: Duration,
+    origin: Instant,
+}
+
+impl WallClock {
+    /// Creates a clock at the current wall-clock time.
+    pub fn new() -> Self {
+        Self {
+            epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for WallClock {
+    fn now(&self) -> Duration {
+        self.epoch + self.origin.elapsed()
+    }
+}
+
+/// A clock moved by hand, for deterministic tests. Clones share the same
+/// time, so a clone kept outside of a window drives the window.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    /// Creates a clock at the given time.
+    pub fn new(now: Duration) -> Self {
+        let clock = Self::default();
+        clock.set(now);
+        clock
+    }
+
+    /// Sets the time of the clock.
+    pub fn set(&self, now: Duration) {
+        self.0.store(now.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.0
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Counts distinct items over a sliding window of time, e.g. the distinct
+/// users of the last 15 minutes, refreshed every minute.
+///
+/// The window is a ring of sketches, one per time slice: items are presented
+/// to the sketch of the current slice, and the sketches of the slices which
+/// left the window are dropped. Estimates over a duration merge the sketches
+/// of the slices it covers, which are the current one and the previous ones:
+/// as the current slice is not over, an estimate over `d` covers at least
+/// `d - slice` and at most `d` of the most recent items.
+///
+/// Every slice is a clone of the empty sketch the window is created with,
+/// so [HllSketch](crate::hll::HllSketch) and
+/// [CpcSketch](crate::cpc::sketch::CpcSketch) windows of any configuration can
+/// be built.
+pub struct SlidingWindow<S, C = SystemClock> {
+    slice: Duration,
+    slices: u32,
+    empty: S,
+    /// The sketches of the slices holding items, oldest first, along with
+    /// the index of their slice since the origin of the clock.
+    buckets: VecDeque<(u64, S)>,
+    clock: C,
+}
+
+impl<S: Mergeable> SlidingWindow<S> {
+    /// Creates a window of `slices` slices of the given duration, timed by
+    /// the [SystemClock], whose slices are clones of `empty`. Use
+    /// [SlidingWindow::with_clock] and a [WallClock] for slices aligned on
+    /// wall-clock boundaries.
+    ///
+    /// Panics if `slice` is zero, if `slices` is zero, or if `empty` is not
+    /// empty.
+    pub fn new(slice: Duration, slices: u32, empty: S) -> Self {
+        Self::with_clock(slice, slices, empty, SystemClock)
+    }
+}
+
+impl<S: Mergeable, C: Clock> SlidingWindow<S, C> {
+    /// Creates a window of `slices` slices of the given duration, timed by
+    /// the given clock, whose slices are clones of `empty`.
+    ///
+    /// Panics if `slice` is zero, if `slices` is zero, or if `empty` is not
+    /// empty.
+    pub fn with_clock(slice: Duration, slices: u32, empty: S, clock: C) -> Self {
+        assert!(!slice.is_zero(), "slice must not be zero");
+        assert!(slices > 0, "slices must not be zero");
+        assert!(empty.is_empty(), "the sketch of the slices must be empty");
+        Self {
+            slice,
+            slices,
+            empty,
+            buckets: VecDeque::with_capacity(slices as usize),
+            clock,
+        }
+    }
+
+    /// Returns the duration of a slice.
+    pub fn slice(&self) -> Duration {
+        self.slice
+    }
+
+    /// Returns the duration of the window, covered by its slices.
+    pub fn duration(&self) -> Duration {
+        self.slice * self.slices
+    }
+
+    /// Returns the number of slices of the window holding items.
+    pub fn num_buckets(&self) -> usize {
+        let oldest = self.oldest(self.slices);
+        self.buckets
+            .iter()
+            .filter(|(index, _)| *index >= oldest)
+            .count()
+    }
+
+    /// Returns the index of the current slice.
+    fn current(&self) -> u64 {
+        (self.clock.now().as_nanos() / self.slice.as_nanos()) as u64
+    }
+
+    /// Returns the index of the oldest slice among the last `slices` ones.
+    fn oldest(&self, slices: u32) -> u64 {
+        (self.current() + 1).saturating_sub(slices as u64)
+    }
+
+    /// Drops the sketches of the slices which left the window.
+    pub fn expire(&mut self) {
+        let oldest = self.oldest(self.slices);
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(index, _)| *index < oldest)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Returns the sketch of the current slice, dropping the expired ones.
+    /// Should the clock go backwards, the sketch of the latest slice is
+    /// returned, so that the slices stay in order.
+    fn bucket(&mut self) -> &mut S {
+        self.expire();
+        let current = self.current();
+        // None, when there is no bucket yet, is less than any index.
+        if self.buckets.back().map(|(index, _)| *index) < Some(current) {
+            self.buckets.push_back((current, self.empty.clone()));
+        }
+        &mut self.buckets.back_mut().unwrap().1
+    }
+
+    /// Present the given item to the current slice, see [SketchItem].
+    #[inline]
+    pub fn update<T: SketchItem + ?Sized>(&mut self, item: &T) {
+        item.update_into(self.bucket());
+    }
+
+    /// Returns the union of the slices covering the given duration, capped
+    /// to the duration of the window.
+    pub fn union_over(&self, duration: Duration) -> S::Union {
+        let slices = duration.as_nanos().div_ceil(self.slice.as_nanos());
+        let oldest = self.oldest(slices.min(self.slices as u128) as u32);
+        let mut union = self.empty.new_union();
+        for (_, sketch) in self.buckets.iter().filter(|(index, _)| *index >= oldest) {
+            union.update_sketch(sketch);
+        }
+        union
+    }
+
+    /// Returns a sketch of the items presented during the given duration,
+    /// capped to the duration of the window.
+    pub fn sketch_over(&self, duration: Duration) -> S {
+        self.union_over(duration).get_result()
+    }
+
+    /// Returns the estimate of the number of distinct items presented during
+    /// the given duration, capped to the duration of the window.
+    pub fn estimate_over(&self, duration: Duration) -> f64 {
+        self.union_over(duration).get_estimate()
+    }
+
+    /// Returns the estimate of the number of distinct items presented during
+    /// the whole window.
+    pub fn get_estimate(&self) -> f64 {
+        self.estimate_over(self.duration())
+    }
+}
+
+impl<S: Mergeable, C: Clock> ItemSink for SlidingWindow<S, C> {
+    #[inline]
+    fn update_u64(&mut self, datum: u64) {
+        self.bucket().update_u64(datum)
+    }
+
+    #[inline]
+    fn update_i64(&mut self, datum: i64) {
+        self.bucket().update_i64(datum)
+    }
+
+    #[inline]
+    fn update_f64(&mut self, datum: f64) {
+        self.bucket().update_f64(datum)
+    }
+
+    #[inline]
+    fn update_bytes(&mut self, datum: &[u8]) {
+        self.bucket().update_bytes(datum)
+    }
+
+    #[inline]
+    fn update_u64_slice(&mut self, data: &[u64]) {
+        self.bucket().update_u64_slice(data)
+    }
+
+    #[inline]
+    fn update_i64_slice(&mut self, data: &[i64]) {
+        self.bucket().update_i64_slice(data)
+    }
+
+    #[inline]
+    fn update_f64_slice(&mut self, data: &[f64]) {
+        self.bucket().update_f64_slice(data)
+    }
+
+    #[inline]
+    fn update_packed_bytes(&mut self, data: &[u8], offsets: &[usize]) {
+        self.bucket().update_packed_bytes(data, offsets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "cpp")]
+    use crate::cpc::sketch::CpcSketch;
+    use crate::hll::HllSketch;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn window<S: Mergeable>(empty: S) -> (SlidingWindow<S, ManualClock>, ManualClock) {
+        let clock = ManualClock::new(MINUTE * 1000);
+        let window = SlidingWindow::with_clock(MINUTE, 15, empty, clock.clone());
+        (window, clock)
+    }
+
+    fn sliding<S: Mergeable>(empty: S) {
+        let (mut window, clock) = window(empty);
+        assert_eq!(window.duration(), MINUTE * 15);
+        assert_eq!(window.get_estimate(), 0.0);
+
+        // 100 new users per minute, each of them seen for 5 minutes.
+        for minute in 0..30u64 {
+            for user in minute * 100..(minute + 5) * 100 {
+                window.update(&user);
+            }
+            clock.advance(MINUTE);
+        }
+        clock.set(MINUTE * 1029 + Duration::from_secs(1));
+
+        assert_eq!(window.num_buckets(), 15);
+        // Minutes 15 to 29 saw users 1500 to 3399.
+        let estimate = window.get_estimate();
+        assert!((estimate - 1900.0).abs() < 1900.0 * 0.05, "{estimate}");
+        assert_eq!(window.estimate_over(MINUTE * 60), estimate);
+        // Minute 29 saw users 2900 to 3399.
+        let estimate = window.estimate_over(Duration::from_secs(1));
+        assert!((estimate - 500.0).abs() < 500.0 * 0.05, "{estimate}");
+        let estimate = window.sketch_over(MINUTE * 2).get_estimate();
+        assert!((estimate - 600.0).abs() < 600.0 * 0.05, "{estimate}");
+
+        clock.advance(MINUTE * 14);
+        assert_eq!(window.num_buckets(), 1);
+        window.expire();
+        assert_eq!(window.buckets.len(), 1);
+        clock.advance(MINUTE);
+        assert_eq!(window.get_estimate(), 0.0);
+    }
+
+    #[test]
+    fn hll() {
+        sliding(HllSketch::default());
+    }
+
+    #[test]
+    #[cfg(feature = "cpp")]
+    fn cpc() {
+        sliding(CpcSketch::default());
+    }
+
+    #[test]
+    fn clocks() {
+        let (mut window, clock) = window(HllSketch::default());
+        window.update(&1u64);
+        clock.set(MINUTE * 990);
+        window.update(&2u64);
+        assert_eq!(window.buckets.len(), 1);
+        clock.set(MINUTE * 1000);
+        assert_eq!(window.get_estimate().round(), 2.0);
+
+        let system = SystemClock;
+        let now = system.now();
+        assert!(SystemClock.now() >= now);
+
+        let wall = WallClock::new();
+        let now = wall.now();
+        assert!(now > Duration::from_secs(1_000_000_000));
+        assert!(wall.now() >= now);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be empty")]
+    fn non_empty() {
+        let mut sketch = HllSketch::default();
+        sketch.update_u64(1);
+        window(sketch);
+    }
+}
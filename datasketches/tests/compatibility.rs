@@ -0,0 +1,189 @@
+// Copyright 2024 Filippo Rossi
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks the sketches serialized by the other DataSketches implementations,
+//! see `tests/fixtures/README.md`.
+
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "cpp")]
+use datasketches::cpc::sketch::CpcSketch;
+use datasketches::hll::{HllHeader, HllMode, HllSketch, HllType};
+use datasketches::{inspect, SketchHeader};
+
+/// A fixture file, named `<sketch>_n<n>_<language>.sk`.
+struct Fixture {
+    path: PathBuf,
+    sketch: String,
+    n: u64,
+}
+
+impl Fixture {
+    fn parse(path: &Path) -> Option<Self> {
+        let stem = path.file_name()?.to_str()?.strip_suffix(".sk")?;
+        let mut parts = stem.split('_');
+        let sketch = parts.next()?.to_owned();
+        let n = parts.next()?.strip_prefix('n')?.parse().ok()?;
+        parts.next()?;
+        Some(Self {
+            path: path.to_owned(),
+            sketch,
+            n,
+        })
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        std::fs::read(&self.path).unwrap()
+    }
+
+    fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Checks the estimate against the number of distinct items, within the
+    /// bounds of 3 standard deviations.
+    fn check_estimate(&self, estimate: f64, lower_bound: f64, upper_bound: f64) {
+        let n = self.n as f64;
+        if self.n == 0 {
+            assert_eq!(estimate, 0.0, "{}", self.name());
+        }
+        assert!(
+            lower_bound <= n && n <= upper_bound,
+            "{}: estimate {estimate} in [{lower_bound}, {upper_bound}]",
+            self.name()
+        );
+    }
+}
+
+/// Returns the fixtures of the given sketches, failing if there are none so
+/// that a missing directory does not let the tests pass vacuously.
+fn fixtures(sketches: &[&str]) -> Vec<Fixture> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let entries = std::fs::read_dir(&dir).unwrap_or_else(|err| panic!("{}: {err}", dir.display()));
+    let mut fixtures: Vec<_> = entries
+        .filter_map(|entry| Fixture::parse(&entry.ok()?.path()))
+        .filter(|fixture| sketches.contains(&fixture.sketch.as_str()))
+        .collect();
+    fixtures.sort_by_key(|fixture| fixture.path.clone());
+    assert!(
+        !fixtures.is_empty(),
+        "no {sketches:?} fixtures in {}, see its README.md",
+        dir.display()
+    );
+    fixtures
+}
+
+/// Returns an HLL image with the entries of its hash table sorted and the
+/// empty ones dropped: the coupons of a set, or the auxiliary map of an HLL4
+/// array. Where the entries land depends on the order in which the table was
+/// filled, which deserialization does not preserve.
+fn canonical(image: &[u8], header: &HllHeader) -> Vec<u8> {
+    // sets store their coupons after 3 preamble ints, HLL4 arrays their
+    // auxiliary map after 10 preamble ints and a nibble per register
+    let start = match (header.mode, header.target_type) {
+        (HllMode::Set, _) => 12,
+        (HllMode::Hll, HllType::HLL4) => 40 + (1 << header.lg_config_k) / 2,
+        _ => return image.to_vec(),
+    };
+    let mut entries: Vec<u32> = image[start..]
+        .chunks_exact(4)
+        .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+        .filter(|entry| *entry != 0)
+        .collect();
+    entries.sort_unstable();
+    let mut canonical = image[..start].to_vec();
+    canonical.extend(entries.iter().flat_map(|entry| entry.to_le_bytes()));
+    canonical
+}
+
+#[test]
+fn hll() {
+    for fixture in fixtures(&["hll4", "hll6", "hll8"]) {
+        let name = fixture.name();
+        let bytes = fixture.bytes();
+        let Ok(SketchHeader::Hll(header)) = inspect(&bytes) else {
+            panic!("{name}: not an HLL image");
+        };
+        let tgt_type = match fixture.sketch.as_str() {
+            "hll4" => HllType::HLL4,
+            "hll6" => HllType::HLL6,
+            _ => HllType::HLL8,
+        };
+        assert_eq!(header.target_type, tgt_type, "{name}");
+
+        let sketch = HllSketch::deserialize(&bytes);
+        assert_eq!(sketch.is_empty(), fixture.n == 0, "{name}");
+        fixture.check_estimate(
+            sketch.get_estimate(),
+            sketch.get_lower_bound(3),
+            sketch.get_upper_bound(3),
+        );
+
+        // Every implementation hashes the integers the same way, so the
+        // registers match the ones of a sketch of the same items.
+        let mut expected = HllSketch::new(header.lg_config_k, tgt_type, false);
+        expected.update_u64_slice(&(0..fixture.n).collect::<Vec<_>>());
+        assert_eq!(sketch.registers(), expected.registers(), "{name}");
+
+        let image = if header.is_compact {
+            sketch.serialize_compact(0)
+        } else {
+            sketch.serialize_updatable()
+        };
+        assert_eq!(
+            canonical(&image, &header),
+            canonical(&bytes, &header),
+            "{name}"
+        );
+        assert_eq!(HllSketch::deserialize(&image), sketch, "{name}");
+    }
+}
+
+#[test]
+#[cfg(feature = "cpp")]
+fn cpc() {
+    for fixture in fixtures(&["cpc"]) {
+        let name = fixture.name();
+        let bytes = fixture.bytes();
+        let Ok(SketchHeader::Cpc(header)) = inspect(&bytes) else {
+            panic!("{name}: not a CPC image");
+        };
+
+        let sketch = CpcSketch::deserialize(&bytes);
+        assert_eq!(sketch.is_empty(), fixture.n == 0, "{name}");
+        assert_eq!(sketch.get_num_coupons(), header.num_coupons, "{name}");
+        fixture.check_estimate(
+            sketch.get_estimate(),
+            sketch.get_lower_bound(3),
+            sketch.get_upper_bound(3),
+        );
+
+        let mut expected = CpcSketch::new(header.lg_k, datasketches::hash::DEFAULT_SEED);
+        expected.update_u64_slice(&(0..fixture.n).collect::<Vec<_>>());
+        assert_eq!(sketch, expected, "{name}");
+        assert_eq!(sketch.serialize(), bytes, "{name}");
+    }
+}
+
+#[test]
+fn fixture_names() {
+    let fixture = Fixture::parse(Path::new("cpc_n20000_java.sk")).unwrap();
+    assert_eq!((fixture.sketch.as_str(), fixture.n), ("cpc", 20000));
+    assert!(Fixture::parse(Path::new("README.md")).is_none());
+    assert!(Fixture::parse(Path::new("hll4_1000_java.sk")).is_none());
+}